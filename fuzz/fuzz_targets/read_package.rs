@@ -0,0 +1,58 @@
+/* read_package.rs - fuzz the RPM reader with arbitrary packages
+ *
+ * Copyright (c) 2017, Red Hat, Inc.
+ *
+ * This library is free software; you can redistribute it and/or modify it
+ * under the terms and conditions of the GNU Lesser General Public License
+ * as published by the Free Software Foundation; either version 2.1 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU Lesser General Public License for
+ * more details.
+ *
+ * Authors:
+ *   Will Woods <wwoods@redhat.com>
+ */
+
+// Run with `cargo fuzz run read_package`; the corpus starts out with the
+// test fixture. Errors are fine, panics (and huge allocations) aren't.
+#![no_main]
+
+use std::io::Read;
+
+use libfuzzer_sys::fuzz_target;
+use rpm::{Package, Reader};
+
+fuzz_target!(|data: &[u8]| {
+    let mut r = Reader::from_bytes(data);
+    if r.lead().is_err() {
+        return;
+    }
+    let sig = match r.signature() {
+        Ok(sig) => sig,
+        Err(_) => return,
+    };
+    let _ = sig.to_bytes();
+    let (hdr, _) = match r.raw_header() {
+        Ok(hdr) => hdr,
+        Err(_) => return,
+    };
+    let _ = hdr.region();
+    let _ = hdr.dribbles();
+    let _ = rpm::header_to_bytes(&hdr);
+    if let Ok(mut archive) = r.archive(&hdr) {
+        while let Ok(Some(mut entry)) = archive.next_entry() {
+            let _ = entry.read_to_end(&mut Vec::new());
+        }
+    }
+    let pkg = Package::new(hdr);
+    let _ = pkg.nevra();
+    let _ = pkg.files();
+    let _ = pkg.requires();
+    let _ = pkg.provides();
+    let _ = pkg.conflicts();
+    let _ = pkg.obsoletes();
+    let _ = rpm::verify_package(data);
+});
@@ -0,0 +1,514 @@
+/* archive.rs - reading the cpio archive inside an RPM payload
+ *
+ * Copyright (c) 2017, Red Hat, Inc.
+ *
+ * This library is free software; you can redistribute it and/or modify it
+ * under the terms and conditions of the GNU Lesser General Public License
+ * as published by the Free Software Foundation; either version 2.1 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU Lesser General Public License for
+ * more details.
+ *
+ * Authors:
+ *   Will Woods <wwoods@redhat.com>
+ */
+
+use std::io::prelude::*;
+use std::io;
+use std::cmp;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::str::from_utf8;
+use nom::IResult;
+
+use header::Header;
+use files::{FileEntry, lookup_uid, lookup_gid, S_IFMT, S_IFDIR};
+use reader::read_full;
+use error::{RPMError, RPMFileError, ParseError, Section};
+use Result;
+
+// The name of the last entry in every cpio archive
+const TRAILER: &str = "TRAILER!!!";
+
+// Longer names than this (PATH_MAX, counting the NUL) are bogus
+const MAX_NAME_SIZE: u32 = 4096;
+
+// The "newc" cpio header. Everything's an 8-digit hex number.
+#[derive(Debug,PartialEq,Eq)]
+struct NewcHeader {
+    ino: u32,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    nlink: u32,
+    mtime: u32,
+    filesize: u32,
+    devmajor: u32,
+    devminor: u32,
+    rdevmajor: u32,
+    rdevminor: u32,
+    namesize: u32,
+    check: u32,
+}
+
+fn from_hex(i: &[u8]) -> Option<u32> {
+    from_utf8(i).ok().and_then(|s| u32::from_str_radix(s, 16).ok())
+}
+
+named!(hex32<u32>, map_opt!(take!(8), from_hex));
+
+named!(parse_newc_header<NewcHeader>,
+    do_parse!(
+        tag!("070701")   >>
+        ino:       hex32 >>
+        mode:      hex32 >>
+        uid:       hex32 >>
+        gid:       hex32 >>
+        nlink:     hex32 >>
+        mtime:     hex32 >>
+        filesize:  hex32 >>
+        devmajor:  hex32 >>
+        devminor:  hex32 >>
+        rdevmajor: hex32 >>
+        rdevminor: hex32 >>
+        namesize:  hex32 >>
+        check:     hex32 >>
+        (NewcHeader {
+            ino, mode, uid, gid, nlink,
+            mtime, filesize,
+            devmajor, devminor,
+            rdevmajor, rdevminor,
+            namesize, check,
+        })
+    )
+);
+
+// The "stripped" header (rpm >= 4.12) just holds an index into the header's
+// file arrays; everything else about the file comes from the header.
+named!(parse_stripped_header<u32>,
+    do_parse!(
+        tag!("07070X") >>
+        idx:     hex32 >>
+        (idx)
+    )
+);
+
+const MAGIC_SIZE: usize = 6;
+const NEWC_HEADER_SIZE: usize = 110;
+const STRIPPED_HEADER_SIZE: usize = 14;
+
+/// A reader for the cpio archive in a (decompressed) RPM payload.
+///
+/// Both the classic "newc" format and the "stripped" format used by rpm
+/// for packages with LONGFILESIZES are supported; the main header is
+/// needed to map archive entries back to the files it lists.
+pub struct Archive<R: io::Read> {
+    rdr: R,
+    files: Vec<FileEntry>,
+    by_path: HashMap<String, usize>,
+    seen: Vec<bool>,
+    pos: u64,
+    remaining: u64,
+    done: bool,
+}
+
+/// A single entry in an RPM payload archive.
+/// It implements `io::Read`, which reads the entry's contents.
+pub struct ArchiveEntry<'a, R: io::Read + 'a> {
+    /// The file's path, as listed in the header
+    pub path: String,
+    /// The file's mode (type and permission bits)
+    pub mode: u32,
+    /// Size of the file's data in the archive
+    pub size: u64,
+    /// Index of this file in the header's file arrays
+    pub index: usize,
+    archive: &'a mut Archive<R>,
+}
+
+impl<R: io::Read> Archive<R> {
+    /// Creates an Archive that reads from the given decompressed payload,
+    /// using `hdr` (the package's main header) to identify the files.
+    pub fn new(rdr: R, hdr: &Header) -> Result<Archive<R>> {
+        let files = FileEntry::from_header(hdr)?;
+        let by_path = files.iter().enumerate().map(|(i, f)| (f.path.clone(), i)).collect();
+        let count = files.len();
+        Ok(Archive {
+            rdr,
+            files,
+            by_path,
+            seen: vec![false; count],
+            pos: 0,
+            remaining: 0,
+            done: false,
+        })
+    }
+
+    // an error at the given position in the archive
+    fn bad(&self, kind: RPMFileError, offset: u64) -> RPMError {
+        RPMError::Parse(ParseError::new(kind, Section::Payload, offset))
+    }
+
+    // read exactly buf.len() bytes, keeping track of our position
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<()> {
+        let start = self.pos;
+        let n = read_full(&mut self.rdr, buf)?;
+        self.pos += n as u64;
+        if n < buf.len() {
+            return Err(RPMError::Parse(ParseError::new(RPMFileError::Truncated, Section::Payload, start)
+                                       .sizes(buf.len() as u64, n as u64)));
+        }
+        Ok(())
+    }
+
+    // skip over the given number of bytes
+    fn skip(&mut self, count: u64) -> Result<()> {
+        let start = self.pos;
+        let skipped = io::copy(&mut (&mut self.rdr).take(count), &mut io::sink())?;
+        self.pos += skipped;
+        if skipped < count {
+            return Err(RPMError::Parse(ParseError::new(RPMFileError::Truncated, Section::Payload, start)
+                                       .sizes(count, skipped)));
+        }
+        Ok(())
+    }
+
+    // skip ahead to the next 4-byte boundary
+    fn align(&mut self) -> Result<()> {
+        let pad = (4 - self.pos % 4) % 4;
+        self.skip(pad)
+    }
+
+    /// Get the next entry in the archive, or `None` after the last one.
+    /// Any unread data from the previous entry is skipped.
+    pub fn next_entry(&mut self) -> Result<Option<ArchiveEntry<'_, R>>> {
+        if self.done {
+            return Ok(None);
+        }
+        let remaining = self.remaining;
+        self.skip(remaining)?;
+        self.remaining = 0;
+        self.align()?;
+
+        let start = self.pos;
+        let mut buf = vec![0; NEWC_HEADER_SIZE];
+        self.read_exact(&mut buf[..MAGIC_SIZE])?;
+        let (index, mode, size) = match &buf[..MAGIC_SIZE] {
+            b"070701" => {
+                self.read_exact(&mut buf[MAGIC_SIZE..])?;
+                let hdr = match parse_newc_header(&buf) {
+                    IResult::Done(_, hdr) => hdr,
+                    _ => return Err(self.bad(RPMFileError::BadHeader, start)),
+                };
+                if hdr.namesize > MAX_NAME_SIZE {
+                    let err = ParseError::new(RPMFileError::BadHeader, Section::Payload, start);
+                    return Err(RPMError::Parse(err.sizes(MAX_NAME_SIZE as u64, hdr.namesize as u64)));
+                }
+                let mut name = vec![0; hdr.namesize as usize];
+                self.read_exact(&mut name)?;
+                // the name is NUL-terminated, and starts with "./" in RPMs
+                let name = match name.split_last() {
+                    Some((&0, name)) => String::from_utf8_lossy(name).into_owned(),
+                    _ => return Err(self.bad(RPMFileError::BadHeader, start + NEWC_HEADER_SIZE as u64)),
+                };
+                // don't insist on padding after the trailer; nothing follows it
+                if name == TRAILER {
+                    return self.finish();
+                }
+                self.align()?;
+                let path = if name.starts_with("./") { &name[1..] } else { &name[..] };
+                let index = match self.by_path.get(path) {
+                    Some(&index) => index,
+                    None => return Err(self.bad(RPMFileError::UnmappedFile, start)),
+                };
+                // newc can't hold files of 4GB or more
+                if self.files[index].size > u32::MAX as u64 {
+                    return Err(self.bad(RPMFileError::FileSize, start));
+                }
+                (index, hdr.mode, hdr.filesize as u64)
+            },
+            b"07070X" => {
+                self.read_exact(&mut buf[MAGIC_SIZE..STRIPPED_HEADER_SIZE])?;
+                let index = match parse_stripped_header(&buf[..STRIPPED_HEADER_SIZE]) {
+                    IResult::Done(_, index) => index as usize,
+                    _ => return Err(self.bad(RPMFileError::BadHeader, start)),
+                };
+                self.align()?;
+                let file = match self.files.get(index) {
+                    Some(file) => file,
+                    None => return Err(self.bad(RPMFileError::UnmappedFile, start)),
+                };
+                (index, file.mode, if file.content { file.size } else { 0 })
+            },
+            _ => return Err(self.bad(RPMFileError::BadMagic, start)),
+        };
+        self.seen[index] = true;
+        self.remaining = size;
+        Ok(Some(ArchiveEntry {
+            path: self.files[index].path.clone(),
+            mode,
+            size,
+            index,
+            archive: self,
+        }))
+    }
+
+    // We hit the trailer; make sure everything in the header was there.
+    fn finish(&mut self) -> Result<Option<ArchiveEntry<'_, R>>> {
+        self.done = true;
+        let missing = self.files.iter().zip(self.seen.iter()).any(|(f, &seen)| !f.is_ghost() && !seen);
+        if missing {
+            return Err(RPMError::File(RPMFileError::MissingFile));
+        }
+        Ok(None)
+    }
+}
+
+impl<'a, R: io::Read> io::Read for ArchiveEntry<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let archive = &mut *self.archive;
+        let len = cmp::min(buf.len() as u64, archive.remaining) as usize;
+        let n = archive.rdr.read(&mut buf[..len])?;
+        // the payload ended in the middle of this entry's data
+        if n == 0 && len > 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "archive entry is truncated"));
+        }
+        archive.remaining -= n as u64;
+        archive.pos += n as u64;
+        Ok(n)
+    }
+}
+
+fn pad4(out: &mut Vec<u8>) {
+    let pad = (4 - out.len() % 4) % 4;
+    out.resize(out.len() + pad, 0);
+}
+
+// Append a "newc" header (up through the file size; the rest is zeros and
+// the name size), the name and the data, padding both to 4 bytes.
+fn write_newc(out: &mut Vec<u8>, name: &str, fields: [u32; 7], data: &[u8]) {
+    out.extend_from_slice(b"070701");
+    for field in fields.iter().chain(&[0, 0, 0, 0, name.len() as u32 + 1, 0]) {
+        out.extend_from_slice(format!("{:08x}", field).as_bytes());
+    }
+    out.extend_from_slice(name.as_bytes());
+    out.push(0);
+    pad4(out);
+    out.extend_from_slice(data);
+    pad4(out);
+}
+
+// Append a file to a "newc" archive that's being built, with the "./"
+// prefix rpm puts on paths. `data` is the file's contents (or a symlink's
+// target), or nothing for hardlinks that don't carry the contents.
+pub(crate) fn write_newc_entry(out: &mut Vec<u8>, file: &FileEntry, data: &[u8]) -> Result<()> {
+    let size = if file.content { file.size } else { 0 };
+    let size = u32::try_from(size).map_err(|_| RPMError::File(RPMFileError::FileSize))?;
+    if data.len() != size as usize {
+        return Err(RPMError::Internal);
+    }
+    let nlink = if file.mode & S_IFMT == S_IFDIR { 2 } else { 1 };
+    let (uid, gid) = (lookup_uid(&file.user), lookup_gid(&file.group));
+    let fields = [file.inode, file.mode, uid, gid, nlink, file.mtime, size];
+    write_newc(out, &format!(".{}", file.path), fields, data);
+    Ok(())
+}
+
+// Finish off a "newc" archive.
+pub(crate) fn write_newc_trailer(out: &mut Vec<u8>) {
+    write_newc(out, TRAILER, [0, 0, 0, 0, 1, 0, 0], &[]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reader::Reader;
+    static BINRPM1: &[u8] = include_bytes!("../tests/rpms/binary.x86_64.rpm");
+
+    fn payload() -> (Header, Vec<u8>) {
+        let mut r = Reader::from_bytes(BINRPM1);
+        let _ = r.lead();
+        let _ = r.header().unwrap();
+        let hdr = r.header().unwrap();
+        let mut payload = Vec::new();
+        r.decompressed_payload(&hdr).unwrap().read_to_end(&mut payload).unwrap();
+        (hdr, payload)
+    }
+
+    fn trailer() -> Vec<u8> {
+        let mut out = format!("070701{:088x}{:08x}{:08x}{}\0", 0, TRAILER.len()+1, 0, TRAILER).into_bytes();
+        pad4(&mut out);
+        out
+    }
+
+    // Build a stripped archive holding the same files as the fixture
+    fn stripped(hdr: &Header) -> Vec<u8> {
+        let mut out = Vec::new();
+        for (i, file) in FileEntry::from_header(hdr).unwrap().iter().enumerate() {
+            out.extend_from_slice(format!("07070X{:08x}\0\0", i).as_bytes());
+            if file.content {
+                out.extend(vec![b'x'; file.size as usize]);
+                pad4(&mut out);
+            }
+        }
+        out.extend(trailer());
+        out
+    }
+
+    #[test]
+    fn parse_newc_header_ok() {
+        let (_, payload) = payload();
+        let (_, hdr) = parse_newc_header(&payload[..NEWC_HEADER_SIZE]).unwrap();
+        assert_eq!(hdr.mode, 0o100755);
+        assert_eq!(hdr.filesize, 15720);
+        assert_eq!(hdr.namesize, b"./usr/sbin/hardlink\0".len() as u32);
+    }
+
+    #[test]
+    fn read_newc_archive() {
+        let (hdr, payload) = payload();
+        let mut archive = Archive::new(&payload[..], &hdr).unwrap();
+        let mut entries = Vec::new();
+        while let Some(mut entry) = archive.next_entry().unwrap() {
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data).unwrap();
+            assert_eq!(data.len() as u64, entry.size);
+            entries.push((entry.path.clone(), entry.mode, entry.size));
+        }
+        assert_eq!(entries, vec![
+            (String::from("/usr/sbin/hardlink"), 0o100755, 15720),
+            (String::from("/usr/share/licenses/hardlink"), 0o40755, 0),
+            (String::from("/usr/share/licenses/hardlink/gpl-2.0.txt"), 0o100644, 18092),
+            (String::from("/usr/share/man/man1/hardlink.1.gz"), 0o100644, 889),
+        ]);
+        assert!(archive.next_entry().unwrap().is_none());
+    }
+
+    #[test]
+    fn read_newc_archive_skipping_data() {
+        let (hdr, payload) = payload();
+        let mut archive = Archive::new(&payload[..], &hdr).unwrap();
+        let mut count = 0;
+        while archive.next_entry().unwrap().is_some() {
+            count += 1;
+        }
+        assert_eq!(count, 4);
+    }
+
+    #[test]
+    fn read_stripped_archive() {
+        let (hdr, _) = payload();
+        let payload = stripped(&hdr);
+        let mut archive = Archive::new(&payload[..], &hdr).unwrap();
+        let mut sizes = Vec::new();
+        while let Some(mut entry) = archive.next_entry().unwrap() {
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data).unwrap();
+            assert!(data.iter().all(|&b| b == b'x'));
+            sizes.push((entry.index, data.len()));
+        }
+        assert_eq!(sizes, vec![(0, 15720), (1, 0), (2, 18092), (3, 889)]);
+    }
+
+    #[test]
+    fn read_stripped_unmapped_file() {
+        let (hdr, _) = payload();
+        let payload = b"07070X0000000a\0\0";
+        let mut archive = Archive::new(&payload[..], &hdr).unwrap();
+        match archive.next_entry() {
+            Err(RPMError::Parse(ParseError { kind: RPMFileError::UnmappedFile, offset: 0, .. })) => (),
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("unexpected success"),
+        }
+    }
+
+    #[test]
+    fn read_archive_missing_file() {
+        let (hdr, _) = payload();
+        let payload = trailer();
+        let mut archive = Archive::new(&payload[..], &hdr).unwrap();
+        match archive.next_entry() {
+            Err(RPMError::File(RPMFileError::MissingFile)) => (),
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("unexpected success"),
+        }
+    }
+
+    #[test]
+    fn write_newc_archive() {
+        let (hdr, payload) = payload();
+        let mut archive = Archive::new(&payload[..], &hdr).unwrap();
+        let mut out = Vec::new();
+        while let Some(mut entry) = archive.next_entry().unwrap() {
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data).unwrap();
+            let file = &FileEntry::from_header(&hdr).unwrap()[entry.index];
+            write_newc_entry(&mut out, file, &data).unwrap();
+        }
+        write_newc_trailer(&mut out);
+        // rpm leaves the device numbers in, but otherwise it's the same
+        let mut archive = Archive::new(&out[..], &hdr).unwrap();
+        let (_, first) = parse_newc_header(&out[..NEWC_HEADER_SIZE]).unwrap();
+        let (_, orig) = parse_newc_header(&payload[..NEWC_HEADER_SIZE]).unwrap();
+        assert_eq!(first, NewcHeader { devmajor: 0, devminor: 0, ..orig });
+        let mut count = 0;
+        while archive.next_entry().unwrap().is_some() {
+            count += 1;
+        }
+        assert_eq!(count, 4);
+        assert_eq!(out.len(), payload.len());
+
+        // the size comes from the file, and has to fit
+        let mut file = FileEntry::from_header(&hdr).unwrap().remove(0);
+        file.size = 3;
+        match write_newc_entry(&mut Vec::new(), &file, b"ab") {
+            Err(RPMError::Internal) => (),
+            r => panic!("unexpected result: {:?}", r),
+        }
+        file.size = 1 << 32;
+        match write_newc_entry(&mut Vec::new(), &file, b"") {
+            Err(RPMError::File(RPMFileError::FileSize)) => (),
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn read_archive_bad_magic() {
+        let (hdr, _) = payload();
+        let mut archive = Archive::new(&b"lolwut"[..], &hdr).unwrap();
+        match archive.next_entry() {
+            Err(RPMError::Parse(ParseError { kind: RPMFileError::BadMagic, section: Section::Payload, .. })) => (),
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("unexpected success"),
+        }
+    }
+
+    #[test]
+    fn read_archive_huge_name() {
+        let (hdr, _) = payload();
+        let payload = format!("070701{:088x}{:08x}{:08x}", 0, 0xffffffffu32, 0).into_bytes();
+        let mut archive = Archive::new(&payload[..], &hdr).unwrap();
+        match archive.next_entry() {
+            Err(RPMError::Parse(ParseError { kind: RPMFileError::BadHeader, expected: Some(4096), .. })) => (),
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("unexpected success"),
+        }
+    }
+
+    #[test]
+    fn read_truncated_archive() {
+        let (hdr, payload) = payload();
+        let mut archive = Archive::new(&payload[..1000], &hdr).unwrap();
+        let mut entry = archive.next_entry().unwrap().unwrap();
+        let err = entry.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        match archive.next_entry() {
+            Err(RPMError::Parse(ParseError { kind: RPMFileError::Truncated, offset: 1000, .. })) => (),
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("unexpected success"),
+        }
+    }
+}
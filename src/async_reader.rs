@@ -0,0 +1,226 @@
+/* async_reader.rs - a Reader for RPM files from tokio's AsyncRead.
+ *
+ * Copyright (c) 2017, Red Hat, Inc.
+ *
+ * This library is free software; you can redistribute it and/or modify it
+ * under the terms and conditions of the GNU Lesser General Public License
+ * as published by the Free Software Foundation; either version 2.1 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU Lesser General Public License for
+ * more details.
+ *
+ * Authors:
+ *   Will Woods <wwoods@redhat.com>
+ */
+use std::future::{Future, poll_fn};
+use std::io;
+use std::mem;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, ReadBuf};
+
+use header::{Lead, Header};
+use signature::SignatureHeader;
+use parser::{HeaderSectionHeader, Limits, check_section_header, check_header};
+use reader::check_lead;
+use error::{RPMError, Section};
+use Result;
+
+// Like `?`, but for a Poll: return early if it's not ready yet, too
+macro_rules! try_ready {
+    ($e:expr) => (match $e {
+        Poll::Ready(Ok(v)) => v,
+        Poll::Ready(Err(e)) => return Poll::Ready(Err(From::from(e))),
+        Poll::Pending => return Poll::Pending,
+    })
+}
+
+/// An RPM reader for a tokio `AsyncRead`. It works like `Reader`: read the
+/// lead, then the signature header, then the main header, and then what's
+/// left is the payload.
+///
+/// Each method returns a future that borrows the reader until it's done.
+/// Dropping one of those futures before it finishes leaves the reader
+/// somewhere in the middle of a section, so it can't be used after that.
+pub struct AsyncReader<R: AsyncRead + Unpin> {
+    rdr: R,
+    did_sig: bool,
+    did_hdr: bool,
+    limits: Limits,
+    pos: u64,
+    // what's been read of the section so far
+    buf: Vec<u8>,
+}
+
+impl<R: AsyncRead + Unpin> AsyncReader<R> {
+    /// Creates a new async RPM Reader from an arbitrary `AsyncRead`.
+    pub fn from_reader(rdr: R) -> AsyncReader<R> {
+        AsyncReader {
+            rdr,
+            did_sig: false,
+            did_hdr: false,
+            limits: Limits::default(),
+            pos: 0,
+            buf: Vec::new(),
+        }
+    }
+    /// Use the given size limits for header sections, instead of the
+    /// defaults.
+    pub fn with_limits(mut self, limits: Limits) -> AsyncReader<R> {
+        self.limits = limits;
+        self
+    }
+    /// Read and parse the rpm Lead.
+    pub fn lead(&mut self) -> impl Future<Output = Result<Lead>> + '_ {
+        poll_fn(move |cx| {
+            try_ready!(self.poll_fill(cx, 0x60));
+            let buf = mem::take(&mut self.buf);
+            Poll::Ready(check_lead(&buf))
+        })
+    }
+    /// Read and parse the signature header, which comes right after the
+    /// lead.
+    pub fn signature(&mut self) -> impl Future<Output = Result<SignatureHeader>> + '_ {
+        let did_sig = self.did_sig;
+        let mut header = self.header();
+        poll_fn(move |cx| {
+            if did_sig {
+                return Poll::Ready(Err(RPMError::OutOfOrder));
+            }
+            Pin::new(&mut header).poll(cx).map(|r| r.map(SignatureHeader::new))
+        })
+    }
+    /// Read and parse the next RPM Header section; see `Reader::header()`.
+    pub fn header(&mut self) -> impl Future<Output = Result<Header>> + '_ {
+        let (section, base) = (self.next_section(), self.pos);
+        let mut found: Option<(HeaderSectionHeader, usize)> = None;
+        poll_fn(move |cx| {
+            if found.is_none() {
+                try_ready!(self.poll_fill(cx, 0x10));
+                let hdr = check_section_header(&self.buf, section, base, &self.limits)?;
+                // If this is the signature header, pad to an 8-byte-aligned size
+                let padsize = if !self.did_sig { hdr.padsize() } else { 0 };
+                if !self.did_sig { self.did_sig = true } else { self.did_hdr = true };
+                let datasize = hdr.datasize() + padsize;
+                self.buf.clear();
+                found = Some((hdr, datasize));
+            }
+            let (ref hdr, datasize) = *found.as_ref().unwrap();
+            try_ready!(self.poll_fill(cx, datasize));
+            let buf = mem::take(&mut self.buf);
+            Poll::Ready(check_header(&buf, hdr, section, base + 0x10))
+        })
+    }
+    // Which header section is up next
+    fn next_section(&self) -> Section {
+        if !self.did_sig { Section::Signature } else { Section::Header }
+    }
+    // Read until there's `len` bytes in the buffer, or the reader runs out.
+    // Never reads past `len`, so the payload's left alone.
+    fn poll_fill(&mut self, cx: &mut Context, len: usize) -> Poll<io::Result<()>> {
+        let mut chunk = [0; 8192];
+        while self.buf.len() < len {
+            let want = (len - self.buf.len()).min(chunk.len());
+            let mut rb = ReadBuf::new(&mut chunk[..want]);
+            match Pin::new(&mut self.rdr).poll_read(cx, &mut rb) {
+                Poll::Ready(Ok(())) if rb.filled().is_empty() => break,
+                Poll::Ready(Ok(())) => {
+                    self.buf.extend_from_slice(rb.filled());
+                    self.pos += rb.filled().len() as u64;
+                }
+                Poll::Ready(Err(ref e)) if e.kind() == io::ErrorKind::Interrupted => (),
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+    /// Get the (still compressed) payload, which starts right after the
+    /// main header. Both header sections must have been read first.
+    pub fn payload(&mut self) -> Result<&mut R> {
+        if !(self.did_sig && self.did_hdr) {
+            return Err(RPMError::OutOfOrder);
+        }
+        Ok(&mut self.rdr)
+    }
+    /// Get the underlying reader back.
+    pub fn into_inner(self) -> R {
+        self.rdr
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::future::Future;
+    use tokio::io::AsyncReadExt;
+    use tokio::runtime;
+    use reader::Reader;
+    use error::{ParseError, RPMFileError};
+    static BINRPM1: &[u8] = include_bytes!("../tests/rpms/binary.x86_64.rpm");
+
+    fn block_on<F: Future>(f: F) -> F::Output {
+        runtime::Builder::new_current_thread().build().unwrap().block_on(f)
+    }
+
+    // Hands out a few bytes at a time, and isn't ready every other time
+    struct Trickle {
+        data: &'static [u8],
+        ready: bool,
+    }
+
+    impl AsyncRead for Trickle {
+        fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context, buf: &mut ReadBuf) -> Poll<io::Result<()>> {
+            self.ready = !self.ready;
+            if !self.ready {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            let n = self.data.len().min(buf.remaining()).min(7);
+            buf.put_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[test]
+    fn async_read_package() {
+        let mut r = Reader::from_bytes(BINRPM1);
+        let (lead, sig, hdr) = (r.lead().unwrap(), r.signature().unwrap(), r.header().unwrap());
+
+        let mut ar = AsyncReader::from_reader(Trickle { data: BINRPM1, ready: false });
+        assert_eq!(block_on(ar.lead()).unwrap(), lead);
+        assert!(ar.payload().is_err());
+        assert_eq!(block_on(ar.signature()).unwrap().header(), sig.header());
+        assert_eq!(block_on(ar.header()).unwrap(), hdr);
+        let mut payload = Vec::new();
+        block_on(ar.payload().unwrap().read_to_end(&mut payload)).unwrap();
+        assert_eq!(payload[..], BINRPM1[0x313a..]);
+    }
+
+    #[test]
+    fn async_read_out_of_order() {
+        let mut ar = AsyncReader::from_reader(BINRPM1);
+        block_on(ar.lead()).unwrap();
+        block_on(ar.signature()).unwrap();
+        match block_on(ar.signature()).unwrap_err() {
+            RPMError::OutOfOrder => (),
+            e => panic!("unexpected error: {}", e),
+        }
+    }
+
+    #[test]
+    fn async_read_truncated() {
+        let mut ar = AsyncReader::from_reader(Trickle { data: &BINRPM1[..0x2000], ready: false });
+        block_on(ar.lead()).unwrap();
+        block_on(ar.signature()).unwrap();
+        match block_on(ar.header()).unwrap_err() {
+            RPMError::Parse(ParseError { kind: RPMFileError::Truncated, section: Section::Header,
+                                         offset: 0x1588, expected: Some(0x1bb2), actual: Some(0xa78), .. }) => (),
+            e => panic!("unexpected error: {}", e),
+        }
+    }
+}
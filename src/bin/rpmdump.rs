@@ -22,21 +22,135 @@
 extern crate clap;
 extern crate rpm;
 
-use rpm::{Reader, TagInfo};
+use std::fs;
+use std::io;
+use std::io::Write;
+use clap::{Arg, SubCommand};
+use rpm::{Reader, SignatureHeader, TagInfo};
 
 fn main() {
     let m = clap_app!(rpmdump =>
         (version: "0.1")
         (author: "Will Woods <wwoods@redhat.com>")
         (about: "Dump RPM header metadata in various formats")
+        (@setting SubcommandsNegateReqs)
 
         (@arg format: -o --format possible_value[pretty json toml]
             default_value("pretty")
             "output format")
+        (@arg tags: -t --tag +takes_value +multiple number_of_values(1)
+            "only show this header tag (by name, like NAME or BASENAMES)")
         (@arg rpms: <RPM> * ...
             "RPM to read")
+
+        (@subcommand extract =>
+            (about: "Extract the files in an RPM (without running scriptlets)")
+            (@arg root: -C --root +takes_value default_value(".")
+                "directory to extract into")
+            (@arg rpm: <RPM> * "RPM to extract"))
+    ).subcommand(SubCommand::with_name("to-tar")
+        .about("Convert the payload of an RPM into a tar archive")
+        .arg(Arg::with_name("output").short("f").long("file").takes_value(true)
+            .help("file to write the tar archive to (default: stdout)"))
+        .arg(Arg::with_name("rpm").value_name("RPM").required(true)
+            .help("RPM to convert"))
+    ).subcommand(SubCommand::with_name("check")
+        .about("Check the digests and signatures of RPMs, like rpm -Kv")
+        .arg(Arg::with_name("keys").short("k").long("key").takes_value(true)
+            .multiple(true).number_of_values(1)
+            .help("ASCII-armored public key file to check signatures with"))
+        .arg(Arg::with_name("rpms").value_name("RPM").required(true).multiple(true)
+            .help("RPM to check"))
+    ).subcommand(SubCommand::with_name("sign")
+        .about("Sign RPMs in place with a local secret key, like rpmsign")
+        .arg(Arg::with_name("key").short("k").long("key").takes_value(true)
+            .required_unless("delete")
+            .help("ASCII-armored secret key file to sign with"))
+        .arg(Arg::with_name("header-payload").long("header-payload")
+            .help("also make a header+payload signature, for older rpm versions"))
+        .arg(Arg::with_name("delete").long("delete").conflicts_with("key")
+            .help("remove the signatures instead"))
+        .arg(Arg::with_name("rpms").value_name("RPM").required(true).multiple(true)
+            .help("RPM to sign"))
     ).get_matches();
 
+    if let Some(m) = m.subcommand_matches("extract") {
+        let path = m.value_of("rpm").unwrap();
+        let root = m.value_of("root").unwrap();
+        if let Err(e) = extract(path, root) {
+            println!("error extracting {}: {}", path, e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(m) = m.subcommand_matches("to-tar") {
+        let path = m.value_of("rpm").unwrap();
+        let res = match m.value_of("output") {
+            Some(out) => fs::File::create(out).map_err(rpm::RPMError::from)
+                             .and_then(|f| to_tar(path, f)),
+            None => to_tar(path, io::stdout()),
+        };
+        // stdout might be the tarball, so complain on stderr
+        if let Err(e) = res {
+            eprintln!("error converting {}: {}", path, e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(m) = m.subcommand_matches("check") {
+        let mut keyring = rpm::Keyring::new();
+        for key in m.values_of("keys").into_iter().flatten() {
+            if let Err(e) = keyring.load(key) {
+                println!("error loading key {}: {}", key, e);
+                std::process::exit(1);
+            }
+        }
+        let mut ok = true;
+        for path in m.values_of("rpms").unwrap() {
+            println!("{}:", path);
+            match fs::File::open(path).map_err(rpm::RPMError::from)
+                      .and_then(|f| rpm::verify_package_signatures(io::BufReader::new(f), &keyring)) {
+                Ok(v) => {
+                    for sig in &v.signatures {
+                        println!("    {}", sig);
+                    }
+                    for digest in &v.digests {
+                        println!("    {} digest: OK", digest);
+                    }
+                    ok &= v.is_ok();
+                },
+                Err(e) => { println!("    {}", e); ok = false; },
+            }
+        }
+        if !ok {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(m) = m.subcommand_matches("sign") {
+        let key = match m.value_of("key").map(rpm::SecretKey::load) {
+            Some(Ok(key)) => Some(key),
+            Some(Err(e)) => { println!("error loading key: {}", e); std::process::exit(1); },
+            None => None,
+        };
+        let signer = key.as_ref().map(|key| rpm::Signer::new(key)
+                                      .header_payload(m.is_present("header-payload")));
+        let mut ok = true;
+        for path in m.values_of("rpms").unwrap() {
+            if let Err(e) = sign(path, signer.as_ref()) {
+                println!("error signing {}: {}", path, e);
+                ok = false;
+            }
+        }
+        if !ok {
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let format = m.value_of("format").unwrap();
     // TODO: set up formatter
     match format {
@@ -44,8 +158,23 @@ fn main() {
         _ => println!("HRM I DUNNO HOW TO DO '{}' ACTUALLY", format)
     }
 
+    // look up the tags we were asked for, if any
+    let mut tags = Vec::new();
+    for name in m.values_of("tags").into_iter().flatten() {
+        match TagInfo::from_name(name) {
+            Some(tag) => tags.push(*tag),
+            None => { println!("unknown tag '{}'", name); std::process::exit(1); },
+        }
+    }
+
     let rpm_args = m.values_of("rpms").unwrap().into_iter();
     for path in rpm_args {
+        if !tags.is_empty() {
+            if let Err(e) = show_tags(path, &tags) {
+                println!("error reading {}: {}", path, e);
+            }
+            continue;
+        }
         // open the file
         let mut r = match Reader::from_file(path) {
             Ok(r)  => r,
@@ -58,12 +187,17 @@ fn main() {
         };
         println!("{}: '{}'", path, lead.name);
         // read sig hdr
-        let sig = match r.header() {
+        let sig = match r.signature() {
             Ok(sig) => sig,
             Err(e)  => { println!("error reading sig: {}: {}", path, e); continue; }
         };
         println!("  signature header: {} items", sig.len());
-        // TODO: do something with the signature header
+        for (tagid, value) in sig.header() {
+            match SignatureHeader::tag_name(*tagid) {
+                Some(name) => println!("    {}: {:?}", name, value),
+                None       => println!("    UNKNOWN[{}]: {:?}", tagid, value),
+            }
+        }
 
         // read main hdr
         let hdr = match r.header() {
@@ -78,5 +212,68 @@ fn main() {
                 None      => println!("    UNKNOWN[{}]: {:?}", tagid, value),
             }
         }
+        for tagid in hdr.duplicates() {
+            println!("  warning: duplicate tag {}", tagid);
+        }
+        match hdr.dribbles() {
+            Ok(ref tags) if tags.is_empty() => (),
+            Ok(tags) => println!("  dribble tags (not covered by signatures): {:?}", tags),
+            Err(e) => println!("  warning: {}", e),
+        }
     }
 }
+
+// Just show the given tags, which only needs those ones decoded
+fn show_tags(path: &str, tags: &[&TagInfo]) -> rpm::Result<()> {
+    let mut r = Reader::from_reader(io::BufReader::new(fs::File::open(path)?));
+    r.lead()?;
+    r.seek_to(rpm::Section::Header)?;
+    let hdr = r.lazy_header()?;
+    println!("{}:", path);
+    for tag in tags {
+        match hdr.get(&(tag.id as rpm::TagID))? {
+            Some(value) => println!("    {}: {:?}", tag.name, value),
+            None => println!("    {}: (none)", tag.name),
+        }
+    }
+    Ok(())
+}
+
+fn extract(path: &str, root: &str) -> rpm::Result<()> {
+    let mut r = Reader::from_reader(fs::File::open(path)?);
+    r.lead()?;
+    r.header()?;
+    let hdr = r.header()?;
+    let mut archive = r.archive(&hdr)?;
+    rpm::extract(&mut archive, &hdr, root)
+}
+
+// Write the signed (or unsigned) package next to the original, then
+// replace it, so a failure doesn't leave a half-written package behind.
+fn sign(path: &str, signer: Option<&rpm::Signer>) -> rpm::Result<()> {
+    let tmp = format!("{}.signing", path);
+    let res = fs::File::open(path).map_err(rpm::RPMError::from).and_then(|f| {
+        let rdr = io::BufReader::new(f);
+        let mut out = io::BufWriter::new(fs::File::create(&tmp)?);
+        match signer {
+            Some(signer) => signer.sign_package(rdr, &mut out)?,
+            None => rpm::remove_signatures(rdr, &mut out)?,
+        }
+        out.flush()?;
+        Ok(())
+    });
+    match res {
+        Ok(()) => Ok(fs::rename(&tmp, path)?),
+        Err(e) => { let _ = fs::remove_file(&tmp); Err(e) },
+    }
+}
+
+fn to_tar<W: io::Write>(path: &str, out: W) -> rpm::Result<()> {
+    let mut r = Reader::from_reader(fs::File::open(path)?);
+    r.lead()?;
+    r.header()?;
+    let hdr = r.header()?;
+    let mut archive = r.archive(&hdr)?;
+    rpm::to_tar(&mut archive, &hdr, io::BufWriter::new(out))?.flush()?;
+    Ok(())
+}
@@ -0,0 +1,393 @@
+/* borrowed.rs - header sections that borrow their values from a buffer
+ *
+ * Copyright (c) 2017, Red Hat, Inc.
+ *
+ * This library is free software; you can redistribute it and/or modify it
+ * under the terms and conditions of the GNU Lesser General Public License
+ * as published by the Free Software Foundation; either version 2.1 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU Lesser General Public License for
+ * more details.
+ *
+ * Authors:
+ *   Will Woods <wwoods@redhat.com>
+ */
+use std::fmt;
+use std::marker::PhantomData;
+use std::slice;
+use std::str;
+
+use header::{Header, TagEntry, Region, check_region};
+use tag::{TagID, TagValue};
+use Result;
+
+/// Integers that RPM stores big-endian, which a `BeArray` can hold.
+pub trait BigEndian: Copy {
+    /// How many bytes each one takes
+    const SIZE: usize;
+    /// Decode one from the first SIZE bytes of `b`.
+    fn from_be_slice(b: &[u8]) -> Self;
+}
+
+impl BigEndian for u16 {
+    const SIZE: usize = 2;
+    fn from_be_slice(b: &[u8]) -> u16 {
+        u16::from_be_bytes([b[0], b[1]])
+    }
+}
+
+impl BigEndian for u32 {
+    const SIZE: usize = 4;
+    fn from_be_slice(b: &[u8]) -> u32 {
+        u32::from_be_bytes([b[0], b[1], b[2], b[3]])
+    }
+}
+
+impl BigEndian for u64 {
+    const SIZE: usize = 8;
+    fn from_be_slice(b: &[u8]) -> u64 {
+        let mut buf = [0; 8];
+        buf.copy_from_slice(&b[..8]);
+        u64::from_be_bytes(buf)
+    }
+}
+
+/// An array of big-endian integers, straight out of a data store. Items
+/// are only decoded when they're asked for.
+#[derive(Clone,Copy,PartialEq,Eq)]
+pub struct BeArray<'a, T: BigEndian> {
+    data: &'a [u8],
+    _type: PhantomData<T>,
+}
+
+impl<'a, T: BigEndian> BeArray<'a, T> {
+    /// Wrap a buffer of big-endian integers. Any bytes past the last whole
+    /// item are ignored.
+    pub fn new(data: &'a [u8]) -> BeArray<'a, T> {
+        let len = data.len() - data.len() % T::SIZE;
+        BeArray { data: &data[..len], _type: PhantomData }
+    }
+    pub fn len(&self) -> usize {
+        self.data.len() / T::SIZE
+    }
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+    pub fn get(&self, i: usize) -> Option<T> {
+        let start = i.checked_mul(T::SIZE)?;
+        self.data.get(start..start + T::SIZE).map(T::from_be_slice)
+    }
+    pub fn iter(&self) -> BeIter<'a, T> {
+        BeIter { chunks: self.data.chunks(T::SIZE), _type: PhantomData }
+    }
+    /// The raw, big-endian bytes.
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.data
+    }
+    pub fn to_vec(&self) -> Vec<T> {
+        self.iter().collect()
+    }
+}
+
+impl<'a, T: BigEndian + fmt::Debug> fmt::Debug for BeArray<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<'a, T: BigEndian> IntoIterator for BeArray<'a, T> {
+    type Item = T;
+    type IntoIter = BeIter<'a, T>;
+    fn into_iter(self) -> BeIter<'a, T> {
+        self.iter()
+    }
+}
+
+/// Iterator over the items in a `BeArray`.
+pub struct BeIter<'a, T> {
+    chunks: slice::Chunks<'a, u8>,
+    _type: PhantomData<T>,
+}
+
+impl<'a, T: BigEndian> Iterator for BeIter<'a, T> {
+    type Item = T;
+    fn next(&mut self) -> Option<T> {
+        self.chunks.next().map(T::from_be_slice)
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.chunks.size_hint()
+    }
+}
+
+/// One or more NUL-terminated strings, straight out of a data store.
+#[derive(Clone,Copy,PartialEq,Eq)]
+pub struct Strings<'a> {
+    // the strings, each with its NUL
+    data: &'a str,
+    count: usize,
+}
+
+impl<'a> Strings<'a> {
+    /// Wrap `count` NUL-terminated strings (which is all that `data` may
+    /// hold). Returns None if they aren't all there.
+    pub fn new(data: &'a str, count: usize) -> Option<Strings<'a>> {
+        let nuls = data.bytes().filter(|&b| b == 0).count();
+        if nuls != count || !(data.is_empty() || data.ends_with('\0')) {
+            return None;
+        }
+        Some(Strings { data, count })
+    }
+    pub fn len(&self) -> usize {
+        self.count
+    }
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+    /// The i'th string. (This has to walk through the ones before it.)
+    pub fn get(&self, i: usize) -> Option<&'a str> {
+        self.iter().nth(i)
+    }
+    pub fn iter(&self) -> str::SplitTerminator<'a, char> {
+        self.data.split_terminator('\0')
+    }
+    pub fn to_vec(&self) -> Vec<String> {
+        self.iter().map(String::from).collect()
+    }
+}
+
+impl<'a> fmt::Debug for Strings<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<'a> IntoIterator for Strings<'a> {
+    type Item = &'a str;
+    type IntoIter = str::SplitTerminator<'a, char>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// A tag's value, borrowed from the data store it was read from.
+/// It's the same as a `TagValue`, but without making copies.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum TagValueRef<'a> {
+    Null,
+    Char(&'a [u8]),
+    Int8(&'a [u8]),
+    Int16(BeArray<'a, u16>),
+    Int32(BeArray<'a, u32>),
+    Int64(BeArray<'a, u64>),
+    Binary(&'a [u8]),
+    String(Strings<'a>),
+}
+
+impl<'a> TagValueRef<'a> {
+    /// Copy the value into a `TagValue`.
+    pub fn to_value(&self) -> TagValue {
+        match *self {
+            TagValueRef::Null => TagValue::Null,
+            TagValueRef::Char(v) => TagValue::Char(v.to_vec()),
+            TagValueRef::Int8(v) => TagValue::Int8(v.to_vec()),
+            TagValueRef::Int16(v) => TagValue::Int16(v.to_vec()),
+            TagValueRef::Int32(v) => TagValue::Int32(v.to_vec()),
+            TagValueRef::Int64(v) => TagValue::Int64(v.to_vec()),
+            TagValueRef::Binary(v) => TagValue::Binary(v.to_vec()),
+            TagValueRef::String(v) => TagValue::String(v.to_vec()),
+        }
+    }
+
+    /// The value, if it's a single string.
+    pub fn as_str(&self) -> Option<&'a str> {
+        match *self {
+            TagValueRef::String(v) if v.len() == 1 => v.get(0),
+            _ => None,
+        }
+    }
+}
+
+/// A header section whose values are borrowed from the buffer it was read
+/// from (see `Reader::header_ref()`), which makes reading one much cheaper
+/// than reading a `Header`, since nothing gets copied.
+///
+/// Tags are looked up by searching the index, which is about as quick as
+/// hashing for a header's worth of tags. Like `Header`, lookups get the
+/// first entry for a tag that shows up more than once.
+#[derive(Debug,Clone)]
+pub struct HeaderRef<'a> {
+    entries: Vec<(TagEntry, TagValueRef<'a>)>,
+}
+
+impl<'a> HeaderRef<'a> {
+    /// Make a header out of entries as they were read from a section.
+    pub fn from_entries(entries: Vec<(TagEntry, TagValueRef<'a>)>) -> HeaderRef<'a> {
+        HeaderRef { entries }
+    }
+
+    /// Number of entries, including any duplicates.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+    pub fn contains_key(&self, id: &TagID) -> bool {
+        self.entry(id).is_some()
+    }
+    /// The value of a tag (the first one, if it's duplicated).
+    pub fn get(&self, id: &TagID) -> Option<&TagValueRef<'a>> {
+        self.entries.iter().find(|(entry, _)| entry.tagid == *id).map(|(_, value)| value)
+    }
+    /// The index entry for a tag (the first one, if it's duplicated).
+    pub fn entry(&self, id: &TagID) -> Option<&TagEntry> {
+        self.entries.iter().find(|(entry, _)| entry.tagid == *id).map(|(entry, _)| entry)
+    }
+    /// The index entries and their values, in order.
+    pub fn entries(&self) -> slice::Iter<'_, (TagEntry, TagValueRef<'a>)> {
+        self.entries.iter()
+    }
+
+    /// Decode and check the header's region; see `Header::region()`.
+    pub fn region(&self) -> Result<Option<Region>> {
+        self.region_in(None)
+    }
+    // See `Header::region_in()`.
+    pub(crate) fn region_in(&self, store: Option<usize>) -> Result<Option<Region>> {
+        let trailer = match self.entries.first() {
+            Some((_, TagValueRef::Binary(v))) => Some(*v),
+            _ => None,
+        };
+        check_region(self.entries.iter().map(|(entry, _)| entry), trailer, store)
+    }
+
+    /// Copy everything into a `Header`.
+    pub fn to_header(&self) -> Header {
+        Header::from_entries(self.entries.iter().map(|(entry, value)| (*entry, value.to_value())).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::{parse_section_data, parse_section_ref};
+    use reader::Reader;
+    use payload::Compressor;
+    use tag::{Tag, TagType};
+    static BINRPM1: &[u8] = include_bytes!("../tests/rpms/binary.x86_64.rpm");
+
+    #[test]
+    fn be_array() {
+        let a: BeArray<u32> = BeArray::new(b"\0\0\0\x01\0\0\x01\0\xff");
+        assert_eq!(a.len(), 2);
+        assert_eq!(a.get(1), Some(256));
+        assert_eq!(a.get(2), None);
+        assert_eq!(a.to_vec(), vec![1, 256]);
+        let b: BeArray<u64> = BeArray::new(b"\0\0\0\0\0\0\0\x07");
+        assert_eq!(b.iter().collect::<Vec<_>>(), vec![7]);
+        assert!(BeArray::<u16>::new(b"\x01").is_empty());
+    }
+
+    #[test]
+    fn strings() {
+        let s = Strings::new("one\0\0three\0", 3).unwrap();
+        assert_eq!(s.iter().collect::<Vec<_>>(), vec!["one", "", "three"]);
+        assert_eq!(s.get(2), Some("three"));
+        assert_eq!(s.get(3), None);
+        assert!(Strings::new("one\0two", 2).is_none());
+        assert!(Strings::new("one\0", 2).is_none());
+        assert!(Strings::new("", 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn borrowed_header() {
+        let data = &BINRPM1[0x1588..];
+        let (rest, hdr) = parse_section_ref(data, 62, 0x17d2).unwrap();
+        assert_eq!(rest.len(), BINRPM1.len() - 0x313a);
+        assert_eq!(hdr.len(), 62);
+        assert_eq!(hdr.get(&(Tag::NAME as TagID)).and_then(|v| v.as_str()), Some("hardlink"));
+        match *hdr.get(&(Tag::FILESIZES as TagID)).unwrap() {
+            TagValueRef::Int32(sizes) => assert_eq!(sizes.to_vec(), vec![15720, 4096, 18092, 889]),
+            ref v => panic!("unexpected value: {:?}", v),
+        }
+        assert_eq!(hdr.entry(&(Tag::NAME as TagID)).unwrap().tagtype, TagType::STRING);
+        assert_eq!(hdr.region().unwrap().unwrap().count, 62);
+        // it's the same as the owned version
+        let (_, owned) = parse_section_data(data, 62, 0x17d2).unwrap();
+        assert_eq!(hdr.to_header(), owned);
+        assert!(hdr.entries().zip(owned.entries()).all(|(a, b)| a.0 == b.0));
+    }
+
+    #[test]
+    fn borrowed_matches_owned() {
+        // NAME is a STRING; mess with its count, and then its type
+        let name = (0..62).find(|i| BINRPM1[0x1588 + i * 16..][..4] == [0, 0, 0x03, 0xe8]).unwrap();
+        let entry = 0x1588 + name * 16;
+        for &(tagtype, count) in [(6u32, 0u32), (6, 3), (6, 0xffffffff), (8, 3), (8, 0xffffffff)].iter() {
+            let mut data = BINRPM1[0x1588..0x313a].to_vec();
+            data[entry - 0x1588 + 4..][..4].copy_from_slice(&tagtype.to_be_bytes());
+            data[entry - 0x1588 + 12..][..4].copy_from_slice(&count.to_be_bytes());
+            let borrowed = parse_section_ref(&data, 62, 0x17d2).map(|(_, hdr)| hdr.to_header());
+            let owned = parse_section_data(&data, 62, 0x17d2).map(|(_, hdr)| hdr);
+            match (borrowed, owned) {
+                (Ok(borrowed), Ok(owned)) => assert_eq!(borrowed, owned),
+                (Err(_), Err(_)) => (),
+                r => panic!("type {}, count {}: {:?}", tagtype, count, r),
+            }
+        }
+        // a STRING is one string, however many its count says
+        let mut data = BINRPM1[0x1588..0x313a].to_vec();
+        data[entry - 0x1588 + 12..][..4].copy_from_slice(&3u32.to_be_bytes());
+        let (_, hdr) = parse_section_ref(&data, 62, 0x17d2).unwrap();
+        assert_eq!(hdr.get(&(Tag::NAME as TagID)).and_then(|v| v.as_str()), Some("hardlink"));
+    }
+
+    #[test]
+    fn reader_header_ref() {
+        let mut r = Reader::from_bytes(BINRPM1);
+        r.lead().unwrap();
+        let sig = r.header_ref().unwrap().to_header();
+        assert_eq!(sig.len(), 8);
+        let owned = {
+            let hdr = r.header_ref().unwrap();
+            assert_eq!(hdr.get(&(Tag::ARCH as TagID)).and_then(|v| v.as_str()), Some("x86_64"));
+            hdr.to_header()
+        };
+        // the payload's right after it, as usual
+        assert_eq!(Compressor::from_header(&owned).unwrap(), Compressor::Xz);
+        assert!(r.archive(&owned).unwrap().next_entry().unwrap().is_some());
+
+        let mut r = Reader::from_bytes(BINRPM1);
+        r.lead().unwrap();
+        r.signature().unwrap();
+        assert_eq!(r.header().unwrap(), owned);
+    }
+}
+
+#[cfg(feature="bench")]
+mod bench {
+    extern crate test;
+    use self::test::Bencher;
+    use parser::{parse_section_data, parse_section_ref};
+    use tag::{Tag, TagID};
+    static BINRPM1: &[u8] = include_bytes!("../tests/rpms/binary.x86_64.rpm");
+
+    #[bench]
+    fn parse_header_owned(b: &mut Bencher) {
+        b.iter(|| {
+            let (_, hdr) = parse_section_data(&BINRPM1[0x1588..], 62, 0x17d2).unwrap();
+            hdr.get(&(Tag::NAME as TagID)).is_some()
+        });
+    }
+
+    #[bench]
+    fn parse_header_borrowed(b: &mut Bencher) {
+        b.iter(|| {
+            let (_, hdr) = parse_section_ref(&BINRPM1[0x1588..], 62, 0x17d2).unwrap();
+            hdr.get(&(Tag::NAME as TagID)).is_some()
+        });
+    }
+}
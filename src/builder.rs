@@ -0,0 +1,813 @@
+/* builder.rs - building RPM packages from scratch
+ *
+ * Copyright (c) 2017, Red Hat, Inc.
+ *
+ * This library is free software; you can redistribute it and/or modify it
+ * under the terms and conditions of the GNU Lesser General Public License
+ * as published by the Free Software Foundation; either version 2.1 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU Lesser General Public License for
+ * more details.
+ *
+ * Authors:
+ *   Will Woods <wwoods@redhat.com>
+ */
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStringExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use md5::Md5;
+use sha1::Sha1;
+use sha2::{Sha256, Digest};
+
+use header::{Header, Lead};
+use signature::SignatureHeader;
+use writer::header_to_bytes;
+use archive::{write_newc_entry, write_newc_trailer};
+use payload::Compressor;
+use files::{FileEntry, FileFlags, S_IFMT, S_IFREG, S_IFDIR, S_IFLNK};
+use deps::{Dependency, DepFlags, DepKind};
+use version::Evr;
+use verify::{hex, PGPHASHALGO_SHA256};
+use tag::{Tag, SigTag, TagID, TagValue};
+use error::{RPMError, RPMFileError};
+use Result;
+
+// How much room to leave in the signature header for signing the package
+// later, like rpm does
+const RESERVED_SPACE: usize = 4128;
+
+/// A file to put in a package built with `PackageBuilder`.
+///
+/// Files are owned by root:root unless you say otherwise.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct PackageFile {
+    path: String,
+    mode: u32,
+    // the file's contents, or a symlink's target
+    data: Vec<u8>,
+    user: String,
+    group: String,
+    flags: FileFlags,
+    mtime: Option<u32>,
+}
+
+impl PackageFile {
+    fn with_mode<S: Into<String>>(path: S, mode: u32, data: Vec<u8>) -> PackageFile {
+        PackageFile {
+            path: path.into(),
+            mode,
+            data,
+            user: String::from("root"),
+            group: String::from("root"),
+            flags: FileFlags::empty(),
+            mtime: None,
+        }
+    }
+
+    /// A regular file at `path` (like "/usr/bin/foo"), with the given
+    /// contents and mode 0644.
+    pub fn new<S: Into<String>, D: Into<Vec<u8>>>(path: S, data: D) -> PackageFile {
+        PackageFile::with_mode(path, S_IFREG | 0o644, data.into())
+    }
+    /// A directory, with mode 0755.
+    pub fn dir<S: Into<String>>(path: S) -> PackageFile {
+        PackageFile::with_mode(path, S_IFDIR | 0o755, Vec::new())
+    }
+    /// A symlink pointing at `target`.
+    pub fn symlink<S: Into<String>, T: Into<String>>(path: S, target: T) -> PackageFile {
+        PackageFile::with_mode(path, S_IFLNK | 0o777, target.into().into_bytes())
+    }
+    /// A file, directory or symlink on disk, to go in the package as
+    /// `path`. Its contents, mode and mtime are read right away.
+    pub fn from_path<P: AsRef<Path>, S: Into<String>>(src: P, path: S) -> Result<PackageFile> {
+        let src = src.as_ref();
+        let meta = fs::symlink_metadata(src)?;
+        let data = match meta.mode() & S_IFMT {
+            S_IFREG => fs::read(src)?,
+            S_IFDIR => Vec::new(),
+            S_IFLNK => fs::read_link(src)?.into_os_string().into_vec(),
+            _ => return Err(RPMError::File(RPMFileError::UnknownFiletype)),
+        };
+        Ok(PackageFile::with_mode(path, meta.mode(), data).mtime(meta.mtime() as u32))
+    }
+
+    /// Set the permission bits, like 0o755.
+    pub fn mode(mut self, mode: u32) -> PackageFile {
+        self.mode = (self.mode & S_IFMT) | (mode & 0o7777);
+        self
+    }
+    pub fn user<S: Into<String>>(mut self, user: S) -> PackageFile {
+        self.user = user.into();
+        self
+    }
+    pub fn group<S: Into<String>>(mut self, group: S) -> PackageFile {
+        self.group = group.into();
+        self
+    }
+    /// Mark the file with flags like `FileFlags::CONFIG` (for `%config`)
+    /// or `FileFlags::DOC` (for `%doc`). `%ghost` files are listed in the
+    /// header, but left out of the payload.
+    pub fn flags(mut self, flags: FileFlags) -> PackageFile {
+        self.flags |= flags;
+        self
+    }
+    /// Set the modification time, in seconds since the epoch. Otherwise
+    /// it's the package's build time.
+    pub fn mtime(mut self, mtime: u32) -> PackageFile {
+        self.mtime = Some(mtime);
+        self
+    }
+
+    // What the archive needs to know about the file
+    fn entry(&self, inode: u32, mtime: u32) -> FileEntry {
+        FileEntry {
+            path: self.path.clone(),
+            size: self.data.len() as u64,
+            mode: self.mode,
+            mtime,
+            digest: None,
+            linkto: None,
+            user: self.user.clone(),
+            group: self.group.clone(),
+            flags: self.flags,
+            inode,
+            device: 1,
+            rdev: 0,
+            lang: None,
+            content: true,
+        }
+    }
+
+    fn is_file(&self) -> bool {
+        self.mode & S_IFMT == S_IFREG
+    }
+    fn is_symlink(&self) -> bool {
+        self.mode & S_IFMT == S_IFLNK
+    }
+}
+
+/// The scriptlets a package can run while it's installed or erased.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum Scriptlet {
+    /// %pre
+    Pre,
+    /// %post
+    Post,
+    /// %preun
+    PreUn,
+    /// %postun
+    PostUn,
+    /// %pretrans
+    PreTrans,
+    /// %posttrans
+    PostTrans,
+}
+
+impl Scriptlet {
+    /// The tags that hold the script and its interpreter.
+    pub fn tags(&self) -> (Tag, Tag) {
+        match *self {
+            Scriptlet::Pre => (Tag::PREIN, Tag::PREINPROG),
+            Scriptlet::Post => (Tag::POSTIN, Tag::POSTINPROG),
+            Scriptlet::PreUn => (Tag::PREUN, Tag::PREUNPROG),
+            Scriptlet::PostUn => (Tag::POSTUN, Tag::POSTUNPROG),
+            Scriptlet::PreTrans => (Tag::PRETRANS, Tag::PRETRANSPROG),
+            Scriptlet::PostTrans => (Tag::POSTTRANS, Tag::POSTTRANSPROG),
+        }
+    }
+    // The flag for the scriptlet's dependency on its interpreter
+    fn dep_flag(&self) -> DepFlags {
+        match *self {
+            Scriptlet::Pre => DepFlags::SCRIPT_PRE,
+            Scriptlet::Post => DepFlags::SCRIPT_POST,
+            Scriptlet::PreUn => DepFlags::SCRIPT_PREUN,
+            Scriptlet::PostUn => DepFlags::SCRIPT_POSTUN,
+            Scriptlet::PreTrans => DepFlags::PRETRANS,
+            Scriptlet::PostTrans => DepFlags::POSTTRANS,
+        }
+    }
+}
+
+/// Builds a binary RPM package from scratch, without rpmbuild or a spec
+/// file.
+///
+/// The package gets a lead, a signature header with the usual digests
+/// (and room for a signature; see `Signer`), a main header in an immutable
+/// region, and a compressed cpio payload. Like rpmbuild, it provides
+/// itself, requires the rpmlib features it uses and the interpreters of its
+/// scriptlets, and lists its files in order.
+#[derive(Debug,Clone)]
+pub struct PackageBuilder {
+    name: String,
+    epoch: Option<u32>,
+    version: String,
+    release: String,
+    arch: String,
+    summary: Option<String>,
+    description: Option<String>,
+    license: Option<String>,
+    group: Option<String>,
+    url: Option<String>,
+    vendor: Option<String>,
+    packager: Option<String>,
+    build_time: Option<u32>,
+    build_host: String,
+    compressor: Compressor,
+    deps: Vec<(DepKind, Dependency)>,
+    files: Vec<PackageFile>,
+    scripts: Vec<(Scriptlet, Vec<String>, String)>,
+}
+
+fn string(s: &str) -> TagValue {
+    TagValue::String(vec![String::from(s)])
+}
+
+fn set(hdr: &mut Header, tag: Tag, value: TagValue) {
+    hdr.insert(tag as TagID, value);
+}
+
+fn bad_build(msg: String) -> RPMError {
+    RPMError::File(RPMFileError::BadBuild(msg))
+}
+
+// rpm keeps paths absolute and normalized
+fn check_path(path: &str) -> Result<()> {
+    let ok = path.len() > 1 && path.starts_with('/') &&
+             path[1..].split('/').all(|c| !c.is_empty() && c != "." && c != "..");
+    if ok { Ok(()) } else { Err(bad_build(format!("bad file path '{}'", path))) }
+}
+
+fn rpmlib(feature: &str, version: &str) -> (DepKind, Dependency) {
+    let flags = DepFlags::RPMLIB | DepFlags::LESS | DepFlags::EQUAL;
+    (DepKind::Require, Dependency::new(format!("rpmlib({})", feature), flags, Some(Evr::parse(version))))
+}
+
+// Nothing reads the lead's arch number any more, but rpm still fills it in
+// (from rpmrc's arch_canon table).
+fn lead_archnum(arch: &str) -> u16 {
+    match arch {
+        "ppc64" | "ppc64le" => 16,
+        "s390x" => 15,
+        "aarch64" => 19,
+        "riscv64" => 22,
+        a if a.starts_with("arm") => 12,
+        _ => 1,
+    }
+}
+
+impl PackageBuilder {
+    /// Start building a package. It needs at least a summary and a license
+    /// before it can be built.
+    pub fn new<N, V, R, A>(name: N, version: V, release: R, arch: A) -> PackageBuilder
+            where N: Into<String>, V: Into<String>, R: Into<String>, A: Into<String> {
+        PackageBuilder {
+            name: name.into(),
+            epoch: None,
+            version: version.into(),
+            release: release.into(),
+            arch: arch.into(),
+            summary: None,
+            description: None,
+            license: None,
+            group: None,
+            url: None,
+            vendor: None,
+            packager: None,
+            build_time: None,
+            build_host: String::from("localhost"),
+            compressor: Compressor::Gzip,
+            deps: Vec::new(),
+            files: Vec::new(),
+            scripts: Vec::new(),
+        }
+    }
+
+    pub fn epoch(mut self, epoch: u32) -> PackageBuilder {
+        self.epoch = Some(epoch);
+        self
+    }
+    pub fn summary<S: Into<String>>(mut self, summary: S) -> PackageBuilder {
+        self.summary = Some(summary.into());
+        self
+    }
+    /// Set the description; otherwise it's the summary.
+    pub fn description<S: Into<String>>(mut self, description: S) -> PackageBuilder {
+        self.description = Some(description.into());
+        self
+    }
+    pub fn license<S: Into<String>>(mut self, license: S) -> PackageBuilder {
+        self.license = Some(license.into());
+        self
+    }
+    /// Set the group; otherwise it's "Unspecified", like rpmbuild uses.
+    pub fn group<S: Into<String>>(mut self, group: S) -> PackageBuilder {
+        self.group = Some(group.into());
+        self
+    }
+    pub fn url<S: Into<String>>(mut self, url: S) -> PackageBuilder {
+        self.url = Some(url.into());
+        self
+    }
+    pub fn vendor<S: Into<String>>(mut self, vendor: S) -> PackageBuilder {
+        self.vendor = Some(vendor.into());
+        self
+    }
+    pub fn packager<S: Into<String>>(mut self, packager: S) -> PackageBuilder {
+        self.packager = Some(packager.into());
+        self
+    }
+    /// Use a fixed build time, in seconds since the epoch, rather than the
+    /// current time. Together with file mtimes, this makes builds
+    /// reproducible.
+    pub fn build_time(mut self, time: u32) -> PackageBuilder {
+        self.build_time = Some(time);
+        self
+    }
+    /// Set the build host; otherwise it's "localhost".
+    pub fn build_host<S: Into<String>>(mut self, host: S) -> PackageBuilder {
+        self.build_host = host.into();
+        self
+    }
+    /// Compress the payload with something other than gzip.
+    pub fn compressor(mut self, compressor: Compressor) -> PackageBuilder {
+        self.compressor = compressor;
+        self
+    }
+
+    /// Add a dependency of any kind.
+    pub fn dependency(mut self, kind: DepKind, dep: Dependency) -> PackageBuilder {
+        self.deps.push((kind, dep));
+        self
+    }
+    pub fn requires(self, dep: Dependency) -> PackageBuilder {
+        self.dependency(DepKind::Require, dep)
+    }
+    pub fn provides(self, dep: Dependency) -> PackageBuilder {
+        self.dependency(DepKind::Provide, dep)
+    }
+    pub fn conflicts(self, dep: Dependency) -> PackageBuilder {
+        self.dependency(DepKind::Conflict, dep)
+    }
+    pub fn obsoletes(self, dep: Dependency) -> PackageBuilder {
+        self.dependency(DepKind::Obsolete, dep)
+    }
+
+    /// Add a file to the package.
+    pub fn file(mut self, file: PackageFile) -> PackageBuilder {
+        self.files.push(file);
+        self
+    }
+
+    /// Add a shell scriptlet.
+    pub fn script<S: Into<String>>(self, scriptlet: Scriptlet, script: S) -> PackageBuilder {
+        self.script_with(scriptlet, "/bin/sh", script)
+    }
+    /// Add a scriptlet run by the given interpreter, like "/usr/bin/lua" or
+    /// "/bin/bash -e".
+    pub fn script_with<S: Into<String>>(mut self, scriptlet: Scriptlet, interpreter: &str,
+                                        script: S) -> PackageBuilder {
+        let prog = interpreter.split_whitespace().map(String::from).collect();
+        self.scripts.retain(|&(s, _, _)| s != scriptlet);
+        self.scripts.push((scriptlet, prog, script.into()));
+        self
+    }
+
+    fn nevr(&self) -> String {
+        let evr = Evr::new(self.epoch, self.version.as_str(), self.release.as_str());
+        format!("{}-{}", self.name, evr)
+    }
+
+    // The package's dependencies, along with the ones rpmbuild would add
+    fn dependencies(&self) -> Vec<(DepKind, Dependency)> {
+        let mut deps = self.deps.clone();
+        let evr = Evr::new(self.epoch, self.version.as_str(), self.release.as_str());
+        deps.push((DepKind::Provide, Dependency::new(self.name.as_str(), DepFlags::EQUAL, Some(evr))));
+        for &(scriptlet, ref prog, _) in self.scripts.iter() {
+            if let Some(interp) = prog.first() {
+                let dep = Dependency::new(interp.as_str(), scriptlet.dep_flag(), None);
+                deps.push((DepKind::Require, dep));
+            }
+            // an interpreter with arguments, like "/bin/bash -e"
+            if prog.len() > 1 {
+                deps.push(rpmlib("ScriptletInterpreterArgs", "4.0.3-1"));
+            }
+        }
+        if !self.files.is_empty() {
+            deps.push(rpmlib("CompressedFileNames", "3.0.4-1"));
+            deps.push(rpmlib("FileDigests", "4.6.0-1"));
+            deps.push(rpmlib("PayloadFilesHavePrefix", "4.0-1"));
+        }
+        match self.compressor {
+            Compressor::Bzip2 => deps.push(rpmlib("PayloadIsBzip2", "3.0.5-1")),
+            Compressor::Xz => deps.push(rpmlib("PayloadIsXz", "5.2-1")),
+            Compressor::Lzma => deps.push(rpmlib("PayloadIsLzma", "4.4.2-1")),
+            Compressor::Zstd => deps.push(rpmlib("PayloadIsZstd", "5.4.18-1")),
+            Compressor::None | Compressor::Gzip => (),
+        }
+        let mut unique = Vec::with_capacity(deps.len());
+        for dep in deps {
+            if !unique.contains(&dep) {
+                unique.push(dep);
+            }
+        }
+        unique
+    }
+
+    // The main header, for the given (sorted) files and compressed payload
+    fn header(&self, files: &[&PackageFile], build_time: u32, payload: &[u8]) -> Result<Header> {
+        let summary = self.summary.as_ref().ok_or(RPMError::File(RPMFileError::MissingTag(Tag::SUMMARY)))?;
+        let license = self.license.as_ref().ok_or(RPMError::File(RPMFileError::MissingTag(Tag::LICENSE)))?;
+        let mut hdr = Header::new();
+        // header_to_bytes() fills in the real region trailer
+        set(&mut hdr, Tag::HEADERIMMUTABLE, TagValue::Binary(vec![0; 16]));
+        set(&mut hdr, Tag::HEADERI18NTABLE, string("C"));
+        set(&mut hdr, Tag::NAME, string(&self.name));
+        set(&mut hdr, Tag::VERSION, string(&self.version));
+        set(&mut hdr, Tag::RELEASE, string(&self.release));
+        if let Some(epoch) = self.epoch {
+            set(&mut hdr, Tag::EPOCH, TagValue::Int32(vec![epoch]));
+        }
+        set(&mut hdr, Tag::SUMMARY, string(summary));
+        set(&mut hdr, Tag::DESCRIPTION, string(self.description.as_ref().unwrap_or(summary)));
+        set(&mut hdr, Tag::BUILDTIME, TagValue::Int32(vec![build_time]));
+        set(&mut hdr, Tag::BUILDHOST, string(&self.build_host));
+        let size: u64 = files.iter().filter(|f| f.is_file() || f.is_symlink())
+                             .map(|f| f.data.len() as u64).sum();
+        if size > u32::MAX as u64 {
+            set(&mut hdr, Tag::LONGSIZE, TagValue::Int64(vec![size]));
+        } else {
+            set(&mut hdr, Tag::SIZE, TagValue::Int32(vec![size as u32]));
+        }
+        set(&mut hdr, Tag::LICENSE, string(license));
+        set(&mut hdr, Tag::GROUP, string(self.group.as_ref().map_or("Unspecified", |g| g.as_str())));
+        for &(tag, value) in [(Tag::URL, &self.url), (Tag::VENDOR, &self.vendor),
+                              (Tag::PACKAGER, &self.packager)].iter() {
+            if let Some(value) = value {
+                set(&mut hdr, tag, string(value));
+            }
+        }
+        set(&mut hdr, Tag::OS, string("linux"));
+        set(&mut hdr, Tag::ARCH, string(&self.arch));
+        // rpm takes packages without a SOURCERPM for source packages
+        let sourcerpm = format!("{}-{}-{}.src.rpm", self.name, self.version, self.release);
+        set(&mut hdr, Tag::SOURCERPM, string(&sourcerpm));
+        set(&mut hdr, Tag::ENCODING, string("utf-8"));
+
+        if !files.is_empty() {
+            let mut dirnames: Vec<String> = Vec::new();
+            let mut dir_index: HashMap<String, u32> = HashMap::new();
+            let mut dirindexes = Vec::new();
+            let mut basenames = Vec::new();
+            for f in files {
+                let (dir, base) = f.path.split_at(f.path.rfind('/').unwrap_or(0) + 1);
+                let index = *dir_index.entry(String::from(dir)).or_insert_with(|| {
+                    dirnames.push(String::from(dir));
+                    dirnames.len() as u32 - 1
+                });
+                dirindexes.push(index);
+                basenames.push(String::from(base));
+            }
+            let count = files.len();
+            let ints = |f: &dyn Fn(&PackageFile) -> u32| TagValue::Int32(files.iter().map(|p| f(p)).collect());
+            let strs = |f: &dyn Fn(&PackageFile) -> String| TagValue::String(files.iter().map(|p| f(p)).collect());
+            set(&mut hdr, Tag::FILESIZES, ints(&|f| f.data.len() as u32));
+            set(&mut hdr, Tag::FILEMODES, TagValue::Int16(files.iter().map(|f| f.mode as u16).collect()));
+            set(&mut hdr, Tag::FILERDEVS, TagValue::Int16(vec![0; count]));
+            set(&mut hdr, Tag::FILEMTIMES, ints(&|f| f.mtime.unwrap_or(build_time)));
+            set(&mut hdr, Tag::FILEDIGESTS, strs(&|f| {
+                if f.is_file() { hex(&Sha256::digest(&f.data)) } else { String::new() }
+            }));
+            set(&mut hdr, Tag::FILELINKTOS, strs(&|f| {
+                if f.is_symlink() { String::from_utf8_lossy(&f.data).into_owned() } else { String::new() }
+            }));
+            set(&mut hdr, Tag::FILEFLAGS, ints(&|f| f.flags.bits()));
+            set(&mut hdr, Tag::FILEUSERNAME, strs(&|f| f.user.clone()));
+            set(&mut hdr, Tag::FILEGROUPNAME, strs(&|f| f.group.clone()));
+            set(&mut hdr, Tag::FILEVERIFYFLAGS, TagValue::Int32(vec![u32::MAX; count]));
+            set(&mut hdr, Tag::FILEDEVICES, TagValue::Int32(vec![1; count]));
+            set(&mut hdr, Tag::FILEINODES, TagValue::Int32((1..=count as u32).collect()));
+            set(&mut hdr, Tag::FILELANGS, TagValue::String(vec![String::new(); count]));
+            set(&mut hdr, Tag::DIRINDEXES, TagValue::Int32(dirindexes));
+            set(&mut hdr, Tag::BASENAMES, TagValue::String(basenames));
+            set(&mut hdr, Tag::DIRNAMES, TagValue::String(dirnames));
+            set(&mut hdr, Tag::FILEDIGESTALGO, TagValue::Int32(vec![PGPHASHALGO_SHA256]));
+        }
+
+        let deps = self.dependencies();
+        for &kind in [DepKind::Require, DepKind::Provide, DepKind::Conflict, DepKind::Obsolete,
+                      DepKind::Recommend, DepKind::Suggest, DepKind::Supplement, DepKind::Enhance].iter() {
+            let deps: Vec<&Dependency> = deps.iter().filter(|&&(k, _)| k == kind).map(|(_, d)| d).collect();
+            if deps.is_empty() {
+                continue;
+            }
+            let (name_tag, flags_tag, version_tag) = kind.tags();
+            set(&mut hdr, name_tag, TagValue::String(deps.iter().map(|d| d.name.clone()).collect()));
+            set(&mut hdr, flags_tag, TagValue::Int32(deps.iter().map(|d| d.flags.bits()).collect()));
+            set(&mut hdr, version_tag, TagValue::String(deps.iter().map(|d| {
+                d.evr.as_ref().map(|evr| evr.to_string()).unwrap_or_default()
+            }).collect()));
+        }
+        for &(scriptlet, ref prog, ref script) in self.scripts.iter() {
+            let (script_tag, prog_tag) = scriptlet.tags();
+            set(&mut hdr, script_tag, string(script));
+            set(&mut hdr, prog_tag, TagValue::String(prog.clone()));
+        }
+
+        set(&mut hdr, Tag::PAYLOADFORMAT, string("cpio"));
+        set(&mut hdr, Tag::PAYLOADCOMPRESSOR, string(self.compressor.name()));
+        if self.compressor != Compressor::None {
+            set(&mut hdr, Tag::PAYLOADFLAGS, string(&self.compressor.level().to_string()));
+        }
+        set(&mut hdr, Tag::PAYLOADDIGEST, string(&hex(&Sha256::digest(payload))));
+        set(&mut hdr, Tag::PAYLOADDIGESTALGO, TagValue::Int32(vec![PGPHASHALGO_SHA256]));
+        Ok(hdr)
+    }
+
+    /// Build the package, and write it to `out`.
+    pub fn build<W: io::Write>(&self, mut out: W) -> Result<()> {
+        let build_time = match self.build_time {
+            Some(time) => time,
+            None => SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as u32).unwrap_or(0),
+        };
+        let mut files: Vec<&PackageFile> = self.files.iter().collect();
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        for f in files.iter() {
+            check_path(&f.path)?;
+            // newc can't hold files of 4GB or more
+            if f.data.len() as u64 > u32::MAX as u64 {
+                return Err(RPMError::File(RPMFileError::FileSize));
+            }
+        }
+        if let Some(pair) = files.windows(2).find(|pair| pair[0].path == pair[1].path) {
+            return Err(bad_build(format!("'{}' is in the package twice", pair[0].path)));
+        }
+
+        let mut archive = Vec::new();
+        for (i, f) in files.iter().enumerate() {
+            if !f.flags.contains(FileFlags::GHOST) {
+                let mtime = f.mtime.unwrap_or(build_time);
+                write_newc_entry(&mut archive, &f.entry(i as u32 + 1, mtime), &f.data)?;
+            }
+        }
+        write_newc_trailer(&mut archive);
+        let payload = self.compressor.compress(&archive)?;
+
+        let raw_hdr = header_to_bytes(&self.header(&files, build_time, &payload)?);
+        let mut sig = SignatureHeader::new(Header::new());
+        let size = (raw_hdr.len() + payload.len()) as u64;
+        if size > u32::MAX as u64 {
+            sig.insert(SigTag::LONGSIZE, TagValue::Int64(vec![size]));
+        } else {
+            sig.insert(SigTag::SIZE, TagValue::Int32(vec![size as u32]));
+        }
+        let mut md5 = Md5::new();
+        md5.update(&raw_hdr);
+        md5.update(&payload);
+        sig.insert(SigTag::MD5, TagValue::Binary(md5.finalize().to_vec()));
+        sig.insert(SigTag::SHA1, string(&hex(&Sha1::digest(&raw_hdr))));
+        sig.insert(SigTag::SHA256, string(&hex(&Sha256::digest(&raw_hdr))));
+        if archive.len() as u64 > u32::MAX as u64 {
+            sig.insert(SigTag::LONGARCHIVESIZE, TagValue::Int64(vec![archive.len() as u64]));
+        } else {
+            sig.insert(SigTag::PAYLOADSIZE, TagValue::Int32(vec![archive.len() as u32]));
+        }
+        sig.insert(SigTag::RESERVEDSPACE, TagValue::Binary(vec![0; RESERVED_SPACE]));
+
+        let lead = Lead {
+            major: 3,
+            minor: 0,
+            rpm_type: 0,
+            archnum: lead_archnum(&self.arch),
+            name: self.nevr(),
+            osnum: 1,
+            signature_type: 5,
+        };
+        out.write_all(&lead.to_bytes())?;
+        out.write_all(&sig.to_bytes())?;
+        out.write_all(&raw_hdr)?;
+        out.write_all(&payload)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use reader::Reader;
+    use package::Package;
+    use files::FileEntry;
+    use verify::{DigestKind, verify_package, verify_package_signatures};
+    use sign::Signer;
+    use pgp::{SecretKey, Keyring};
+
+    fn builder() -> PackageBuilder {
+        PackageBuilder::new("foo", "1.0", "1", "x86_64")
+            .epoch(2)
+            .summary("A foo")
+            .description("Foo does foo things.")
+            .license("MIT")
+            .url("https://example.com/foo")
+            .build_time(1500000000)
+            .build_host("builder.example.com")
+            .requires(Dependency::new("bar", DepFlags::GREATER | DepFlags::EQUAL, Some(Evr::parse("1.2"))))
+            .provides(Dependency::new("foo-tools", DepFlags::ANY, None))
+            .file(PackageFile::new("/usr/bin/foo", &b"#!/bin/sh\necho foo\n"[..]).mode(0o755))
+            .file(PackageFile::new("/etc/foo.conf", "answer=42\n")
+                  .flags(FileFlags::CONFIG | FileFlags::NOREPLACE).mtime(1400000000))
+            .file(PackageFile::dir("/usr/share/doc/foo"))
+            .file(PackageFile::new("/usr/share/doc/foo/README", "Read me\n").flags(FileFlags::DOC))
+            .file(PackageFile::symlink("/usr/bin/foo2", "foo"))
+            .file(PackageFile::new("/var/log/foo.log", "").flags(FileFlags::GHOST).user("foo").group("adm"))
+            .script(Scriptlet::Post, "echo installed")
+            .script_with(Scriptlet::PreUn, "/bin/bash -e", "echo bye")
+    }
+
+    fn build(builder: &PackageBuilder) -> Vec<u8> {
+        let mut rpm = Vec::new();
+        builder.build(&mut rpm).unwrap();
+        rpm
+    }
+
+    fn names(deps: Vec<Dependency>) -> Vec<String> {
+        deps.iter().map(|d| format!("{} {} {}", d.name, d.flags.operator(),
+                                     d.evr.as_ref().map(|e| e.to_string()).unwrap_or_default())
+                                   .trim().to_string()).collect()
+    }
+
+    #[test]
+    fn build_and_read() {
+        let rpm = build(&builder());
+        let mut r = Reader::from_bytes(&rpm[..]);
+        let lead = r.lead().unwrap();
+        assert_eq!(lead.name, "foo-2:1.0-1");
+        let sig = r.signature().unwrap();
+        assert_eq!(sig.size().unwrap(), Some(rpm.len() as u64 - 0x60 - sig.to_bytes().len() as u64));
+        let hdr = r.header().unwrap();
+        assert!(hdr.duplicates().is_empty());
+        let pkg = Package::new(hdr);
+        assert_eq!(pkg.nevra().unwrap(), "foo-2:1.0-1.x86_64");
+        assert_eq!(pkg.summary().unwrap(), "A foo");
+        assert_eq!(pkg.description().unwrap(), "Foo does foo things.");
+        assert_eq!(pkg.license().unwrap(), "MIT");
+        assert_eq!(pkg.group().unwrap(), Some("Unspecified"));
+        assert_eq!(pkg.buildtime().unwrap(), 1500000000);
+        assert_eq!(pkg.buildhost().unwrap(), Some("builder.example.com"));
+        assert_eq!(pkg.size().unwrap(), 19 + 10 + 8 + 3);
+        assert!(!pkg.is_source());
+        assert_eq!(names(pkg.requires().unwrap()), vec![
+            "bar >= 1.2", "/bin/sh", "/bin/bash",
+            "rpmlib(ScriptletInterpreterArgs) <= 4.0.3-1",
+            "rpmlib(CompressedFileNames) <= 3.0.4-1",
+            "rpmlib(FileDigests) <= 4.6.0-1",
+            "rpmlib(PayloadFilesHavePrefix) <= 4.0-1",
+        ]);
+        assert_eq!(pkg.requires().unwrap()[1].flags, DepFlags::SCRIPT_POST);
+        assert_eq!(names(pkg.provides().unwrap()), vec!["foo-tools", "foo = 2:1.0-1"]);
+        assert_eq!(pkg.get_string(Tag::POSTIN).unwrap(), "echo installed");
+        assert_eq!(pkg.get_strings(Tag::PREUNPROG).unwrap(), &["/bin/bash", "-e"]);
+
+        let files = pkg.files().unwrap();
+        let paths: Vec<&str> = files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(paths, vec!["/etc/foo.conf", "/usr/bin/foo", "/usr/bin/foo2",
+                               "/usr/share/doc/foo", "/usr/share/doc/foo/README", "/var/log/foo.log"]);
+        assert_eq!(files[0].flags, FileFlags::CONFIG | FileFlags::NOREPLACE);
+        assert_eq!(files[0].mtime, 1400000000);
+        assert_eq!(files[1].mode, 0o100755);
+        assert_eq!(files[1].mtime, 1500000000);
+        assert_eq!(files[1].digest.as_deref(),
+                   Some(hex(&Sha256::digest(b"#!/bin/sh\necho foo\n")).as_str()));
+        assert_eq!(files[2].linkto, Some(String::from("foo")));
+        assert!(files[3].is_dir());
+        assert_eq!(files[4].flags, FileFlags::DOC);
+        assert!(files[5].is_ghost());
+        assert_eq!((files[5].user.as_str(), files[5].group.as_str()), ("foo", "adm"));
+
+        let mut archive = r.archive(pkg.header()).unwrap();
+        let mut contents = Vec::new();
+        while let Some(mut entry) = archive.next_entry().unwrap() {
+            let mut data = String::new();
+            entry.read_to_string(&mut data).unwrap();
+            contents.push((entry.path.clone(), data));
+        }
+        assert_eq!(contents.len(), 5);
+        assert_eq!(contents[0], (String::from("/etc/foo.conf"), String::from("answer=42\n")));
+        assert_eq!(contents[2], (String::from("/usr/bin/foo2"), String::from("foo")));
+    }
+
+    #[test]
+    fn build_digests() {
+        let rpm = build(&builder());
+        assert_eq!(verify_package(&rpm[..]).unwrap(), vec![
+            DigestKind::HeaderSHA1, DigestKind::HeaderSHA256,
+            DigestKind::HeaderPayloadMD5, DigestKind::Payload,
+        ]);
+        // same inputs, same package
+        assert_eq!(build(&builder()), rpm);
+    }
+
+    #[test]
+    fn build_compressors() {
+        for &c in [Compressor::None, Compressor::Bzip2, Compressor::Xz, Compressor::Zstd].iter() {
+            let rpm = build(&builder().compressor(c));
+            assert_eq!(verify_package(&rpm[..]).unwrap().len(), 4);
+            let mut r = Reader::from_bytes(&rpm[..]);
+            r.lead().unwrap();
+            r.signature().unwrap();
+            let hdr = r.header().unwrap();
+            assert_eq!(Compressor::from_header(&hdr).unwrap(), c);
+            assert_eq!(FileEntry::from_header(&hdr).unwrap().len(), 6);
+            let mut count = 0;
+            let mut archive = r.archive(&hdr).unwrap();
+            while archive.next_entry().unwrap().is_some() {
+                count += 1;
+            }
+            assert_eq!(count, 5);
+        }
+    }
+
+    #[test]
+    fn build_dirnames() {
+        let b = PackageBuilder::new("dirs", "0", "0", "noarch").summary("Dirs").license("none")
+            .file(PackageFile::dir("/a/b"))
+            .file(PackageFile::new("/a/b/c", "c"))
+            .file(PackageFile::new("/a/d", "d"));
+        let rpm = build(&b);
+        let mut r = Reader::from_bytes(&rpm[..]);
+        r.lead().unwrap();
+        r.signature().unwrap();
+        let pkg = Package::new(r.header().unwrap());
+        // "/a/" comes up again after "/a/b/", but it's only listed once
+        assert_eq!(pkg.get_strings(Tag::DIRNAMES).unwrap(), &["/a/", "/a/b/"]);
+        let paths: Vec<String> = pkg.files().unwrap().into_iter().map(|f| f.path).collect();
+        assert_eq!(paths, vec!["/a/b", "/a/b/c", "/a/d"]);
+    }
+
+    #[test]
+    fn build_and_sign() {
+        let rpm = build(&builder());
+        let key = SecretKey::load("tests/keys/rsa.sec.asc").unwrap();
+        let mut signed = Vec::new();
+        Signer::new(&key).sign_package(io::Cursor::new(&rpm), &mut signed).unwrap();
+        // there was room for the signature
+        assert_eq!(signed.len(), rpm.len());
+        let mut keyring = Keyring::new();
+        keyring.load("tests/keys/rsa.asc").unwrap();
+        assert!(verify_package_signatures(&signed[..], &keyring).unwrap().is_ok());
+    }
+
+    #[test]
+    fn build_empty() {
+        let rpm = build(&PackageBuilder::new("empty", "0", "0", "noarch").summary("Nothing").license("none"));
+        assert_eq!(verify_package(&rpm[..]).unwrap().len(), 4);
+        let mut r = Reader::from_bytes(&rpm[..]);
+        r.lead().unwrap();
+        r.signature().unwrap();
+        let pkg = Package::new(r.header().unwrap());
+        assert!(pkg.files().unwrap().is_empty());
+        assert!(pkg.requires().unwrap().is_empty());
+    }
+
+    #[test]
+    fn build_errors() {
+        let check = |b: PackageBuilder, msg: &str| {
+            match b.build(io::sink()) {
+                Err(e) => assert_eq!(e.to_string(), msg),
+                Ok(_) => panic!("unexpected success"),
+            }
+        };
+        check(PackageBuilder::new("foo", "1", "1", "noarch").license("MIT"),
+              "RPM file error: Missing SUMMARY tag");
+        for path in ["usr/bin/foo", "/usr//bin", "/usr/bin/", "/usr/../etc", "/"].iter() {
+            check(builder().file(PackageFile::new(*path, "")),
+                  &format!("RPM file error: Can't build package: bad file path '{}'", path));
+        }
+        check(builder().file(PackageFile::dir("/usr/bin/foo")),
+              "RPM file error: Can't build package: '/usr/bin/foo' is in the package twice");
+    }
+
+    #[test]
+    fn file_from_path() {
+        let dir = ::tempfile::tempdir().unwrap();
+        let path = dir.path().join("hello");
+        fs::write(&path, "hello\n").unwrap();
+        fs::set_permissions(&path, ::std::os::unix::fs::PermissionsExt::from_mode(0o640)).unwrap();
+        ::std::os::unix::fs::symlink("hello", dir.path().join("link")).unwrap();
+        let file = PackageFile::from_path(&path, "/usr/share/hello").unwrap();
+        assert_eq!((file.mode, &file.data[..]), (S_IFREG | 0o640, &b"hello\n"[..]));
+        assert!(file.mtime.is_some());
+        let link = PackageFile::from_path(dir.path().join("link"), "/usr/share/link").unwrap();
+        assert!(link.is_symlink());
+        assert_eq!(link.data, b"hello");
+        let dir = PackageFile::from_path(dir.path(), "/usr/share/dir").unwrap().mode(0o700);
+        assert_eq!(dir.mode, S_IFDIR | 0o700);
+    }
+}
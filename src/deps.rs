@@ -0,0 +1,264 @@
+/* deps.rs - package dependencies (requires, provides, etc.)
+ *
+ * Copyright (c) 2017, Red Hat, Inc.
+ *
+ * This library is free software; you can redistribute it and/or modify it
+ * under the terms and conditions of the GNU Lesser General Public License
+ * as published by the Free Software Foundation; either version 2.1 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU Lesser General Public License for
+ * more details.
+ *
+ * Authors:
+ *   Will Woods <wwoods@redhat.com>
+ */
+use std::fmt;
+use std::cmp::Ordering;
+
+use header::Header;
+use files::{strings, ints, per_item};
+use version::{Evr, rpmvercmp};
+use tag::Tag;
+use Result;
+
+bitflags! {
+    /// Dependency flags (RPMSENSE_* in rpm/lib/rpmds.h). The low bits say
+    /// how the version is compared; the rest say where the dependency came
+    /// from or when it's needed.
+    pub struct DepFlags: u32 {
+        const ANY           = 0;
+        const LESS          = 1 << 1;
+        const GREATER       = 1 << 2;
+        const EQUAL         = 1 << 3;
+        const POSTTRANS     = 1 << 5;   // %posttrans dependency
+        const PREREQ        = 1 << 6;   // legacy PreReq
+        const PRETRANS      = 1 << 7;   // %pretrans dependency
+        const INTERP        = 1 << 8;   // interpreter used by a scriptlet
+        const SCRIPT_PRE    = 1 << 9;   // %pre dependency
+        const SCRIPT_POST   = 1 << 10;  // %post dependency
+        const SCRIPT_PREUN  = 1 << 11;  // %preun dependency
+        const SCRIPT_POSTUN = 1 << 12;  // %postun dependency
+        const SCRIPT_VERIFY = 1 << 13;  // %verify dependency
+        const FIND_REQUIRES = 1 << 14;  // generated by find-requires
+        const FIND_PROVIDES = 1 << 15;  // generated by find-provides
+        const TRIGGERIN     = 1 << 16;  // %triggerin
+        const TRIGGERUN     = 1 << 17;  // %triggerun
+        const TRIGGERPOSTUN = 1 << 18;  // %triggerpostun
+        const MISSINGOK     = 1 << 19;  // suggests/recommends (old style)
+        const PREUNTRANS    = 1 << 20;  // %preuntrans dependency
+        const POSTUNTRANS   = 1 << 21;  // %postuntrans dependency
+        const RPMLIB        = 1 << 24;  // rpmlib(feature) dependency
+        const TRIGGERPREIN  = 1 << 25;  // %triggerprein
+        const KEYRING       = 1 << 26;
+        const CONFIG        = 1 << 28;  // config(foo) dependency
+        const META          = 1 << 29;  // meta dependency, not needed for ordering
+
+        const SENSEMASK = Self::LESS.bits | Self::GREATER.bits | Self::EQUAL.bits;
+    }
+}
+
+impl DepFlags {
+    /// The comparison operator, like ">=", or "" if there isn't one.
+    pub fn operator(&self) -> &'static str {
+        let sense = *self & DepFlags::SENSEMASK;
+        if sense == DepFlags::LESS { "<" }
+        else if sense == DepFlags::LESS | DepFlags::EQUAL { "<=" }
+        else if sense == DepFlags::EQUAL { "=" }
+        else if sense == DepFlags::GREATER | DepFlags::EQUAL { ">=" }
+        else if sense == DepFlags::GREATER { ">" }
+        else if sense == DepFlags::SENSEMASK { "<=>" }
+        else { "" }
+    }
+}
+
+/// The different kinds of dependencies a package can have.
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash)]
+pub enum DepKind {
+    Require,
+    Provide,
+    Conflict,
+    Obsolete,
+    Recommend,
+    Suggest,
+    Supplement,
+    Enhance,
+}
+
+impl DepKind {
+    /// The NAME, FLAGS and VERSION tags that hold this kind of dependency.
+    pub fn tags(&self) -> (Tag, Tag, Tag) {
+        match *self {
+            DepKind::Require => (Tag::REQUIRENAME, Tag::REQUIREFLAGS, Tag::REQUIREVERSION),
+            DepKind::Provide => (Tag::PROVIDENAME, Tag::PROVIDEFLAGS, Tag::PROVIDEVERSION),
+            DepKind::Conflict => (Tag::CONFLICTNAME, Tag::CONFLICTFLAGS, Tag::CONFLICTVERSION),
+            DepKind::Obsolete => (Tag::OBSOLETENAME, Tag::OBSOLETEFLAGS, Tag::OBSOLETEVERSION),
+            DepKind::Recommend => (Tag::RECOMMENDNAME, Tag::RECOMMENDFLAGS, Tag::RECOMMENDVERSION),
+            DepKind::Suggest => (Tag::SUGGESTNAME, Tag::SUGGESTFLAGS, Tag::SUGGESTVERSION),
+            DepKind::Supplement => (Tag::SUPPLEMENTNAME, Tag::SUPPLEMENTFLAGS, Tag::SUPPLEMENTVERSION),
+            DepKind::Enhance => (Tag::ENHANCENAME, Tag::ENHANCEFLAGS, Tag::ENHANCEVERSION),
+        }
+    }
+}
+
+/// A single dependency, like "foo >= 1.2-3".
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct Dependency {
+    pub name: String,
+    pub flags: DepFlags,
+    /// The version this depends on, if the dependency is versioned
+    pub evr: Option<Evr>,
+}
+
+impl Dependency {
+    pub fn new<S: Into<String>>(name: S, flags: DepFlags, evr: Option<Evr>) -> Dependency {
+        Dependency { name: name.into(), flags, evr }
+    }
+
+    /// Get the given kind of dependencies from a main header, in header
+    /// order. Packages without any of them just get an empty list.
+    pub fn from_header(hdr: &Header, kind: DepKind) -> Result<Vec<Dependency>> {
+        let (name_tag, flags_tag, version_tag) = kind.tags();
+        let names = strings(hdr, name_tag)?;
+        let count = names.len();
+        let flags = per_item(ints(hdr, flags_tag)?, count, 0)?;
+        let versions = per_item(strings(hdr, version_tag)?.to_vec(), count, String::new())?;
+        Ok(names.iter().zip(flags).zip(versions).map(|((name, flags), version)| {
+            let evr = if version.is_empty() { None } else { Some(Evr::parse(&version)) };
+            Dependency::new(name.as_str(), DepFlags::from_bits_truncate(flags as u32), evr)
+        }).collect())
+    }
+
+    /// Whether this is a rich (boolean) dependency, like "(foo or bar)";
+    /// see `RichDep`.
+    pub fn is_rich(&self) -> bool {
+        self.name.starts_with('(')
+    }
+
+    /// Whether `other` satisfies this dependency (or vice versa): the names
+    /// have to be the same and the version ranges have to overlap, like
+    /// rpmdsCompare(). An unversioned dependency matches any version.
+    pub fn matches(&self, other: &Dependency) -> bool {
+        if self.name != other.name {
+            return false;
+        }
+        let (a, b) = (self.flags & DepFlags::SENSEMASK, other.flags & DepFlags::SENSEMASK);
+        let (aevr, bevr) = match (&self.evr, &other.evr) {
+            (Some(aevr), Some(bevr)) if !a.is_empty() && !b.is_empty() => (aevr, bevr),
+            _ => return true,
+        };
+        match compare_dep_evr(aevr, bevr) {
+            Ordering::Less => a.contains(DepFlags::GREATER) || b.contains(DepFlags::LESS),
+            Ordering::Greater => a.contains(DepFlags::LESS) || b.contains(DepFlags::GREATER),
+            Ordering::Equal => !(a & b).is_empty(),
+        }
+    }
+}
+
+// Dependencies compare versions a little differently from packages: the
+// release is only compared if both sides have one, so "foo >= 1.0" is
+// satisfied by "foo = 1.0-3".
+fn compare_dep_evr(a: &Evr, b: &Evr) -> Ordering {
+    let order = a.epoch.unwrap_or(0).cmp(&b.epoch.unwrap_or(0))
+        .then_with(|| rpmvercmp(&a.version, &b.version));
+    if a.release.is_empty() || b.release.is_empty() {
+        order
+    } else {
+        order.then_with(|| rpmvercmp(&a.release, &b.release))
+    }
+}
+
+impl fmt::Display for Dependency {
+    // This matches how rpm -q --requires etc. show them.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        let op = self.flags.operator();
+        if !op.is_empty() {
+            write!(f, " {}", op)?;
+        }
+        if let Some(ref evr) = self.evr {
+            write!(f, " {}", evr)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reader::Reader;
+    use tag::{TagID, TagValue};
+    static BINRPM1: &[u8] = include_bytes!("../tests/rpms/binary.x86_64.rpm");
+
+    fn header() -> Header {
+        let mut r = Reader::from_bytes(BINRPM1);
+        let _ = r.lead();
+        let _ = r.header().unwrap();
+        r.header().unwrap()
+    }
+
+    #[test]
+    fn rpm_deps() {
+        let hdr = header();
+        let provides = Dependency::from_header(&hdr, DepKind::Provide).unwrap();
+        let provides: Vec<String> = provides.iter().map(|d| d.to_string()).collect();
+        assert_eq!(provides, vec!["hardlink = 1:1.0-23.fc24", "hardlink(x86-64) = 1:1.0-23.fc24"]);
+
+        let requires = Dependency::from_header(&hdr, DepKind::Require).unwrap();
+        let libc = requires.iter().find(|d| d.name == "libc.so.6()(64bit)").unwrap();
+        assert_eq!(libc.flags, DepFlags::FIND_REQUIRES);
+        assert_eq!(libc.evr, None);
+        let rpmlib = requires.iter().find(|d| d.name == "rpmlib(CompressedFileNames)").unwrap();
+        assert_eq!(rpmlib.flags, DepFlags::RPMLIB | DepFlags::LESS | DepFlags::EQUAL);
+        assert_eq!(rpmlib.to_string(), "rpmlib(CompressedFileNames) <= 3.0.4-1");
+
+        assert!(Dependency::from_header(&hdr, DepKind::Conflict).unwrap().is_empty());
+    }
+
+    #[test]
+    fn dep_display() {
+        let dep = |flags, evr: &str| Dependency::new("foo", flags, Some(Evr::parse(evr))).to_string();
+        assert_eq!(dep(DepFlags::GREATER | DepFlags::EQUAL, "1.2-3"), "foo >= 1.2-3");
+        assert_eq!(dep(DepFlags::LESS, "2:1.2"), "foo < 2:1.2");
+        assert_eq!(dep(DepFlags::EQUAL | DepFlags::SCRIPT_PRE, "1"), "foo = 1");
+        assert_eq!(Dependency::new("/bin/sh", DepFlags::INTERP, None).to_string(), "/bin/sh");
+    }
+
+    #[test]
+    fn dep_matches() {
+        let dep = |s: &str| {
+            let parts: Vec<&str> = s.split(' ').collect();
+            let flags = match parts.get(1).cloned() {
+                Some("<") => DepFlags::LESS,
+                Some("<=") => DepFlags::LESS | DepFlags::EQUAL,
+                Some("=") => DepFlags::EQUAL,
+                Some(">=") => DepFlags::GREATER | DepFlags::EQUAL,
+                Some(">") => DepFlags::GREATER,
+                _ => DepFlags::ANY,
+            };
+            Dependency::new(parts[0], flags, parts.get(2).map(|v| Evr::parse(v)))
+        };
+        let provide = dep("foo = 1:1.0-23.fc24");
+        for req in &["foo", "foo >= 1.0", "foo = 1:1.0", "foo > 0:0.9", "foo < 1:1.0-24", "foo <= 1:1.0-23.fc24"] {
+            assert!(dep(req).matches(&provide), "{} should match {}", req, provide);
+        }
+        for req in &["bar", "foo > 1:1.0", "foo < 1.0", "foo = 1.0-23.fc24", "foo >= 2:0"] {
+            assert!(!dep(req).matches(&provide), "{} shouldn't match {}", req, provide);
+        }
+        // an unversioned provide satisfies everything
+        assert!(dep("foo >= 2.0").matches(&dep("foo")));
+        // two open ranges in the same direction always overlap
+        assert!(dep("foo > 2.0").matches(&dep("foo >= 1.0")));
+    }
+
+    #[test]
+    fn dep_missing_flags() {
+        // very old packages may not have the flags and versions at all
+        let mut hdr = Header::new();
+        hdr.insert(Tag::REQUIRENAME as TagID, TagValue::String(vec![String::from("bar")]));
+        let deps = Dependency::from_header(&hdr, DepKind::Require).unwrap();
+        assert_eq!(deps, vec![Dependency::new("bar", DepFlags::ANY, None)]);
+    }
+}
@@ -21,10 +21,15 @@ use std::fmt;
 use std::error;
 use nom;
 
+use tag::{Tag, SigTag, TagID, TagType};
+use verify::DigestKind;
+
 #[derive(Debug)]
 pub enum RPMError {
     Io(io::Error),
     File(RPMFileError),
+    Parse(ParseError),
+    OutOfOrder,
     Internal,
 }
 
@@ -35,6 +40,8 @@ impl fmt::Display for RPMError {
         match *self {
             RPMError::Io(ref err)   => write!(f, "IO error: {}", err),
             RPMError::File(ref err) => write!(f, "RPM file error: {}", err),
+            RPMError::Parse(ref err) => write!(f, "RPM parse error: {}", err),
+            RPMError::OutOfOrder    => write!(f, "RPM sections read out of order"),
             RPMError::Internal      => write!(f, "Internal error"),
         }
     }
@@ -45,6 +52,8 @@ impl error::Error for RPMError {
         match *self {
             RPMError::Io(ref err)   => err.description(),
             RPMError::File(ref err) => err.description(),
+            RPMError::Parse(_)      => "parse error",
+            RPMError::OutOfOrder    => "sections read out of order",
             RPMError::Internal      => "internal error",
         }
     }
@@ -53,11 +62,25 @@ impl error::Error for RPMError {
         match *self {
             RPMError::Io(ref err)   => Some(err),
             RPMError::File(ref err) => Some(err),
+            RPMError::Parse(ref err) => Some(err),
+            RPMError::OutOfOrder    => None,
             RPMError::Internal      => None,
         }
     }
 }
 
+impl RPMError {
+    /// The RPMFileError behind this error, if there is one - whether or not
+    /// it came with a position (see `ParseError`).
+    pub fn file_error(&self) -> Option<&RPMFileError> {
+        match *self {
+            RPMError::File(ref err) => Some(err),
+            RPMError::Parse(ref err) => Some(&err.kind),
+            _ => None,
+        }
+    }
+}
+
 impl From<io::Error> for RPMError {
     fn from(err: io::Error) -> RPMError {
         RPMError::Io(err)
@@ -84,9 +107,22 @@ pub enum RPMFileError {
     HeaderSize,
     UnknownFiletype,
     MissingFile,
-    DigestMismatch,
+    DigestMismatch(DigestKind),
+    NoDigests,
     UnmappedFile,
     FileSize,
+    UnknownCompressor(String),
+    UnsafePath(String),
+    MissingTag(Tag),
+    WrongTagType(Tag),
+    WrongSigTagType(SigTag),
+    UnknownDigestAlgo(u32),
+    BadRichDep(String, usize, &'static str),
+    BadPGP(&'static str),
+    BadBuild(String),
+    BadRegion(&'static str),
+    BadValue(&'static str),
+    Truncated,
     Internal,
     // The rest of the stuff in rpmfilesErrorCodes mostly maps io::error,
     // so we don't need it here.
@@ -100,9 +136,26 @@ impl fmt::Display for RPMFileError {
             RPMFileError::HeaderSize => write!(f, "Header size too big"),
             RPMFileError::UnknownFiletype => write!(f, "Unknown file type"),
             RPMFileError::MissingFile => write!(f, "Missing file(s)"),
-            RPMFileError::DigestMismatch => write!(f, "Digest mismatch"),
+            RPMFileError::DigestMismatch(kind) => write!(f, "Digest mismatch: {}", kind),
+            RPMFileError::NoDigests => write!(f, "No digests or signatures to check"),
             RPMFileError::UnmappedFile => write!(f, "Archive file not in header"),
             RPMFileError::FileSize => write!(f, "File too large for archive"),
+            RPMFileError::UnknownCompressor(ref name) =>
+                write!(f, "Unknown payload compressor '{}'", name),
+            RPMFileError::UnsafePath(ref path) =>
+                write!(f, "Refusing to write outside of root: '{}'", path),
+            RPMFileError::MissingTag(tag) => write!(f, "Missing {:?} tag", tag),
+            RPMFileError::WrongTagType(tag) => write!(f, "Wrong value type for {:?} tag", tag),
+            RPMFileError::WrongSigTagType(tag) =>
+                write!(f, "Wrong value type for {:?} signature tag", tag),
+            RPMFileError::UnknownDigestAlgo(algo) => write!(f, "Unknown digest algorithm {}", algo),
+            RPMFileError::BadRichDep(ref dep, pos, msg) =>
+                write!(f, "Bad rich dependency '{}' at offset {}: {}", dep, pos, msg),
+            RPMFileError::BadPGP(msg) => write!(f, "Bad OpenPGP data: {}", msg),
+            RPMFileError::BadBuild(ref msg) => write!(f, "Can't build package: {}", msg),
+            RPMFileError::BadRegion(msg) => write!(f, "Bad header region: {}", msg),
+            RPMFileError::BadValue(msg) => write!(f, "Bad tag value: {}", msg),
+            RPMFileError::Truncated => write!(f, "Truncated data"),
             RPMFileError::Internal => write!(f, "Internal error"),
         }
     }
@@ -116,10 +169,123 @@ impl error::Error for RPMFileError {
             RPMFileError::HeaderSize => "header size",
             RPMFileError::UnknownFiletype => "unknown filetype",
             RPMFileError::MissingFile => "missing file",
-            RPMFileError::DigestMismatch => "digest mismatch",
+            RPMFileError::DigestMismatch(_) => "digest mismatch",
+            RPMFileError::NoDigests => "no digests",
             RPMFileError::UnmappedFile => "unmapped file",
             RPMFileError::FileSize => "file size",
+            RPMFileError::UnknownCompressor(_) => "unknown payload compressor",
+            RPMFileError::UnsafePath(_) => "unsafe path",
+            RPMFileError::MissingTag(_) => "missing tag",
+            RPMFileError::WrongTagType(_) => "wrong tag type",
+            RPMFileError::WrongSigTagType(_) => "wrong signature tag type",
+            RPMFileError::UnknownDigestAlgo(_) => "unknown digest algorithm",
+            RPMFileError::BadRichDep(..) => "bad rich dependency",
+            RPMFileError::BadPGP(_) => "bad OpenPGP data",
+            RPMFileError::BadBuild(_) => "can't build package",
+            RPMFileError::BadRegion(_) => "bad header region",
+            RPMFileError::BadValue(_) => "bad tag value",
+            RPMFileError::Truncated => "truncated data",
             RPMFileError::Internal => "internal error",
         }
     }
 }
+
+/// The parts of an RPM file, for saying where a `ParseError` happened.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum Section {
+    Lead,
+    Signature,
+    Header,
+    /// The (decompressed) cpio archive
+    Payload,
+}
+
+impl fmt::Display for Section {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Section::Lead      => "lead",
+            Section::Signature => "signature header",
+            Section::Header    => "header",
+            Section::Payload   => "payload",
+        })
+    }
+}
+
+/// An RPMFileError, plus where it happened: which section, how far into
+/// the file, and (if it's about a tag) which tag. Offsets count from the
+/// start of the package, except in the payload, where they count from the
+/// start of the decompressed archive.
+#[derive(Debug)]
+pub struct ParseError {
+    pub kind: RPMFileError,
+    pub section: Section,
+    pub offset: u64,
+    /// The tag whose index entry or value is bad
+    pub tag: Option<TagID>,
+    /// The type the tag's index entry gives, which might not be a real type
+    pub tagtype: Option<u32>,
+    /// The size (or count) that was expected, for things that didn't fit:
+    /// how many bytes were needed, or the most that's allowed
+    pub expected: Option<u64>,
+    /// ...and what there actually was
+    pub actual: Option<u64>,
+}
+
+impl ParseError {
+    pub fn new(kind: RPMFileError, section: Section, offset: u64) -> ParseError {
+        ParseError {
+            kind,
+            section,
+            offset,
+            tag: None,
+            tagtype: None,
+            expected: None,
+            actual: None,
+        }
+    }
+
+    /// Say which tag (and type) the error is about.
+    pub fn tag(mut self, tag: TagID, tagtype: u32) -> ParseError {
+        self.tag = Some(tag);
+        self.tagtype = Some(tagtype);
+        self
+    }
+
+    /// Say what size was expected, and what it actually was.
+    pub fn sizes(mut self, expected: u64, actual: u64) -> ParseError {
+        self.expected = Some(expected);
+        self.actual = Some(actual);
+        self
+    }
+
+    // Move an error found in part of a file to where that part is.
+    pub(crate) fn relative_to(mut self, section: Section, base: u64) -> ParseError {
+        self.section = section;
+        self.offset += base;
+        self
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} in {} at offset {:#x}", self.kind, self.section, self.offset)?;
+        if let Some(tag) = self.tag {
+            write!(f, ", tag {}", tag)?;
+        }
+        match self.tagtype.map(|t| (t, TagType::from_u32(t))) {
+            Some((_, Some(ttype))) => write!(f, " ({:?})", ttype)?,
+            Some((t, None)) => write!(f, " (type {})", t)?,
+            None => (),
+        }
+        if let (Some(expected), Some(actual)) = (self.expected, self.actual) {
+            write!(f, ": expected {}, found {}", expected, actual)?;
+        }
+        Ok(())
+    }
+}
+
+impl error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(&self.kind)
+    }
+}
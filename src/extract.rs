@@ -0,0 +1,287 @@
+/* extract.rs - unpack the files in an RPM payload into a directory
+ *
+ * Copyright (c) 2017, Red Hat, Inc.
+ *
+ * This library is free software; you can redistribute it and/or modify it
+ * under the terms and conditions of the GNU Lesser General Public License
+ * as published by the Free Software Foundation; either version 2.1 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU Lesser General Public License for
+ * more details.
+ *
+ * Authors:
+ *   Will Woods <wwoods@redhat.com>
+ */
+use std::io::prelude::*;
+use std::io;
+use std::fs;
+use std::ffi::CString;
+use std::collections::HashMap;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{symlink, PermissionsExt};
+use std::path::{Component, Path, PathBuf};
+
+use filetime::{FileTime, set_symlink_file_times};
+use libc;
+
+use archive::Archive;
+use files::{FileEntry, lookup_uid, lookup_gid, S_IFMT, S_IFDIR, S_IFREG, S_IFLNK, S_IFCHR, S_IFBLK, S_IFIFO};
+use header::Header;
+use error::{RPMError, RPMFileError};
+use Result;
+
+fn unsafe_path(path: &str) -> RPMError {
+    RPMError::File(RPMFileError::UnsafePath(String::from(path)))
+}
+
+// Find where the given path from the header goes under root. Anything that
+// would land outside of root - "..", or a symlink in one of the parent
+// directories - is refused.
+fn target_path(root: &Path, path: &str) -> Result<PathBuf> {
+    let mut target = root.to_path_buf();
+    for component in Path::new(path).components() {
+        // don't follow symlinks that are already in the way
+        if target != root && fs::symlink_metadata(&target).map(|m| m.file_type().is_symlink()).unwrap_or(false) {
+            return Err(unsafe_path(path));
+        }
+        match component {
+            Component::RootDir | Component::CurDir => (),
+            Component::Normal(name) => target.push(name),
+            Component::ParentDir | Component::Prefix(_) => return Err(unsafe_path(path)),
+        }
+    }
+    if target == root {
+        return Err(unsafe_path(path));
+    }
+    Ok(target)
+}
+
+// Make the parent directories of path and get rid of anything that's
+// already at path, so we can put a new file there.
+fn make_room(path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    match fs::symlink_metadata(path) {
+        Ok(ref m) if m.is_dir() => fs::remove_dir(path)?,
+        Ok(_) => fs::remove_file(path)?,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+        Err(e) => return Err(RPMError::Io(e)),
+    }
+    Ok(())
+}
+
+fn cpath(path: &Path) -> Result<CString> {
+    CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| unsafe_path(&path.to_string_lossy()))
+}
+
+// Set ownership (if we're allowed to), mode, and mtime from the header.
+fn set_metadata(path: &Path, file: &FileEntry, privileged: bool) -> Result<()> {
+    // chown before chmod, since chown clears the setuid/setgid bits
+    if privileged {
+        let p = cpath(path)?;
+        let (uid, gid) = (lookup_uid(&file.user), lookup_gid(&file.group));
+        if unsafe { libc::lchown(p.as_ptr(), uid, gid) } != 0 {
+            return Err(RPMError::Io(io::Error::last_os_error()));
+        }
+    }
+    if !file.is_symlink() {
+        fs::set_permissions(path, fs::Permissions::from_mode(file.mode & 0o7777))?;
+    }
+    let mtime = FileTime::from_unix_time(file.mtime as i64, 0);
+    set_symlink_file_times(path, mtime, mtime)?;
+    Ok(())
+}
+
+/// Extract the files in a package's payload archive into the directory
+/// `root`, which is created if needed. `hdr` is the package's main header.
+///
+/// Directories, regular files, symlinks and hardlinks are recreated with
+/// the modes and mtimes from the header. If we're running as root, files
+/// are also given the owners and groups listed in the header.
+/// Anything that would be written outside of `root` is refused with
+/// `RPMFileError::UnsafePath`. Scriptlets are never run.
+pub fn extract<R, P>(archive: &mut Archive<R>, hdr: &Header, root: P) -> Result<()>
+        where R: io::Read, P: AsRef<Path> {
+    let root = root.as_ref();
+    let files = FileEntry::from_header(hdr)?;
+    let privileged = unsafe { libc::geteuid() } == 0;
+    fs::create_dir_all(root)?;
+
+    // hardlinks that are waiting for the link that has the data. We keep
+    // the paths from the header and look them up again when we make the
+    // links, since the entries in between can change what's under root.
+    let mut links: HashMap<(u32, u32), Vec<String>> = HashMap::new();
+    // directory metadata gets set last, in case it makes them read-only
+    let mut dirs = Vec::new();
+
+    while let Some(mut entry) = archive.next_entry()? {
+        let file = &files[entry.index];
+        let path = target_path(root, &entry.path)?;
+        match entry.mode & S_IFMT {
+            S_IFDIR => {
+                if !fs::symlink_metadata(&path).map(|m| m.is_dir()).unwrap_or(false) {
+                    make_room(&path)?;
+                    fs::create_dir(&path)?;
+                }
+                dirs.push((path, file));
+                continue;
+            },
+            S_IFREG if !file.content => {
+                links.entry((file.device, file.inode)).or_default().push(entry.path.clone());
+                continue;
+            },
+            S_IFREG => {
+                make_room(&path)?;
+                let mut f = fs::File::create(&path)?;
+                io::copy(&mut entry, &mut f)?;
+                for link in links.remove(&(file.device, file.inode)).unwrap_or_default() {
+                    let link = target_path(root, &link)?;
+                    make_room(&link)?;
+                    fs::hard_link(&path, &link)?;
+                }
+            },
+            S_IFLNK => {
+                let mut linkto = String::new();
+                entry.read_to_string(&mut linkto)?;
+                make_room(&path)?;
+                symlink(&linkto, &path)?;
+            },
+            S_IFCHR | S_IFBLK | S_IFIFO => {
+                make_room(&path)?;
+                let p = cpath(&path)?;
+                let rdev = libc::makedev(file.rdev_major(), file.rdev_minor());
+                if unsafe { libc::mknod(p.as_ptr(), file.mode as libc::mode_t, rdev) } != 0 {
+                    return Err(RPMError::Io(io::Error::last_os_error()));
+                }
+            },
+            _ => return Err(RPMError::File(RPMFileError::UnknownFiletype)),
+        }
+        set_metadata(&path, file, privileged)?;
+    }
+
+    for &(ref path, file) in dirs.iter().rev() {
+        set_metadata(path, file, privileged)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::MetadataExt;
+    use tempfile;
+    use reader::Reader;
+    use tag::{Tag, TagID, TagValue};
+    static BINRPM1: &[u8] = include_bytes!("../tests/rpms/binary.x86_64.rpm");
+
+    #[test]
+    fn extract_rpm() {
+        let root = tempfile::tempdir().unwrap();
+        let mut r = Reader::from_bytes(BINRPM1);
+        let _ = r.lead();
+        let _ = r.header().unwrap();
+        let hdr = r.header().unwrap();
+        extract(&mut r.archive(&hdr).unwrap(), &hdr, root.path()).unwrap();
+
+        let bin = fs::metadata(root.path().join("usr/sbin/hardlink")).unwrap();
+        assert_eq!(bin.len(), 15720);
+        assert_eq!(bin.mode() & 0o7777, 0o755);
+        assert_eq!(bin.mtime(), 1454553867);
+        let dir = fs::metadata(root.path().join("usr/share/licenses/hardlink")).unwrap();
+        assert!(dir.is_dir());
+        assert_eq!(dir.mtime(), 1454553868);
+        let doc = fs::metadata(root.path().join("usr/share/man/man1/hardlink.1.gz")).unwrap();
+        assert_eq!(doc.len(), 889);
+        assert_eq!(doc.mode() & 0o7777, 0o644);
+    }
+
+    #[test]
+    fn extract_hardlinks() {
+        let mut hdr = Header::new();
+        let strings = |v: &[&str]| TagValue::String(v.iter().map(|s| String::from(*s)).collect());
+        hdr.insert(Tag::BASENAMES as TagID, strings(&["a", "b", "c"]));
+        hdr.insert(Tag::DIRNAMES as TagID, strings(&["/"]));
+        hdr.insert(Tag::DIRINDEXES as TagID, TagValue::Int32(vec![0, 0, 0]));
+        hdr.insert(Tag::FILEMODES as TagID, TagValue::Int16(vec![0o100644, 0o100644, 0o120777]));
+        hdr.insert(Tag::FILESIZES as TagID, TagValue::Int32(vec![4, 4, 1]));
+        hdr.insert(Tag::FILEINODES as TagID, TagValue::Int32(vec![1, 1, 2]));
+        // stripped archive: "a" has no data, "b" has the data, "c" -> "a"
+        let mut payload = Vec::new();
+        payload.extend_from_slice(b"07070X00000000\x00\x0007070X00000001\x00\x00data");
+        payload.extend_from_slice(b"07070X00000002\0\0a\0\0\0");
+        payload.extend_from_slice(format!("070701{:088x}{:08x}{:08x}TRAILER!!!\0", 0, 11, 0).as_bytes());
+
+        let root = tempfile::tempdir().unwrap();
+        extract(&mut Archive::new(&payload[..], &hdr).unwrap(), &hdr, root.path()).unwrap();
+        let a = fs::metadata(root.path().join("a")).unwrap();
+        let b = fs::metadata(root.path().join("b")).unwrap();
+        assert_eq!(a.ino(), b.ino());
+        assert_eq!(a.nlink(), 2);
+        assert_eq!(fs::read(root.path().join("a")).unwrap(), b"data");
+        assert_eq!(fs::read_link(root.path().join("c")).unwrap(), Path::new("a"));
+    }
+
+    #[test]
+    fn extract_hardlink_through_symlink() {
+        let outside = tempfile::tempdir().unwrap();
+        let outside_path = outside.path().to_str().unwrap();
+        let mut hdr = Header::new();
+        let strings = |v: &[&str]| TagValue::String(v.iter().map(|s| String::from(*s)).collect());
+        hdr.insert(Tag::BASENAMES as TagID, strings(&["x", "d", "y"]));
+        hdr.insert(Tag::DIRNAMES as TagID, strings(&["/d/", "/"]));
+        hdr.insert(Tag::DIRINDEXES as TagID, TagValue::Int32(vec![0, 1, 1]));
+        hdr.insert(Tag::FILEMODES as TagID, TagValue::Int16(vec![0o100644, 0o120777, 0o100644]));
+        hdr.insert(Tag::FILESIZES as TagID, TagValue::Int32(vec![4, outside_path.len() as u32, 4]));
+        hdr.insert(Tag::FILEINODES as TagID, TagValue::Int32(vec![1, 2, 1]));
+        // "/d/x" gets queued as a hardlink, then "/d" turns into a symlink
+        // that points outside of root before "/y" brings the data
+        let mut payload = Vec::new();
+        payload.extend_from_slice(b"07070X00000000\x00\x0007070X00000001\x00\x00");
+        payload.extend_from_slice(outside_path.as_bytes());
+        payload.resize((payload.len() + 3) / 4 * 4, 0);
+        payload.extend_from_slice(b"07070X00000002\x00\x00data");
+        payload.extend_from_slice(format!("070701{:088x}{:08x}{:08x}TRAILER!!!\0", 0, 11, 0).as_bytes());
+
+        let root = tempfile::tempdir().unwrap();
+        match extract(&mut Archive::new(&payload[..], &hdr).unwrap(), &hdr, root.path()) {
+            Err(RPMError::File(RPMFileError::UnsafePath(ref p))) => assert_eq!(p, "/d/x"),
+            r => panic!("unexpected result: {:?}", r),
+        }
+        assert!(!outside.path().join("x").exists());
+    }
+
+    #[test]
+    fn target_path_ok() {
+        let root = Path::new("/tmp/root");
+        assert_eq!(target_path(root, "/usr/bin/ls").unwrap(), root.join("usr/bin/ls"));
+        assert_eq!(target_path(root, "./usr/./bin").unwrap(), root.join("usr/bin"));
+    }
+
+    #[test]
+    fn target_path_traversal() {
+        let root = Path::new("/tmp/root");
+        for path in &["/usr/../../etc/passwd", "../etc/passwd", "/", ""] {
+            match target_path(root, path) {
+                Err(RPMError::File(RPMFileError::UnsafePath(ref p))) => assert_eq!(p, path),
+                r => panic!("unexpected result for {:?}: {:?}", path, r),
+            }
+        }
+    }
+
+    #[test]
+    fn target_path_through_symlink() {
+        let root = tempfile::tempdir().unwrap();
+        symlink("/etc", root.path().join("etc")).unwrap();
+        match target_path(root.path(), "/etc/passwd") {
+            Err(RPMError::File(RPMFileError::UnsafePath(_))) => (),
+            r => panic!("unexpected result: {:?}", r),
+        }
+        // replacing the symlink itself is fine, though
+        assert_eq!(target_path(root.path(), "/etc").unwrap(), root.path().join("etc"));
+    }
+}
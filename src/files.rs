@@ -0,0 +1,365 @@
+/* files.rs - the list of files in an RPM header
+ *
+ * Copyright (c) 2017, Red Hat, Inc.
+ *
+ * This library is free software; you can redistribute it and/or modify it
+ * under the terms and conditions of the GNU Lesser General Public License
+ * as published by the Free Software Foundation; either version 2.1 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU Lesser General Public License for
+ * more details.
+ *
+ * Authors:
+ *   Will Woods <wwoods@redhat.com>
+ */
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::{mem, ptr};
+use libc;
+
+use header::Header;
+use tag::{Tag, TagID, TagValue};
+use error::{RPMError, RPMFileError};
+use Result;
+
+// mode bits from <sys/stat.h>
+pub const S_IFMT: u32 = 0o170000;
+pub const S_IFDIR: u32 = 0o040000;
+pub const S_IFREG: u32 = 0o100000;
+pub const S_IFLNK: u32 = 0o120000;
+pub const S_IFCHR: u32 = 0o020000;
+pub const S_IFBLK: u32 = 0o060000;
+pub const S_IFIFO: u32 = 0o010000;
+
+bitflags! {
+    /// Per-file flags from FILEFLAGS (see rpmfileAttrs in rpm/lib/rpmfiles.h)
+    pub struct FileFlags: u32 {
+        const CONFIG    = 1 << 0;   // %config
+        const DOC       = 1 << 1;   // %doc
+        const ICON      = 1 << 2;   // (unused)
+        const MISSINGOK = 1 << 3;   // %config(missingok)
+        const NOREPLACE = 1 << 4;   // %config(noreplace)
+        const SPECFILE  = 1 << 5;   // the specfile, in source packages
+        const GHOST     = 1 << 6;   // %ghost: not in the payload
+        const LICENSE   = 1 << 7;   // %license
+        const README    = 1 << 8;   // %readme
+        const PUBKEY    = 1 << 11;  // %pubkey
+        const ARTIFACT  = 1 << 12;  // %artifact
+    }
+}
+
+/// Everything the header says about one of the package's files.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct FileEntry {
+    /// Full path, e.g. "/usr/bin/ls"
+    pub path: String,
+    /// Size of the file (FILESIZES or LONGFILESIZES)
+    pub size: u64,
+    /// File type and permission bits
+    pub mode: u32,
+    /// Modification time, in seconds since the epoch
+    pub mtime: u32,
+    /// Hex digest of the file's contents; see FILEDIGESTALGO for the type
+    pub digest: Option<String>,
+    /// Symlink target
+    pub linkto: Option<String>,
+    pub user: String,
+    pub group: String,
+    pub flags: FileFlags,
+    /// Inode and device numbers; files sharing both are hardlinks
+    pub inode: u32,
+    pub device: u32,
+    /// Device number, for device nodes
+    pub rdev: u32,
+    /// Language, for files marked with %lang
+    pub lang: Option<String>,
+    // whether this file's data is actually stored in the archive;
+    // hardlinked files only store it with the last link.
+    pub(crate) content: bool,
+}
+
+// Get a string array from the header. Missing tags are empty.
+pub(crate) fn strings(hdr: &Header, tag: Tag) -> Result<&[String]> {
+    match hdr.get(&(tag as TagID)) {
+        None => Ok(&[]),
+        Some(TagValue::String(v)) => Ok(v),
+        Some(_) => Err(RPMError::File(RPMFileError::BadHeader)),
+    }
+}
+
+// Get an integer array from the header. Missing tags are empty.
+pub(crate) fn ints(hdr: &Header, tag: Tag) -> Result<Vec<u64>> {
+    match hdr.get(&(tag as TagID)) {
+        None => Ok(Vec::new()),
+        Some(TagValue::Int16(v)) => Ok(v.iter().map(|&n| n as u64).collect()),
+        Some(TagValue::Int32(v)) => Ok(v.iter().map(|&n| n as u64).collect()),
+        Some(TagValue::Int64(v)) => Ok(v.clone()),
+        Some(_) => Err(RPMError::File(RPMFileError::BadHeader)),
+    }
+}
+
+// Get an array that has to have one item per file (or dependency, etc),
+// or none at all.
+pub(crate) fn per_item<T: Clone>(v: Vec<T>, count: usize, default: T) -> Result<Vec<T>> {
+    if v.is_empty() {
+        Ok(vec![default; count])
+    } else if v.len() == count {
+        Ok(v)
+    } else {
+        Err(RPMError::File(RPMFileError::BadHeader))
+    }
+}
+
+// rpm uses empty strings for "nothing here"
+fn nonempty(s: &str) -> Option<String> {
+    if s.is_empty() { None } else { Some(String::from(s)) }
+}
+
+// The biggest scratch buffer we'll hand to getpwnam_r/getgrnam_r before
+// giving up on an entry.
+const MAX_LOOKUP_BUF: usize = 1 << 20;
+
+// Look up a user or group with one of the reentrant getpwnam_r-style
+// functions (the plain ones return static storage, which isn't safe with
+// threads around), growing the scratch buffer while it's too small. `id`
+// picks what we want out of the entry; unknown names give None.
+fn lookup_id<T, U, F, G>(name: &str, lookup: F, id: G) -> Option<U>
+        where F: Fn(*const libc::c_char, *mut T, *mut libc::c_char, usize, *mut *mut T) -> libc::c_int,
+              G: Fn(&T) -> U {
+    let name = CString::new(name).ok()?;
+    let mut buf: Vec<libc::c_char> = vec![0; 1024];
+    loop {
+        let mut entry: T = unsafe { mem::zeroed() };
+        let mut result = ptr::null_mut();
+        match lookup(name.as_ptr(), &mut entry, buf.as_mut_ptr(), buf.len(), &mut result) {
+            0 if !result.is_null() => return Some(id(&entry)),
+            libc::ERANGE if buf.len() < MAX_LOOKUP_BUF => {
+                let len = buf.len() * 2;
+                buf.resize(len, 0);
+            },
+            _ => return None,
+        }
+    }
+}
+
+// Look up the uid for a user name; rpm falls back to root if it's unknown.
+pub(crate) fn lookup_uid(user: &str) -> libc::uid_t {
+    let getpwnam = |name, pw, buf, len, result| unsafe { libc::getpwnam_r(name, pw, buf, len, result) };
+    lookup_id(user, getpwnam, |pw: &libc::passwd| pw.pw_uid).unwrap_or(0)
+}
+
+// Same deal for groups.
+pub(crate) fn lookup_gid(group: &str) -> libc::gid_t {
+    let getgrnam = |name, gr, buf, len, result| unsafe { libc::getgrnam_r(name, gr, buf, len, result) };
+    lookup_id(group, getgrnam, |gr: &libc::group| gr.gr_gid).unwrap_or(0)
+}
+
+impl FileEntry {
+    /// Get the list of files in the given main header, in header order.
+    ///
+    /// Paths are joined from BASENAMES, DIRNAMES and DIRINDEXES, or taken
+    /// from OLDFILENAMES in packages too old to have those.
+    pub fn from_header(hdr: &Header) -> Result<Vec<FileEntry>> {
+        let basenames = strings(hdr, Tag::BASENAMES)?;
+        let paths: Vec<String> = if basenames.is_empty() {
+            strings(hdr, Tag::OLDFILENAMES)?.to_vec()
+        } else {
+            let dirnames = strings(hdr, Tag::DIRNAMES)?;
+            let dirindexes = ints(hdr, Tag::DIRINDEXES)?;
+            if dirindexes.len() != basenames.len() {
+                return Err(RPMError::File(RPMFileError::BadHeader));
+            }
+            let mut paths = Vec::with_capacity(basenames.len());
+            for (base, &idx) in basenames.iter().zip(dirindexes.iter()) {
+                match dirnames.get(idx as usize) {
+                    Some(dir) => paths.push(format!("{}{}", dir, base)),
+                    None => return Err(RPMError::File(RPMFileError::BadHeader)),
+                }
+            }
+            paths
+        };
+        let count = paths.len();
+        let modes = per_item(ints(hdr, Tag::FILEMODES)?, count, 0)?;
+        let flags = per_item(ints(hdr, Tag::FILEFLAGS)?, count, 0)?;
+        let mtimes = per_item(ints(hdr, Tag::FILEMTIMES)?, count, 0)?;
+        let rdevs = per_item(ints(hdr, Tag::FILERDEVS)?, count, 0)?;
+        let inodes = per_item(ints(hdr, Tag::FILEINODES)?, count, 0)?;
+        let devices = per_item(ints(hdr, Tag::FILEDEVICES)?, count, 0)?;
+        let sizes = match ints(hdr, Tag::LONGFILESIZES)? {
+            ref v if v.is_empty() => per_item(ints(hdr, Tag::FILESIZES)?, count, 0)?,
+            v => per_item(v, count, 0)?,
+        };
+        let empty = String::new();
+        let digests = per_item(strings(hdr, Tag::FILEDIGESTS)?.to_vec(), count, empty.clone())?;
+        let linktos = per_item(strings(hdr, Tag::FILELINKTOS)?.to_vec(), count, empty.clone())?;
+        let users = per_item(strings(hdr, Tag::FILEUSERNAME)?.to_vec(), count, empty.clone())?;
+        let groups = per_item(strings(hdr, Tag::FILEGROUPNAME)?.to_vec(), count, empty.clone())?;
+        let langs = per_item(strings(hdr, Tag::FILELANGS)?.to_vec(), count, empty)?;
+
+        let mut files: Vec<FileEntry> = paths.into_iter().enumerate().map(|(i, path)| {
+            FileEntry {
+                path,
+                size: sizes[i],
+                mode: modes[i] as u32,
+                mtime: mtimes[i] as u32,
+                digest: nonempty(&digests[i]),
+                linkto: nonempty(&linktos[i]),
+                user: users[i].clone(),
+                group: groups[i].clone(),
+                flags: FileFlags::from_bits_truncate(flags[i] as u32),
+                inode: inodes[i] as u32,
+                device: devices[i] as u32,
+                rdev: rdevs[i] as u32,
+                lang: nonempty(&langs[i]),
+                content: false,
+            }
+        }).collect();
+
+        // Find the last link of each set of hardlinks; that one gets the data.
+        let mut last_link = HashMap::new();
+        for (i, f) in files.iter().enumerate() {
+            if f.is_file() && !f.is_ghost() {
+                last_link.insert((f.device, f.inode), i);
+            }
+        }
+        for (i, f) in files.iter_mut().enumerate() {
+            f.content = match f.mode & S_IFMT {
+                S_IFREG => f.inode == 0 || last_link.get(&(f.device, f.inode)) == Some(&i),
+                S_IFLNK => true,
+                _ => false,
+            };
+        }
+        Ok(files)
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.mode & S_IFMT == S_IFDIR
+    }
+    pub fn is_file(&self) -> bool {
+        self.mode & S_IFMT == S_IFREG
+    }
+    pub fn is_symlink(&self) -> bool {
+        self.mode & S_IFMT == S_IFLNK
+    }
+    /// Ghost files are listed in the header, but aren't in the payload.
+    pub fn is_ghost(&self) -> bool {
+        self.flags.contains(FileFlags::GHOST)
+    }
+    // FILERDEVS only has room for an old-style 8-bit major and minor
+    pub fn rdev_major(&self) -> u32 {
+        (self.rdev >> 8) & 0xff
+    }
+    pub fn rdev_minor(&self) -> u32 {
+        self.rdev & 0xff
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reader::Reader;
+    static BINRPM1: &[u8] = include_bytes!("../tests/rpms/binary.x86_64.rpm");
+
+    fn strings(v: &[&str]) -> TagValue {
+        TagValue::String(v.iter().map(|s| String::from(*s)).collect())
+    }
+
+    #[test]
+    fn lookup_ids() {
+        let threads: Vec<_> = (0..4).map(|_| ::std::thread::spawn(|| {
+            for _ in 0..100 {
+                assert_eq!(lookup_uid("root"), 0);
+                assert_eq!(lookup_gid("root"), 0);
+                assert_eq!(lookup_uid("no-such-user-here"), 0);
+            }
+        })).collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+        assert_eq!(lookup_uid("ro\0ot"), 0);
+    }
+
+    #[test]
+    fn lookup_id_grows_buffer() {
+        // pretend the entry needs 5000 bytes of scratch space
+        let lookup = |_, entry: *mut u32, _, len, result: *mut *mut u32| {
+            if len < 5000 {
+                return libc::ERANGE;
+            }
+            unsafe { *entry = len as u32; *result = entry; }
+            0
+        };
+        assert_eq!(lookup_id("x", lookup, |&len| len), Some(8192));
+        let too_big = |_, _, _, _, _: *mut *mut u32| libc::ERANGE;
+        assert_eq!(lookup_id("x", too_big, |&len| len), None);
+    }
+
+    #[test]
+    fn files_from_header() {
+        let mut r = Reader::from_bytes(BINRPM1);
+        let _ = r.lead();
+        let _ = r.header().unwrap();
+        let hdr = r.header().unwrap();
+        let files = FileEntry::from_header(&hdr).unwrap();
+        let paths: Vec<&str> = files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(paths, vec![
+            "/usr/sbin/hardlink",
+            "/usr/share/licenses/hardlink",
+            "/usr/share/licenses/hardlink/gpl-2.0.txt",
+            "/usr/share/man/man1/hardlink.1.gz",
+        ]);
+        assert_eq!(files[0], FileEntry {
+            path: String::from("/usr/sbin/hardlink"),
+            size: 15720,
+            mode: 0o100755,
+            mtime: 1454553867,
+            digest: Some(String::from("b7a69159645b51b1e832d00cb13d7df58731db1b824d7038237ebfba05d4259c")),
+            linkto: None,
+            user: String::from("root"),
+            group: String::from("root"),
+            flags: FileFlags::empty(),
+            inode: 1,
+            device: 1,
+            rdev: 0,
+            lang: None,
+            content: true,
+        });
+        assert!(files[1].is_dir());
+        assert_eq!(files[1].digest, None);
+        assert_eq!(files[2].flags, FileFlags::LICENSE);
+        assert_eq!(files[3].flags, FileFlags::DOC);
+    }
+
+    #[test]
+    fn files_from_oldfilenames() {
+        let mut hdr = Header::new();
+        hdr.insert(Tag::OLDFILENAMES as TagID, strings(&["/etc/foo.conf", "/usr/bin/foo"]));
+        hdr.insert(Tag::FILEFLAGS as TagID, TagValue::Int32(vec![0x11, 0]));
+        let files = FileEntry::from_header(&hdr).unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].path, "/etc/foo.conf");
+        assert_eq!(files[0].flags, FileFlags::CONFIG | FileFlags::NOREPLACE);
+        assert_eq!(files[1].path, "/usr/bin/foo");
+    }
+
+    #[test]
+    fn files_bad_arrays() {
+        let mut hdr = Header::new();
+        hdr.insert(Tag::BASENAMES as TagID, strings(&["a", "b"]));
+        hdr.insert(Tag::DIRNAMES as TagID, strings(&["/"]));
+        hdr.insert(Tag::DIRINDEXES as TagID, TagValue::Int32(vec![0, 1]));
+        match FileEntry::from_header(&hdr) {
+            Err(RPMError::File(RPMFileError::BadHeader)) => (),
+            r => panic!("unexpected result: {:?}", r),
+        }
+        hdr.insert(Tag::DIRINDEXES as TagID, TagValue::Int32(vec![0, 0]));
+        hdr.insert(Tag::FILESIZES as TagID, TagValue::Int32(vec![1, 2, 3]));
+        match FileEntry::from_header(&hdr) {
+            Err(RPMError::File(RPMFileError::BadHeader)) => (),
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+}
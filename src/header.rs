@@ -16,14 +16,353 @@
  *   Will Woods <wwoods@redhat.com>
  */
 
-use std::collections::HashMap;
-use tag::{TagID, TagValue};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::iter::FromIterator;
+use std::slice;
+use tag::{Tag, TagID, TagInfo, TagType, TagValue};
+use writer::value_type;
+use parser::parse_bare_header;
+use error::{RPMError, RPMFileError};
+use Result;
 
-// An RPM header section is basically an in-memory hash map
-pub type Header = HashMap<TagID, TagValue>;
+/// An entry in a header section's index: a tag, the type of its value, and
+/// where the value was in the data store.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub struct TagEntry {
+    pub tagid: TagID,
+    pub tagtype: TagType,
+    /// Offset of the value in the data store, as read. (For tags that were
+    /// added after reading, it's 0 until the header is written out.)
+    pub offset: u32,
+    /// Number of items in the value
+    pub count: u32,
+}
+
+/// An RPM header section: a list of tags and their values.
+///
+/// The entries are kept in the order they were read (or added), along with
+/// their `TagEntry` metadata, and can be looked up quickly by tag, like a
+/// hash map. A tag can show up more than once in a damaged (or malicious)
+/// header; lookups get the first one, and `duplicates()` lists the rest.
+#[derive(Clone,Default)]
+pub struct Header {
+    entries: Vec<(TagEntry, TagValue)>,
+    // tag -> position of its first entry
+    index: HashMap<TagID, usize>,
+}
+
+// How many items a value counts as, in a TagEntry
+fn value_count(value: &TagValue) -> u32 {
+    (match *value {
+        TagValue::Null => 0,
+        TagValue::Char(ref v) | TagValue::Int8(ref v) | TagValue::Binary(ref v) => v.len(),
+        TagValue::Int16(ref v) => v.len(),
+        TagValue::Int32(ref v) => v.len(),
+        TagValue::Int64(ref v) => v.len(),
+        TagValue::String(ref v) => v.len(),
+    }) as u32
+}
+
+impl Header {
+    pub fn new() -> Header {
+        Header::default()
+    }
+
+    /// Parse a header that's on its own, like in a bare header file: a
+    /// header section (starting with its magic), and nothing after it.
+    pub fn from_bytes(buf: &[u8]) -> Result<Header> {
+        parse_bare_header(buf, true)
+    }
+
+    /// Parse a header the way the rpmdb stores it: a header section without
+    /// the magic, so it starts right at the entry count.
+    pub fn from_blob(buf: &[u8]) -> Result<Header> {
+        parse_bare_header(buf, false)
+    }
+
+    /// Make a header out of entries as they were read from a section.
+    pub fn from_entries(entries: Vec<(TagEntry, TagValue)>) -> Header {
+        let mut hdr = Header { entries, index: HashMap::new() };
+        hdr.reindex();
+        hdr
+    }
+
+    fn reindex(&mut self) {
+        self.index.clear();
+        for (i, (entry, _)) in self.entries.iter().enumerate() {
+            self.index.entry(entry.tagid).or_insert(i);
+        }
+    }
+
+    /// Number of entries, including any duplicates.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+    pub fn contains_key(&self, id: &TagID) -> bool {
+        self.index.contains_key(id)
+    }
+    /// The value of a tag (the first one, if it's duplicated).
+    pub fn get(&self, id: &TagID) -> Option<&TagValue> {
+        self.index.get(id).map(|&i| &self.entries[i].1)
+    }
+    /// The index entry for a tag (the first one, if it's duplicated).
+    pub fn entry(&self, id: &TagID) -> Option<&TagEntry> {
+        self.index.get(id).map(|&i| &self.entries[i].0)
+    }
+
+    /// Set the value of a tag, returning the old value if there was one.
+    /// A tag that's already there keeps its place (and its type, if the new
+    /// value fits it); new tags are added at the end, with the type the tag
+    /// is supposed to have.
+    pub fn insert(&mut self, id: TagID, value: TagValue) -> Option<TagValue> {
+        let ttype = match self.entry(&id) {
+            Some(entry) => value_type(Some(entry.tagtype), &value),
+            None => value_type(TagInfo::from_id(id).map(|ti| ti.ttype), &value),
+        };
+        self.insert_with_type(id, ttype, value)
+    }
+
+    /// Like `insert()`, but with the given type.
+    pub fn insert_with_type(&mut self, id: TagID, ttype: TagType, value: TagValue) -> Option<TagValue> {
+        let count = value_count(&value);
+        match self.index.get(&id) {
+            Some(&i) => {
+                let (ref mut entry, ref mut old) = self.entries[i];
+                entry.tagtype = ttype;
+                entry.count = count;
+                Some(::std::mem::replace(old, value))
+            },
+            None => {
+                let entry = TagEntry { tagid: id, tagtype: ttype, offset: 0, count };
+                self.index.insert(id, self.entries.len());
+                self.entries.push((entry, value));
+                None
+            },
+        }
+    }
+
+    /// Remove a tag (and any duplicates of it), returning its value.
+    pub fn remove(&mut self, id: &TagID) -> Option<TagValue> {
+        let i = *self.index.get(id)?;
+        let (_, value) = self.entries.remove(i);
+        self.entries.retain(|(entry, _)| entry.tagid != *id);
+        self.reindex();
+        Some(value)
+    }
+
+    /// The tags and their values, in order.
+    pub fn iter(&self) -> Iter<'_> {
+        Iter(self.entries.iter())
+    }
+    /// The index entries and their values, in order.
+    pub fn entries(&self) -> slice::Iter<'_, (TagEntry, TagValue)> {
+        self.entries.iter()
+    }
+
+    /// Tags that show up more than once, in the order they were found.
+    pub fn duplicates(&self) -> Vec<TagID> {
+        let mut seen = HashSet::new();
+        self.entries.iter().enumerate()
+            .filter(|&(i, (entry, _))| self.index[&entry.tagid] != i)
+            .map(|(_, (entry, _))| entry.tagid)
+            .filter(|&tagid| seen.insert(tagid))
+            .collect()
+    }
+}
+
+// HEADERIMAGE, HEADERSIGNATURES and HEADERIMMUTABLE mark regions
+pub(crate) fn is_region_tag(id: TagID) -> bool {
+    id >= Tag::HEADERIMAGE as TagID && id <= Tag::HEADERIMMUTABLE as TagID
+}
+
+fn bad_region(msg: &'static str) -> RPMError {
+    RPMError::File(RPMFileError::BadRegion(msg))
+}
+
+// Find and check the region in a header with the given index entries, where
+// `trailer` is the first entry's value, if it's binary, and `store` is the
+// size of the data store, if there is one. (See `Header::region()`.)
+pub(crate) fn check_region<'a, I>(entries: I, trailer: Option<&[u8]>, store: Option<usize>) -> Result<Option<Region>>
+        where I: Iterator<Item=&'a TagEntry> + Clone {
+    let entry = match entries.clone().next() {
+        Some(entry) if is_region_tag(entry.tagid) => entry,
+        _ if entries.clone().any(|e| is_region_tag(e.tagid)) =>
+            return Err(bad_region("region tag isn't the first entry")),
+        _ => return Ok(None),
+    };
+    if entries.clone().skip(1).any(|e| is_region_tag(e.tagid)) {
+        return Err(bad_region("more than one region tag"));
+    }
+    let trailer = match trailer {
+        Some(v) if entry.tagtype == TagType::BIN && v.len() == 16 => v,
+        _ => return Err(bad_region("region tag isn't a 16-byte trailer")),
+    };
+    match store {
+        Some(size) if entry.offset as usize + 16 > size =>
+            return Err(bad_region("trailer is outside the data store")),
+        _ => (),
+    }
+    let field = |i: usize| u32::from_be_bytes([trailer[i], trailer[i+1], trailer[i+2], trailer[i+3]]);
+    let (tag, ttype, offset, count) = (field(0), field(4), field(8) as i32, field(12));
+    // old packages have HEADERIMAGE trailers in their signature headers
+    let legacy = entry.tagid == Tag::HEADERSIGNATURES as TagID && tag == Tag::HEADERIMAGE as u32;
+    if tag != entry.tagid as u32 && !legacy {
+        return Err(bad_region("trailer tag doesn't match the region tag"));
+    }
+    if ttype != TagType::BIN as u32 || count != 16 {
+        return Err(bad_region("trailer isn't a region entry"));
+    }
+    // the offset is minus the size of the region's index entries
+    let length = -(offset as i64);
+    if length <= 0 || length % 16 != 0 || length / 16 > entries.clone().count() as i64 {
+        return Err(bad_region("trailer doesn't point back into the index"));
+    }
+    let region = Region { tag: entry.tagid, count: (length / 16) as usize, size: entry.offset as usize + 16 };
+    if entries.clone().take(region.count).skip(1).any(|e| e.offset >= entry.offset) {
+        return Err(bad_region("entry in the region has data outside it"));
+    }
+    if entries.skip(region.count).any(|e| (e.offset as usize) < region.size) {
+        return Err(bad_region("dribble entry has data inside the region"));
+    }
+    Ok(Some(region))
+}
+
+/// The region at the start of a header section, marked by a tag like
+/// HEADERIMMUTABLE (or HEADERSIGNATURES, in the signature header).
+///
+/// The region covers the entries the header had when it was built and
+/// signed. Anything after it is a "dribble" entry, added later (like the
+/// tags rpm adds to installed headers), and isn't covered by the header's
+/// signatures.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub struct Region {
+    /// The tag that marks the region
+    pub tag: TagID,
+    /// How many index entries the region covers, counting its own
+    pub count: usize,
+    /// How much of the data store the region covers, counting its trailer
+    pub size: usize,
+}
+
+impl Region {
+    /// Cut the region out of a raw header section (like the one from
+    /// `Reader::raw_header()`), as a section of its own: the header as it
+    /// was before any dribble entries were added.
+    pub fn section(&self, raw: &[u8]) -> Result<Vec<u8>> {
+        let count = match raw.get(8..12) {
+            Some(b) => u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as usize,
+            None => return Err(RPMError::File(RPMFileError::BadHeader)),
+        };
+        let store = 16 + count * 16;
+        if self.count > count || raw.len() < store + self.size {
+            return Err(bad_region("region doesn't fit in the header section"));
+        }
+        let mut section = raw[..8].to_vec();
+        section.extend_from_slice(&(self.count as u32).to_be_bytes());
+        section.extend_from_slice(&(self.size as u32).to_be_bytes());
+        section.extend_from_slice(&raw[16..16 + self.count * 16]);
+        section.extend_from_slice(&raw[store..store + self.size]);
+        Ok(section)
+    }
+}
+
+impl Header {
+    /// Decode and check the header's region, if it has one.
+    ///
+    /// The region tag has to be the first entry, and its value is a
+    /// trailer: an index entry that points back at the start of the index,
+    /// which says how many entries the region covers. The entries in the
+    /// region have to have their data in the region, and the dribble
+    /// entries after it have to have theirs after it; anything else gets
+    /// `RPMFileError::BadRegion`, since it means the header is damaged or
+    /// has been tampered with.
+    pub fn region(&self) -> Result<Option<Region>> {
+        self.region_in(None)
+    }
+    // Same, but for a header that was read from a section with a data
+    // store of the given size, which the trailer has to fit in.
+    pub(crate) fn region_in(&self, store: Option<usize>) -> Result<Option<Region>> {
+        let trailer = match self.entries.first() {
+            Some((_, TagValue::Binary(v))) => Some(&v[..]),
+            _ => None,
+        };
+        check_region(self.entries.iter().map(|(entry, _)| entry), trailer, store)
+    }
+
+    /// Whether a tag is in the header's region, so it's covered by the
+    /// header's signatures. A header without a region has no immutable tags
+    /// (and no dribble tags either).
+    pub fn is_immutable(&self, id: &TagID) -> Result<bool> {
+        let region = match self.region()? {
+            Some(region) => region,
+            None => return Ok(false),
+        };
+        Ok(self.entries[1..region.count].iter().any(|(e, _)| e.tagid == *id))
+    }
+
+    /// The tags of the dribble entries that come after the header's region,
+    /// in order.
+    pub fn dribbles(&self) -> Result<Vec<TagID>> {
+        Ok(match self.region()? {
+            Some(region) => self.entries[region.count..].iter().map(|(e, _)| e.tagid).collect(),
+            None => Vec::new(),
+        })
+    }
+}
 
-// There's a separate Header section that's just for signatures, so:
-pub type SignatureHeader = Header;
+/// Iterator over the tags and values in a `Header`.
+pub struct Iter<'a>(slice::Iter<'a, (TagEntry, TagValue)>);
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a TagID, &'a TagValue);
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|(entry, value)| (&entry.tagid, value))
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<'a> IntoIterator for &'a Header {
+    type Item = (&'a TagID, &'a TagValue);
+    type IntoIter = Iter<'a>;
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
+    }
+}
+
+impl FromIterator<(TagID, TagValue)> for Header {
+    fn from_iter<I: IntoIterator<Item=(TagID, TagValue)>>(iter: I) -> Header {
+        let mut hdr = Header::new();
+        for (id, value) in iter {
+            hdr.insert(id, value);
+        }
+        hdr
+    }
+}
+
+// Headers are equal if they have the same tags with the same values, in the
+// same order; where the values were in the data store doesn't matter.
+impl PartialEq for Header {
+    fn eq(&self, other: &Header) -> bool {
+        self.iter().eq(other.iter())
+    }
+}
+
+impl Eq for Header {}
+
+impl fmt::Debug for Header {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+// There's a separate Header section that's just for signatures; see
+// SignatureHeader in signature.rs.
 
 // The Lead structure, which is basically useless except to identify an RPM
 #[derive(Debug,PartialEq,Eq)]
@@ -36,3 +375,214 @@ pub struct Lead {
     pub osnum:          u16,        // if binary: package OS (0x01 = Linux)
     pub signature_type: u16,        // package signature type (0x05)
 }
+
+impl Lead {
+    /// Write the lead back out the way it's stored in a package. Names that
+    /// don't fit are cut short.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![0xED, 0xAB, 0xEE, 0xDB, self.major, self.minor];
+        bytes.extend_from_slice(&self.rpm_type.to_be_bytes());
+        bytes.extend_from_slice(&self.archnum.to_be_bytes());
+        let mut name = [0; 66];
+        let len = self.name.len().min(65);
+        name[..len].copy_from_slice(&self.name.as_bytes()[..len]);
+        bytes.extend_from_slice(&name);
+        bytes.extend_from_slice(&self.osnum.to_be_bytes());
+        bytes.extend_from_slice(&self.signature_type.to_be_bytes());
+        bytes.extend_from_slice(&[0; 16]);
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reader::Reader;
+    static BINRPM1: &[u8] = include_bytes!("../tests/rpms/binary.x86_64.rpm");
+
+    fn string(s: &str) -> TagValue {
+        TagValue::String(vec![String::from(s)])
+    }
+
+    #[test]
+    fn header_entries() {
+        let mut r = Reader::from_bytes(BINRPM1);
+        r.lead().unwrap();
+        r.signature().unwrap();
+        let hdr = r.header().unwrap();
+        assert_eq!(hdr.len(), 62);
+        assert!(hdr.duplicates().is_empty());
+        // the region comes first on disk, then the rest in tag order
+        let ids: Vec<TagID> = hdr.iter().map(|(&id, _)| id).collect();
+        assert_eq!(ids[0], Tag::HEADERIMMUTABLE as TagID);
+        assert!(ids[1..].windows(2).all(|w| w[0] < w[1]));
+        let i18n = hdr.entry(&(Tag::HEADERI18NTABLE as TagID)).unwrap();
+        assert_eq!((i18n.tagtype, i18n.offset, i18n.count), (TagType::STRING_ARRAY, 0, 1));
+        let name = hdr.entry(&(Tag::NAME as TagID)).unwrap();
+        assert_eq!((name.tagtype, name.offset, name.count), (TagType::STRING, 2, 1));
+        assert_eq!(hdr.get(&(Tag::NAME as TagID)), Some(&string("hardlink")));
+    }
+
+    // The fixture's main header, raw
+    fn raw_header() -> (Header, Vec<u8>) {
+        let mut r = Reader::from_bytes(BINRPM1);
+        r.lead().unwrap();
+        r.signature().unwrap();
+        r.raw_header().unwrap()
+    }
+
+    fn region_error(hdr: &Header) -> &'static str {
+        match hdr.region() {
+            Err(RPMError::File(RPMFileError::BadRegion(msg))) => msg,
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn immutable_region() {
+        let (hdr, raw) = raw_header();
+        let region = hdr.region().unwrap().unwrap();
+        assert_eq!(region, Region { tag: Tag::HEADERIMMUTABLE as TagID, count: 62, size: raw.len() - 16 - 62 * 16 });
+        assert!(hdr.is_immutable(&(Tag::NAME as TagID)).unwrap());
+        assert!(!hdr.is_immutable(&(Tag::INSTALLTIME as TagID)).unwrap());
+        assert!(hdr.dribbles().unwrap().is_empty());
+        assert_eq!(region.section(&raw).unwrap(), raw);
+
+        let mut r = Reader::from_bytes(BINRPM1);
+        r.lead().unwrap();
+        let sig = r.signature().unwrap();
+        let region = sig.header().region().unwrap().unwrap();
+        assert_eq!((region.tag, region.count), (Tag::HEADERSIGNATURES as TagID, 8));
+        assert_eq!(Header::new().region().unwrap(), None);
+    }
+
+    #[test]
+    fn dribble_entries() {
+        let (hdr, raw) = raw_header();
+        let region = hdr.region().unwrap().unwrap();
+        // add a dribble entry, like rpm does when it installs a package
+        let mut entries: Vec<_> = hdr.entries().cloned().collect();
+        let entry = TagEntry { tagid: Tag::INSTALLTIME as TagID, tagtype: TagType::INT32,
+                               offset: region.size as u32, count: 1 };
+        entries.push((entry, TagValue::Int32(vec![1500000000])));
+        let installed = Header::from_entries(entries.clone());
+        assert_eq!(installed.region().unwrap(), Some(region));
+        assert_eq!(installed.dribbles().unwrap(), vec![Tag::INSTALLTIME as TagID]);
+        assert!(!installed.is_immutable(&(Tag::INSTALLTIME as TagID)).unwrap());
+
+        // the raw section with the dribble added cuts back down to the original
+        let mut raw2 = raw[..8].to_vec();
+        raw2.extend_from_slice(&63u32.to_be_bytes());
+        raw2.extend_from_slice(&(region.size as u32 + 4).to_be_bytes());
+        raw2.extend_from_slice(&raw[16..16 + 62 * 16]);
+        raw2.extend_from_slice(&[0, 0, 0x03, 0xe8 + 8, 0, 0, 0, 4]);
+        raw2.extend_from_slice(&(region.size as u32).to_be_bytes());
+        raw2.extend_from_slice(&1u32.to_be_bytes());
+        raw2.extend_from_slice(&raw[16 + 62 * 16..]);
+        raw2.extend_from_slice(&1500000000u32.to_be_bytes());
+        assert_eq!(region.section(&raw2).unwrap(), raw);
+
+        // but dribbles can't sneak their data into the region
+        entries.last_mut().unwrap().0.offset = 0;
+        assert_eq!(region_error(&Header::from_entries(entries)), "dribble entry has data inside the region");
+    }
+
+    #[test]
+    fn bad_regions() {
+        let (hdr, _) = raw_header();
+        let entries: Vec<_> = hdr.entries().cloned().collect();
+        let with_trailer = |trailer: [u8; 16]| {
+            let mut entries = entries.clone();
+            entries[0].1 = TagValue::Binary(trailer.to_vec());
+            Header::from_entries(entries)
+        };
+        // the real trailer is 63, BIN, -62*16, 16
+        let good = [0, 0, 0, 63, 0, 0, 0, 7, 0xff, 0xff, 0xfc, 0x20, 0, 0, 0, 16];
+        assert!(with_trailer(good).region().is_ok());
+        let mut bad = good;
+        bad[3] = 62;
+        assert_eq!(region_error(&with_trailer(bad)), "trailer tag doesn't match the region tag");
+        let mut bad = good;
+        bad[15] = 15;
+        assert_eq!(region_error(&with_trailer(bad)), "trailer isn't a region entry");
+        for offset in [0i32, 16, -8, -63 * 16, i32::MIN].iter() {
+            let mut bad = good;
+            bad[8..12].copy_from_slice(&offset.to_be_bytes());
+            assert_eq!(region_error(&with_trailer(bad)), "trailer doesn't point back into the index");
+        }
+        // a shorter region is fine, as long as the rest have their data after it
+        let mut short = good;
+        short[8..12].copy_from_slice(&(-61i32 * 16).to_be_bytes());
+        assert_eq!(region_error(&with_trailer(short)), "dribble entry has data inside the region");
+
+        let mut entries2 = entries.clone();
+        entries2[5].0.offset = entries[0].0.offset + 16;
+        assert_eq!(region_error(&Header::from_entries(entries2)), "entry in the region has data outside it");
+        let mut entries2 = entries.clone();
+        entries2.swap(0, 1);
+        assert_eq!(region_error(&Header::from_entries(entries2)), "region tag isn't the first entry");
+        let mut entries2 = entries.clone();
+        entries2[0].0.tagtype = TagType::STRING;
+        assert_eq!(region_error(&Header::from_entries(entries2)), "region tag isn't a 16-byte trailer");
+        let mut entries2 = entries.clone();
+        entries2.push(entries[0].clone());
+        assert_eq!(region_error(&Header::from_entries(entries2)), "more than one region tag");
+
+        // the trailer is the last thing in this data store, so it has to fit
+        let store = entries[0].0.offset as usize + 16;
+        assert!(hdr.region_in(Some(store)).is_ok());
+        match hdr.region_in(Some(store - 1)) {
+            Err(RPMError::File(RPMFileError::BadRegion(msg))) => assert_eq!(msg, "trailer is outside the data store"),
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn duplicate_tags() {
+        let entry = |tagid, offset| TagEntry { tagid, tagtype: TagType::STRING, offset, count: 1 };
+        let mut hdr = Header::from_entries(vec![
+            (entry(1000, 0), string("a")),
+            (entry(1001, 2), string("b")),
+            (entry(1000, 4), string("c")),
+            (entry(1000, 6), string("d")),
+        ]);
+        assert_eq!(hdr.len(), 4);
+        assert_eq!(hdr.duplicates(), vec![1000]);
+        assert_eq!(hdr.get(&1000), Some(&string("a")));
+        assert_eq!(hdr.entry(&1000).map(|e| e.offset), Some(0));
+        // replacing a tag keeps its place; removing it gets rid of them all
+        assert_eq!(hdr.insert(1001, string("e")), Some(string("b")));
+        assert_eq!(hdr.iter().nth(1), Some((&1001, &string("e"))));
+        assert_eq!(hdr.remove(&1000), Some(string("a")));
+        assert_eq!(hdr.len(), 1);
+        assert!(hdr.duplicates().is_empty());
+
+        // repeats that aren't next to each other only get listed once
+        let hdr = Header::from_entries(vec![
+            (entry(1000, 0), string("a")),
+            (entry(1001, 2), string("b")),
+            (entry(1000, 4), string("a")),
+            (entry(1001, 6), string("b")),
+            (entry(1000, 8), string("a")),
+        ]);
+        assert_eq!(hdr.duplicates(), vec![1000, 1001]);
+    }
+
+    #[test]
+    fn insert_order() {
+        let mut hdr = Header::new();
+        hdr.insert(Tag::VERSION as TagID, string("1.0"));
+        hdr.insert(Tag::NAME as TagID, string("foo"));
+        hdr.insert(Tag::SUMMARY as TagID, string("a foo"));
+        hdr.insert(Tag::EPOCH as TagID, TagValue::Int32(vec![1, 2]));
+        let ids: Vec<TagID> = hdr.iter().map(|(&id, _)| id).collect();
+        assert_eq!(ids, vec![1001, 1000, 1004, 1003]);
+        // new tags get the type they're supposed to have
+        let summary = hdr.entry(&(Tag::SUMMARY as TagID)).unwrap();
+        assert_eq!((summary.tagtype, summary.count), (TagType::I18NSTRING, 1));
+        let epoch = hdr.entry(&(Tag::EPOCH as TagID)).unwrap();
+        assert_eq!((epoch.tagtype, epoch.count), (TagType::INT32, 2));
+        let same: Header = hdr.iter().map(|(&id, v)| (id, v.clone())).collect();
+        assert_eq!(same, hdr);
+    }
+}
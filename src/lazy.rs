@@ -0,0 +1,228 @@
+/* lazy.rs - header sections that decode their values on demand
+ *
+ * Copyright (c) 2017, Red Hat, Inc.
+ *
+ * This library is free software; you can redistribute it and/or modify it
+ * under the terms and conditions of the GNU Lesser General Public License
+ * as published by the Free Software Foundation; either version 2.1 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU Lesser General Public License for
+ * more details.
+ *
+ * Authors:
+ *   Will Woods <wwoods@redhat.com>
+ */
+use std::cell::OnceCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::slice;
+
+use header::{Header, TagEntry, Region, check_region};
+use parser::{parse_section_index, decode_tagval};
+use tag::{TagID, TagValue};
+use error::{RPMError, Section};
+use Result;
+
+/// A header section that only reads its index up front. Each tag's value
+/// is decoded from the data store the first time it's asked for, and kept
+/// for next time.
+///
+/// That makes looking at a handful of tags much cheaper than reading a
+/// whole `Header`, especially in packages with long file lists. A value
+/// that can't be decoded is only an error when it's asked for.
+pub struct LazyHeader {
+    entries: Vec<TagEntry>,
+    values: Vec<OnceCell<TagValue>>,
+    // tag -> position of its first entry
+    index: HashMap<TagID, usize>,
+    // the index and the data store, as read
+    data: Vec<u8>,
+    index_size: usize,
+    // where the section was, for errors
+    section: Section,
+    base: u64,
+}
+
+impl LazyHeader {
+    /// Read the index of a header section, given the section's data (the
+    /// index followed by the store) and the count and size from its section
+    /// header.
+    pub fn from_section(mut data: Vec<u8>, count: usize, size: usize) -> Result<LazyHeader> {
+        let entries = parse_section_index(&data, count, size).map_err(RPMError::Parse)?.1;
+        let index_size = entries.len() * 16;
+        data.truncate(index_size + size);
+        let mut index = HashMap::new();
+        for (i, entry) in entries.iter().enumerate() {
+            index.entry(entry.tagid).or_insert(i);
+        }
+        Ok(LazyHeader {
+            values: entries.iter().map(|_| OnceCell::new()).collect(),
+            entries,
+            index,
+            data,
+            index_size,
+            section: Section::Header,
+            base: 0,
+        })
+    }
+
+    // Say where the section's data was, for errors.
+    pub(crate) fn located(mut self, section: Section, base: u64) -> LazyHeader {
+        self.section = section;
+        self.base = base;
+        self
+    }
+
+    /// Number of entries, including any duplicates.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+    pub fn contains_key(&self, id: &TagID) -> bool {
+        self.index.contains_key(id)
+    }
+    /// The index entry for a tag (the first one, if it's duplicated).
+    pub fn entry(&self, id: &TagID) -> Option<&TagEntry> {
+        self.index.get(id).map(|&i| &self.entries[i])
+    }
+    /// The index entries, in order.
+    pub fn entries(&self) -> slice::Iter<'_, TagEntry> {
+        self.entries.iter()
+    }
+
+    /// The value of a tag (the first one, if it's duplicated), decoding it
+    /// if it hasn't been already.
+    pub fn get(&self, id: &TagID) -> Result<Option<&TagValue>> {
+        match self.index.get(id) {
+            Some(&i) => self.value(i).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Whether a tag's value has been decoded yet.
+    pub fn is_decoded(&self, id: &TagID) -> bool {
+        self.index.get(id).is_some_and(|&i| self.values[i].get().is_some())
+    }
+
+    // The value of entry `i`
+    fn value(&self, i: usize) -> Result<&TagValue> {
+        if let Some(value) = self.values[i].get() {
+            return Ok(value);
+        }
+        let store = &self.data[self.index_size..];
+        let value = decode_tagval(store, &self.entries[i], i, self.index_size)
+            .map_err(|e| RPMError::Parse(e.relative_to(self.section, self.base)))?;
+        Ok(self.values[i].get_or_init(|| value))
+    }
+
+    /// Decode and check the header's region; see `Header::region()`.
+    pub fn region(&self) -> Result<Option<Region>> {
+        let trailer = match self.entries.first() {
+            Some(_) => match *self.value(0)? {
+                TagValue::Binary(ref v) => Some(&v[..]),
+                _ => None,
+            },
+            None => None,
+        };
+        check_region(self.entries.iter(), trailer, Some(self.data.len() - self.index_size))
+    }
+
+    /// Decode everything into a `Header`.
+    pub fn to_header(&self) -> Result<Header> {
+        let mut entries = Vec::with_capacity(self.entries.len());
+        for (i, entry) in self.entries.iter().enumerate() {
+            entries.push((*entry, self.value(i)?.clone()));
+        }
+        Ok(Header::from_entries(entries))
+    }
+}
+
+impl fmt::Debug for LazyHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.entries.iter().zip(self.values.iter())
+                                  .map(|(entry, value)| (entry.tagid, value.get()))).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reader::Reader;
+    use tag::{Tag, TagInfo};
+    use error::{ParseError, RPMFileError};
+    static BINRPM1: &[u8] = include_bytes!("../tests/rpms/binary.x86_64.rpm");
+
+    #[test]
+    fn lazy_header() {
+        let mut r = Reader::from_bytes(BINRPM1);
+        r.lead().unwrap();
+        r.signature().unwrap();
+        let hdr = r.lazy_header().unwrap();
+        assert_eq!(hdr.len(), 62);
+        let name = TagInfo::from_name("name").unwrap().id as TagID;
+        let basenames = Tag::BASENAMES as TagID;
+        assert!(!hdr.is_decoded(&name));
+        assert_eq!(hdr.get(&name).unwrap(), Some(&TagValue::String(vec![String::from("hardlink")])));
+        assert!(hdr.is_decoded(&name));
+        assert!(!hdr.is_decoded(&basenames));
+        assert_eq!(hdr.get(&31337).unwrap(), None);
+        // the payload's right after it, as usual
+        let owned = hdr.to_header().unwrap();
+        assert!(r.archive(&owned).unwrap().next_entry().unwrap().is_some());
+
+        let mut r = Reader::from_bytes(BINRPM1);
+        r.lead().unwrap();
+        r.signature().unwrap();
+        assert_eq!(r.header().unwrap(), owned);
+    }
+
+    #[test]
+    fn lazy_bad_value() {
+        // make the main header's NAME (at 0x1968 + 2) not UTF-8
+        let mut bytes = BINRPM1.to_vec();
+        bytes[0x196a] = 0xff;
+        let mut r = Reader::from_bytes(bytes);
+        r.lead().unwrap();
+        r.signature().unwrap();
+        // it's fine until NAME gets decoded
+        let hdr = r.lazy_header().unwrap();
+        assert!(hdr.get(&(Tag::VERSION as TagID)).unwrap().is_some());
+        match hdr.get(&(Tag::NAME as TagID)).unwrap_err() {
+            RPMError::Parse(ParseError { kind: RPMFileError::BadValue(_), section: Section::Header,
+                                         offset: 0x196a, tag: Some(1000), .. }) => (),
+            e => panic!("unexpected error: {}", e),
+        }
+        assert!(hdr.to_header().is_err());
+    }
+}
+
+#[cfg(feature="bench")]
+mod bench {
+    extern crate test;
+    use self::test::Bencher;
+    use super::LazyHeader;
+    use parser::parse_section_data;
+    use tag::{Tag, TagID};
+    static BINRPM1: &[u8] = include_bytes!("../tests/rpms/binary.x86_64.rpm");
+
+    #[bench]
+    fn get_name_eager(b: &mut Bencher) {
+        b.iter(|| {
+            let (_, hdr) = parse_section_data(&BINRPM1[0x1588..], 62, 0x17d2).unwrap();
+            hdr.get(&(Tag::NAME as TagID)).is_some()
+        });
+    }
+
+    #[bench]
+    fn get_name_lazy(b: &mut Bencher) {
+        b.iter(|| {
+            let hdr = LazyHeader::from_section(BINRPM1[0x1588..0x313a].to_vec(), 62, 0x17d2).unwrap();
+            hdr.get(&(Tag::NAME as TagID)).unwrap().is_some()
+        });
+    }
+}
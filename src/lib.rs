@@ -25,16 +25,84 @@ extern crate nom;
 #[macro_use]
 extern crate lazy_static;
 
+#[macro_use]
+extern crate bitflags;
+
+extern crate bzip2;
+extern crate flate2;
+extern crate xz2;
+extern crate zstd;
+extern crate libc;
+extern crate filetime;
+extern crate tar;
+extern crate digest;
+extern crate md5;
+extern crate sha1;
+extern crate sha2;
+extern crate base64;
+extern crate rsa;
+extern crate dsa;
+extern crate p256;
+extern crate p384;
+extern crate p521;
+extern crate ed25519_dalek;
+extern crate memmap2;
+#[cfg(feature="async")]
+extern crate tokio;
+
+#[cfg(test)]
+extern crate tempfile;
+
 use std::result;
 
 mod rpmtag;
 mod tag;
 mod error;
 mod header;
+mod borrowed;
+mod lazy;
 mod parser;
+mod writer;
 mod reader;
+#[cfg(feature="async")]
+mod async_reader;
+mod payload;
+mod archive;
+mod files;
+mod extract;
+mod totar;
+mod package;
+mod version;
+mod deps;
+mod rich;
+mod signature;
+mod verify;
+mod pgp;
+mod sign;
+mod builder;
 
-pub use error::RPMError;
-pub use reader::Reader;
-pub use tag::{Tag, TagInfo};
+pub use error::{RPMError, RPMFileError, ParseError, Section};
+pub use header::{Lead, Header, TagEntry, Region};
+pub use borrowed::{HeaderRef, TagValueRef, BeArray, BeIter, BigEndian, Strings};
+pub use lazy::LazyHeader;
+pub use reader::{Reader, Limits, Offsets, Headers};
+#[cfg(feature="async")]
+pub use async_reader::AsyncReader;
+pub use payload::Compressor;
+pub use archive::{Archive, ArchiveEntry};
+pub use files::{FileEntry, FileFlags};
+pub use package::Package;
+pub use version::{Evr, rpmvercmp};
+pub use deps::{Dependency, DepFlags, DepKind};
+pub use rich::{RichDep, ProviderSet};
+pub use signature::{SignatureHeader, SigTagInfo};
+pub use verify::{DigestKind, verify_digests, verify_package};
+pub use verify::{SignatureCheck, Verification, verify_signatures, verify_package_signatures};
+pub use pgp::{PgpSignature, PublicKey, SecretKey, Keyring, Verdict};
+pub use sign::{Signer, remove_signatures};
+pub use writer::header_to_bytes;
+pub use builder::{PackageBuilder, PackageFile, Scriptlet};
+pub use extract::extract;
+pub use totar::to_tar;
+pub use tag::{Tag, SigTag, TagID, TagInfo, TagValue};
 pub type Result<T> = result::Result<T, error::RPMError>;
@@ -0,0 +1,320 @@
+/* package.rs - a typed view of an RPM's main header
+ *
+ * Copyright (c) 2017, Red Hat, Inc.
+ *
+ * This library is free software; you can redistribute it and/or modify it
+ * under the terms and conditions of the GNU Lesser General Public License
+ * as published by the Free Software Foundation; either version 2.1 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU Lesser General Public License for
+ * more details.
+ *
+ * Authors:
+ *   Will Woods <wwoods@redhat.com>
+ */
+use std::cmp::Ordering;
+
+use header::Header;
+use files::FileEntry;
+use deps::{Dependency, DepKind};
+use version::Evr;
+use tag::{Tag, TagID, TagValue};
+use error::{RPMError, RPMFileError};
+use Result;
+
+/// A package's main header, with typed accessors for the common tags.
+///
+/// Accessors return `RPMFileError::MissingTag` if a required tag isn't in
+/// the header, and `RPMFileError::WrongTagType` if it holds the wrong kind
+/// of value. Tags that packages may legitimately leave out (like EPOCH) are
+/// returned as an `Option` instead.
+#[derive(Debug)]
+pub struct Package {
+    header: Header,
+}
+
+fn missing(tag: Tag) -> RPMError {
+    RPMError::File(RPMFileError::MissingTag(tag))
+}
+
+fn wrong_type(tag: Tag) -> RPMError {
+    RPMError::File(RPMFileError::WrongTagType(tag))
+}
+
+// Turn "missing tag" into None, leaving other errors alone
+fn optional<T>(r: Result<T>) -> Result<Option<T>> {
+    match r {
+        Ok(v) => Ok(Some(v)),
+        Err(RPMError::File(RPMFileError::MissingTag(_))) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+impl Package {
+    /// Wraps a package's main header.
+    pub fn new(header: Header) -> Package {
+        Package { header }
+    }
+    /// The underlying header.
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+    /// Unwrap the underlying header.
+    pub fn into_header(self) -> Header {
+        self.header
+    }
+
+    /// Get the raw value of a tag.
+    pub fn get(&self, tag: Tag) -> Result<&TagValue> {
+        self.header.get(&(tag as TagID)).ok_or_else(|| missing(tag))
+    }
+    /// Get a string tag. For I18NSTRING tags this is the untranslated
+    /// ("C" locale) string.
+    pub fn get_string(&self, tag: Tag) -> Result<&str> {
+        match self.get(tag)? {
+            TagValue::String(v) if !v.is_empty() => Ok(&v[0]),
+            _ => Err(wrong_type(tag)),
+        }
+    }
+    /// Get a string array tag.
+    pub fn get_strings(&self, tag: Tag) -> Result<&[String]> {
+        match self.get(tag)? {
+            TagValue::String(v) => Ok(v),
+            _ => Err(wrong_type(tag)),
+        }
+    }
+    /// Get a single INT32 tag.
+    pub fn get_u32(&self, tag: Tag) -> Result<u32> {
+        match self.get(tag)? {
+            TagValue::Int32(v) if v.len() == 1 => Ok(v[0]),
+            _ => Err(wrong_type(tag)),
+        }
+    }
+    /// Get an INT32 array tag.
+    pub fn get_u32s(&self, tag: Tag) -> Result<&[u32]> {
+        match self.get(tag)? {
+            TagValue::Int32(v) => Ok(v),
+            _ => Err(wrong_type(tag)),
+        }
+    }
+    /// Get a single INT64 tag.
+    pub fn get_u64(&self, tag: Tag) -> Result<u64> {
+        match self.get(tag)? {
+            TagValue::Int64(v) if v.len() == 1 => Ok(v[0]),
+            _ => Err(wrong_type(tag)),
+        }
+    }
+
+    pub fn name(&self) -> Result<&str> {
+        self.get_string(Tag::NAME)
+    }
+    /// The package's epoch, if it has one.
+    pub fn epoch(&self) -> Result<Option<u32>> {
+        optional(self.get_u32(Tag::EPOCH))
+    }
+    pub fn version(&self) -> Result<&str> {
+        self.get_string(Tag::VERSION)
+    }
+    pub fn release(&self) -> Result<&str> {
+        self.get_string(Tag::RELEASE)
+    }
+    pub fn arch(&self) -> Result<&str> {
+        self.get_string(Tag::ARCH)
+    }
+    /// The package's epoch, version and release.
+    pub fn evr(&self) -> Result<Evr> {
+        Ok(Evr::new(self.epoch()?, self.version()?, self.release()?))
+    }
+    /// Compare two packages by EVR, the way rpm decides which one is newer.
+    /// The names aren't compared. Use it to sort packages, oldest first:
+    /// `pkgs.sort_by(Package::cmp_evr)`.
+    ///
+    /// Like librpm, a missing or unreadable epoch counts as 0 and a missing
+    /// version or release as an empty string.
+    pub fn cmp_evr(&self, other: &Package) -> Ordering {
+        fn evr(pkg: &Package) -> Evr {
+            Evr::new(pkg.epoch().unwrap_or(None), pkg.version().unwrap_or(""),
+                     pkg.release().unwrap_or(""))
+        }
+        evr(self).cmp(&evr(other))
+    }
+    /// Name-[epoch:]version-release.arch, like `rpm -q` shows it.
+    pub fn nevra(&self) -> Result<String> {
+        let epoch = match self.epoch()? {
+            Some(e) => format!("{}:", e),
+            None => String::new(),
+        };
+        Ok(format!("{}-{}{}-{}.{}", self.name()?, epoch,
+                   self.version()?, self.release()?, self.arch()?))
+    }
+
+    pub fn summary(&self) -> Result<&str> {
+        self.get_string(Tag::SUMMARY)
+    }
+    pub fn description(&self) -> Result<&str> {
+        self.get_string(Tag::DESCRIPTION)
+    }
+    pub fn license(&self) -> Result<&str> {
+        self.get_string(Tag::LICENSE)
+    }
+    pub fn group(&self) -> Result<Option<&str>> {
+        optional(self.get_string(Tag::GROUP))
+    }
+    pub fn url(&self) -> Result<Option<&str>> {
+        optional(self.get_string(Tag::URL))
+    }
+    pub fn vendor(&self) -> Result<Option<&str>> {
+        optional(self.get_string(Tag::VENDOR))
+    }
+    pub fn packager(&self) -> Result<Option<&str>> {
+        optional(self.get_string(Tag::PACKAGER))
+    }
+    pub fn os(&self) -> Result<Option<&str>> {
+        optional(self.get_string(Tag::OS))
+    }
+    pub fn buildhost(&self) -> Result<Option<&str>> {
+        optional(self.get_string(Tag::BUILDHOST))
+    }
+    /// Build time, in seconds since the epoch.
+    pub fn buildtime(&self) -> Result<u32> {
+        self.get_u32(Tag::BUILDTIME)
+    }
+    /// Total size of the package's files, in bytes.
+    pub fn size(&self) -> Result<u64> {
+        match optional(self.get_u64(Tag::LONGSIZE))? {
+            Some(size) => Ok(size),
+            None => self.get_u32(Tag::SIZE).map(|s| s as u64),
+        }
+    }
+    /// The source package this was built from. Source packages don't have one.
+    pub fn sourcerpm(&self) -> Result<Option<&str>> {
+        optional(self.get_string(Tag::SOURCERPM))
+    }
+    pub fn is_source(&self) -> bool {
+        !self.header.contains_key(&(Tag::SOURCERPM as TagID))
+    }
+    /// The package's files; see `FileEntry::from_header`.
+    pub fn files(&self) -> Result<Vec<FileEntry>> {
+        FileEntry::from_header(&self.header)
+    }
+
+    /// The package's dependencies of the given kind; see
+    /// `Dependency::from_header`.
+    pub fn dependencies(&self, kind: DepKind) -> Result<Vec<Dependency>> {
+        Dependency::from_header(&self.header, kind)
+    }
+    pub fn requires(&self) -> Result<Vec<Dependency>> {
+        self.dependencies(DepKind::Require)
+    }
+    pub fn provides(&self) -> Result<Vec<Dependency>> {
+        self.dependencies(DepKind::Provide)
+    }
+    pub fn conflicts(&self) -> Result<Vec<Dependency>> {
+        self.dependencies(DepKind::Conflict)
+    }
+    pub fn obsoletes(&self) -> Result<Vec<Dependency>> {
+        self.dependencies(DepKind::Obsolete)
+    }
+    pub fn recommends(&self) -> Result<Vec<Dependency>> {
+        self.dependencies(DepKind::Recommend)
+    }
+    pub fn suggests(&self) -> Result<Vec<Dependency>> {
+        self.dependencies(DepKind::Suggest)
+    }
+    pub fn supplements(&self) -> Result<Vec<Dependency>> {
+        self.dependencies(DepKind::Supplement)
+    }
+    pub fn enhances(&self) -> Result<Vec<Dependency>> {
+        self.dependencies(DepKind::Enhance)
+    }
+}
+
+impl From<Header> for Package {
+    fn from(header: Header) -> Package {
+        Package::new(header)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reader::Reader;
+    static BINRPM1: &[u8] = include_bytes!("../tests/rpms/binary.x86_64.rpm");
+
+    fn package() -> Package {
+        let mut r = Reader::from_bytes(BINRPM1);
+        let _ = r.lead();
+        let _ = r.header().unwrap();
+        Package::new(r.header().unwrap())
+    }
+
+    #[test]
+    fn package_accessors() {
+        let pkg = package();
+        assert_eq!(pkg.name().unwrap(), "hardlink");
+        assert_eq!(pkg.epoch().unwrap(), Some(1));
+        assert_eq!(pkg.version().unwrap(), "1.0");
+        assert_eq!(pkg.release().unwrap(), "23.fc24");
+        assert_eq!(pkg.arch().unwrap(), "x86_64");
+        assert_eq!(pkg.nevra().unwrap(), "hardlink-1:1.0-23.fc24.x86_64");
+        assert_eq!(pkg.evr().unwrap().to_string(), "1:1.0-23.fc24");
+        assert_eq!(pkg.summary().unwrap(), "Create a tree of hardlinks");
+        assert_eq!(pkg.license().unwrap(), "GPLv2+");
+        assert_eq!(pkg.buildtime().unwrap(), 1454553868);
+        assert_eq!(pkg.size().unwrap(), 34701);
+        assert_eq!(pkg.sourcerpm().unwrap(), Some("hardlink-1.0-23.fc24.src.rpm"));
+        assert_eq!(pkg.packager().unwrap(), Some("Fedora Project"));
+        assert!(!pkg.is_source());
+        assert_eq!(pkg.files().unwrap().len(), 4);
+        assert_eq!(pkg.provides().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn package_missing_tags() {
+        let pkg = Package::new(Header::new());
+        match pkg.name() {
+            Err(RPMError::File(RPMFileError::MissingTag(tag))) => assert_eq!(tag, Tag::NAME),
+            r => panic!("unexpected result: {:?}", r),
+        }
+        assert_eq!(pkg.epoch().unwrap(), None);
+        assert_eq!(pkg.url().unwrap(), None);
+    }
+
+    #[test]
+    fn package_sort_by_evr() {
+        let pkg = |epoch: Option<u32>, version: &str, release: &str| {
+            let mut hdr = Header::new();
+            if let Some(e) = epoch {
+                hdr.insert(Tag::EPOCH as TagID, TagValue::Int32(vec![e]));
+            }
+            hdr.insert(Tag::VERSION as TagID, TagValue::String(vec![String::from(version)]));
+            hdr.insert(Tag::RELEASE as TagID, TagValue::String(vec![String::from(release)]));
+            Package::new(hdr)
+        };
+        let mut pkgs = [pkg(None, "1.10", "1"), pkg(Some(1), "0.9", "1"), package(),
+                        pkg(None, "1.9", "2"), pkg(Some(0), "1.9", "1"), pkg(None, "1.10~rc1", "1")];
+        pkgs.sort_by(Package::cmp_evr);
+        let sorted: Vec<String> = pkgs.iter().map(|p| p.evr().unwrap().to_string()).collect();
+        assert_eq!(sorted, vec!["0:1.9-1", "1.9-2", "1.10~rc1-1", "1.10-1", "1:0.9-1", "1:1.0-23.fc24"]);
+    }
+
+    #[test]
+    fn package_wrong_tag_type() {
+        let mut hdr = Header::new();
+        hdr.insert(Tag::NAME as TagID, TagValue::Int32(vec![1]));
+        hdr.insert(Tag::EPOCH as TagID, TagValue::String(vec![String::from("1")]));
+        let pkg = Package::new(hdr);
+        match pkg.name() {
+            Err(RPMError::File(RPMFileError::WrongTagType(tag))) => assert_eq!(tag, Tag::NAME),
+            r => panic!("unexpected result: {:?}", r),
+        }
+        // a wrong type isn't the same thing as a missing tag
+        match pkg.epoch() {
+            Err(RPMError::File(RPMFileError::WrongTagType(tag))) => assert_eq!(tag, Tag::EPOCH),
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+}
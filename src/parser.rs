@@ -19,12 +19,18 @@
 // the linter is pretty bad at dealing with "dead" code in macros, it seems
 #![allow(unused_variables)]
 
+use std::result;
 use std::string::String;
 use std::str::{from_utf8, FromStr};
-use nom::{be_u8, be_u16, be_u32, be_i32, be_u64, IResult};
+use nom::{be_u8, be_u16, be_u32, be_i32, be_u64, IResult, ErrorKind};
 
-use tag::{TagID, TagType, TagValue};
-use header::{Lead, Header};
+use tag::{TagType, TagValue};
+use header::{Lead, Header, TagEntry, is_region_tag};
+use borrowed::{HeaderRef, TagValueRef, BeArray, Strings, BigEndian};
+use error::{RPMError, RPMFileError, ParseError, Section};
+use Result;
+
+const SECTION_MAGIC: [u8; 3] = [0x8E, 0xAD, 0xE8];
 
 // structs that are part of the RPM header structure
 #[derive(Debug,PartialEq,Eq)]
@@ -33,17 +39,10 @@ pub struct HeaderSectionHeader {
     pub count: u32,
     pub size: u32,
 }
-#[derive(Debug,PartialEq,Eq)]
-struct TagEntry {
-    tagid: TagID,
-    tagtype: TagType,
-    offset: u32,
-    count: u32,
-}
 
 impl HeaderSectionHeader {
     pub fn datasize(&self) -> usize {
-        (16*self.count + self.size) as usize
+        16 * self.count as usize + self.size as usize
     }
     pub fn padsize(&self) -> usize {
         (if self.size % 8 != 0 {8-(self.size%8)} else {0}) as usize
@@ -110,37 +109,154 @@ named!(parse_tag_entry<TagEntry>,
 );
 
 // Here's the strategy for reading the Header:
-// * Peek ahead and read the data store
+// * Make sure the index and the data store are all there
 // * Iterate through the tag entries:
 //   * Read a tag entry
 //   * Read its value from the store
-//   * Return a (TagID, TagValue) pair
-// * Construct a HashMap<TagID, TagValue> from those pairs
-pub fn parse_section_data(i: &[u8], count: usize, size: usize) -> IResult<&[u8], Header> {
-    do_parse!(i,
-        // peek ahead and grab the store
-        store: peek!(do_parse!(take!(16*count) >> store:take!(size) >> (store))) >>
-        // parse each tag entry, grabbing its data from the store
-        pairs: count!(apply!(parse_tag, store), count) >>
-        // we're finished with the store now - consume it
-        take!(size) >>
-        // dump the output pairs into a Header
-        (pairs.into_iter().collect::<Header>())
-    )
+//   * If either of those fails, work out what's wrong with it
+// * Construct a Header from the entries and values, keeping them in order
+//
+// Offsets in the errors count from the start of `i`, and the section is
+// always Header; the caller knows where the section really is.
+pub fn parse_section_data(i: &[u8], count: usize, size: usize)
+        -> result::Result<(&[u8], Header), ParseError> {
+    let (rest, entries) = parse_index(i, count, size, |store, entry| match parse_tagval(store, entry) {
+        IResult::Done(_, value) => Some(value),
+        _ => None,
+    })?;
+    Ok((rest, Header::from_entries(entries)))
 }
 
-// these helpers are kinda gnarly, but that's partly because RPM is terrible
-fn parse_tag<'a>(i: &'a [u8], store: &'a [u8]) -> IResult<&'a [u8], (TagID, TagValue)> {
-    let (rest, tag) = try_parse!(i, parse_tag_entry);
-    let (_, val)    = try_parse!(store, apply!(parse_tagval, &tag));
-    IResult::Done(rest, (tag.tagid, val))
+// Same as parse_section_data, but borrowing the values from `i`
+pub fn parse_section_ref(i: &[u8], count: usize, size: usize)
+        -> result::Result<(&[u8], HeaderRef<'_>), ParseError> {
+    let (rest, entries) = parse_index(i, count, size, tagval_ref)?;
+    Ok((rest, HeaderRef::from_entries(entries)))
+}
+
+type Entries<V> = Vec<(TagEntry, V)>;
+
+// Read the index entries, getting each value out of the store with `value`
+fn parse_index<'a, V, F>(i: &'a [u8], count: usize, size: usize, value: F)
+        -> result::Result<(&'a [u8], Entries<V>), ParseError>
+        where F: Fn(&'a [u8], &TagEntry) -> Option<V> {
+    let bad = |kind, offset: usize| ParseError::new(kind, Section::Header, offset as u64);
+    let index_size = count.saturating_mul(16);
+    let total = index_size.saturating_add(size);
+    if total > i.len() {
+        return Err(bad(RPMFileError::Truncated, 0).sizes(total as u64, i.len() as u64));
+    }
+    let (index, store) = (&i[..index_size], &i[index_size..total]);
+    let mut entries = Vec::with_capacity(count);
+    for (n, raw) in index.chunks(16).enumerate() {
+        let entry = match parse_tag_entry(raw) {
+            IResult::Done(_, entry) => entry,
+            _ => {
+                let id = i32::from_be_bytes([raw[0], raw[1], raw[2], raw[3]]);
+                let ttype = u32::from_be_bytes([raw[4], raw[5], raw[6], raw[7]]);
+                return Err(bad(RPMFileError::BadValue("unknown tag type"), n * 16).tag(id, ttype));
+            },
+        };
+        match value(store, &entry) {
+            Some(value) => entries.push((entry, value)),
+            None => return Err(bad_value(store, &entry, n, index_size)),
+        }
+    }
+    Ok((&i[total..], entries))
+}
+
+// Same as parse_section_data, but only reading the index; the values are
+// left in the store for decode_tagval.
+pub fn parse_section_index(i: &[u8], count: usize, size: usize)
+        -> result::Result<(&[u8], Vec<TagEntry>), ParseError> {
+    let (rest, entries) = parse_index(i, count, size, |_, _| Some(()))?;
+    Ok((rest, entries.into_iter().map(|(entry, _)| entry).collect()))
+}
+
+// Decode the value of entry `n` (of a section whose index is `index_size`
+// bytes) out of the store.
+pub fn decode_tagval(store: &[u8], entry: &TagEntry, n: usize, index_size: usize)
+        -> result::Result<TagValue, ParseError> {
+    match parse_tagval(store, entry) {
+        IResult::Done(_, value) => Ok(value),
+        _ => Err(bad_value(store, entry, n, index_size)),
+    }
+}
+
+// The error for entry `n`, whose value couldn't be read
+fn bad_value(store: &[u8], entry: &TagEntry, n: usize, index_size: usize) -> ParseError {
+    let (msg, offset, sizes) = value_error(store, entry);
+    let offset = offset.map_or(n * 16, |o| index_size + o);
+    let err = ParseError::new(RPMFileError::BadValue(msg), Section::Header, offset as u64)
+        .tag(entry.tagid, entry.tagtype as u32);
+    match sizes {
+        Some((expected, actual)) => err.sizes(expected as u64, actual as u64),
+        None => err,
+    }
 }
 
+// Each item of this type takes at least this many bytes in the store
+fn min_size(ttype: TagType) -> usize {
+    match ttype {
+        TagType::NULL => 0,
+        TagType::INT16 => 2,
+        TagType::INT32 => 4,
+        TagType::INT64 => 8,
+        _ => 1,
+    }
+}
+
+// How many items a value has. A STRING is always just the one string,
+// whatever its count says, like rpm reads it.
+fn item_count(tag: &TagEntry) -> usize {
+    match tag.tagtype {
+        TagType::STRING => 1,
+        _ => tag.count as usize,
+    }
+}
+
+// Figure out why a value couldn't be read: what's wrong, where in the store
+// (if it's in the store at all), and the bytes it needed versus the size
+// of the store, if it didn't fit.
+fn value_error(store: &[u8], tag: &TagEntry) -> (&'static str, Option<usize>, Option<(usize, usize)>) {
+    let offset = tag.offset as usize;
+    if offset > store.len() {
+        return ("offset is past the end of the store", None, Some((offset, store.len())));
+    }
+    let needed = item_count(tag).saturating_mul(min_size(tag.tagtype)).saturating_add(offset);
+    if needed > store.len() {
+        return ("value runs past the end of the store", Some(offset), Some((needed, store.len())));
+    }
+    // must be a string, then
+    let mut pos = offset;
+    for _ in 0..item_count(tag) {
+        let len = match store[pos..].iter().position(|&b| b == 0) {
+            Some(len) => len,
+            None => return ("string runs past the end of the store", Some(pos), None),
+        };
+        if from_utf8(&store[pos..pos + len]).is_err() {
+            return ("string isn't valid UTF-8", Some(pos), None);
+        }
+        pos += len + 1;
+    }
+    ("unreadable value", Some(offset), None)
+}
+
+// these helpers are kinda gnarly, but that's partly because RPM is terrible
+
 // Pull the TagValue for the given TagEntry out of the store, consuming the
 // bytes read. (This may throw off your offsets; consider parse_tagval)
 fn parse_and_consume_tagval<'a>(store: &'a [u8], tag: &TagEntry) -> IResult<&'a [u8], TagValue> {
-    let count = tag.count as usize;
-    let i = &store[tag.offset as usize..];
+    let count = item_count(tag);
+    let i = match store.get(tag.offset as usize..) {
+        Some(i) => i,
+        None => return IResult::Error(ErrorKind::Eof),
+    };
+    // A count that couldn't possibly fit in the rest of the store is bogus
+    // (and we shouldn't allocate room for it).
+    if count.saturating_mul(min_size(tag.tagtype)) > i.len() {
+        return IResult::Error(ErrorKind::Eof);
+    }
     // TODO: benchmark this match block against the alt!(cond_reduce!(...)|) style
     match tag.tagtype {
         TagType::NULL   => value!(i, TagValue::Null),
@@ -155,12 +271,148 @@ fn parse_and_consume_tagval<'a>(store: &'a [u8], tag: &TagEntry) -> IResult<&'a
     }
 }
 
+// Borrow the value for the given TagEntry from the store, if it's all there
+// (and its strings are NUL-terminated UTF-8, like parse_tagval wants).
+fn tagval_ref<'a>(store: &'a [u8], tag: &TagEntry) -> Option<TagValueRef<'a>> {
+    let count = item_count(tag);
+    let i = store.get(tag.offset as usize..)?;
+    let fixed = |size: usize| i.get(..count.checked_mul(size)?);
+    Some(match tag.tagtype {
+        TagType::NULL   => TagValueRef::Null,
+        TagType::CHAR   => TagValueRef::Char(fixed(1)?),
+        TagType::INT8   => TagValueRef::Int8(fixed(1)?),
+        TagType::INT16  => TagValueRef::Int16(BeArray::new(fixed(2)?)),
+        TagType::INT32  => TagValueRef::Int32(BeArray::new(fixed(4)?)),
+        TagType::INT64  => TagValueRef::Int64(BeArray::new(fixed(8)?)),
+        TagType::BIN    => TagValueRef::Binary(fixed(1)?),
+        TagType::STRING | TagType::STRING_ARRAY | TagType::I18NSTRING => {
+            let mut len = 0;
+            for _ in 0..count {
+                len += i[len..].iter().position(|&b| b == 0)? + 1;
+            }
+            TagValueRef::String(Strings::new(from_utf8(&i[..len]).ok()?, count)?)
+        },
+    })
+}
+
 // Pull the TagValue for the given TagEntry out of the store.
 // Leaves the store untouched.
 fn parse_tagval<'a>(store: &'a [u8], tag: &TagEntry) -> IResult<&'a [u8], TagValue> {
     peek!(store, apply!(parse_and_consume_tagval, &tag))
 }
 
+/// How big a header section a Reader will read. Headers that claim to be
+/// bigger than this are rejected with `RPMFileError::HeaderSize` before
+/// anything gets allocated for them.
+///
+/// Also how deeply the rich dependencies in them may be nested; see
+/// `RichDep::parse_with_limits()`.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub struct Limits {
+    /// The most index entries a header may have.
+    pub max_entries: usize,
+    /// The biggest a header's data store may be, in bytes.
+    pub max_data: usize,
+    /// The most parentheses a rich dependency may be nested in.
+    pub max_rich_depth: usize,
+}
+
+impl Default for Limits {
+    /// The same limits rpm itself uses (see `hdrchkTags`/`hdrchkData`),
+    /// and room for far more nesting than any real dependency has.
+    fn default() -> Limits {
+        Limits {
+            max_entries: 0xffff,
+            max_data: 0x0fff_ffff,
+            max_rich_depth: 128,
+        }
+    }
+}
+
+// Parse a header section header, which starts at `base` and is in the given
+// section, and check it against the limits.
+pub(crate) fn check_section_header(raw: &[u8], section: Section, base: u64, limits: &Limits)
+        -> Result<HeaderSectionHeader> {
+    let bad = |kind, offset| ParseError::new(kind, section, base + offset);
+    if raw.len() < 0x10 {
+        return Err(RPMError::Parse(bad(RPMFileError::Truncated, 0).sizes(0x10, raw.len() as u64)));
+    }
+    let hdr: HeaderSectionHeader = match parse_section_header(raw) {
+        IResult::Done(_, hdr) if raw[..3] == SECTION_MAGIC => hdr,
+        _ => return Err(RPMError::Parse(bad(RPMFileError::BadMagic, 0))),
+    };
+    check_sizes(&hdr, section, base + 8, limits)?;
+    Ok(hdr)
+}
+
+// Don't believe a header if it says it's huge. `at` is where its entry
+// count is, with the data size right after it.
+fn check_sizes(hdr: &HeaderSectionHeader, section: Section, at: u64, limits: &Limits) -> Result<()> {
+    if hdr.count as usize > limits.max_entries {
+        let err = ParseError::new(RPMFileError::HeaderSize, section, at);
+        return Err(RPMError::Parse(err.sizes(limits.max_entries as u64, hdr.count as u64)));
+    }
+    if hdr.size as usize > limits.max_data {
+        let err = ParseError::new(RPMFileError::HeaderSize, section, at + 4);
+        return Err(RPMError::Parse(err.sizes(limits.max_data as u64, hdr.size as u64)));
+    }
+    Ok(())
+}
+
+// Parse the data that follows a header section header into a Header, and
+// check its region. The data (starting with the index) is at `data_at`.
+pub(crate) fn check_header(buf: &[u8], hdr: &HeaderSectionHeader, section: Section, data_at: u64)
+        -> Result<Header> {
+    let (_, header) = parse_section_data(buf, hdr.count as usize, hdr.size as usize)
+        .map_err(|e| RPMError::Parse(e.relative_to(section, data_at)))?;
+    let store_at = data_at + hdr.count as u64 * 16;
+    header.region_in(Some(hdr.size as usize))
+        .map_err(|e| region_error(e, section, store_at, header.entries().map(|(entry, _)| entry)))?;
+    Ok(header)
+}
+
+// Parse a header that's all there is in `buf`, with or without the section
+// magic in front of it (files have it; the rpmdb doesn't).
+pub(crate) fn parse_bare_header(buf: &[u8], magic: bool) -> Result<Header> {
+    let (section, limits) = (Section::Header, Limits::default());
+    let (hdr, data_at) = if magic {
+        (check_section_header(buf, section, 0, &limits)?, 0x10)
+    } else {
+        if buf.len() < 8 {
+            let err = ParseError::new(RPMFileError::Truncated, section, 0);
+            return Err(RPMError::Parse(err.sizes(8, buf.len() as u64)));
+        }
+        let hdr = HeaderSectionHeader {
+            version: 1,
+            count: u32::from_be_slice(&buf[..4]),
+            size: u32::from_be_slice(&buf[4..]),
+        };
+        check_sizes(&hdr, section, 0, &limits)?;
+        (hdr, 8)
+    };
+    // it should all be header
+    let end = data_at + hdr.datasize();
+    if buf.len() > end {
+        let err = ParseError::new(RPMFileError::HeaderSize, section, end as u64);
+        return Err(RPMError::Parse(err.sizes(end as u64, buf.len() as u64)));
+    }
+    check_header(&buf[data_at..], &hdr, section, data_at as u64)
+}
+
+// Put a region error where the region tag's value is, in a section whose
+// data store starts at `store_at`.
+pub(crate) fn region_error<'a, I>(e: RPMError, section: Section, store_at: u64, mut entries: I) -> RPMError
+        where I: Iterator<Item=&'a TagEntry> {
+    match e {
+        RPMError::File(kind) => RPMError::Parse(match entries.find(|e| is_region_tag(e.tagid)) {
+            Some(entry) => ParseError::new(kind, section, store_at + entry.offset as u64)
+                .tag(entry.tagid, entry.tagtype as u32),
+            None => ParseError::new(kind, section, store_at),
+        }),
+        e => e,
+    }
+}
+
 /*************************************************************
  * BELOW HERE BE TESTS!! WHEEEEE!
  *************************************************************/
@@ -169,7 +421,8 @@ fn parse_tagval<'a>(store: &'a [u8], tag: &TagEntry) -> IResult<&'a [u8], TagVal
 mod tests {
     use super::*;
     // XXX: Not sure why these need to be specifically used but...
-    use super::{TagEntry, parse_tag_entry, parse_tagval};
+    use super::{parse_tag_entry, parse_tagval};
+    use header::TagEntry;
     use header::Lead;
     use tag::{TagType, TagValue, TagID};
     use nom::{ErrorKind, Needed, IResult};
@@ -244,6 +497,70 @@ mod tests {
                    IResult::Done(ministore, TagValue::String(vec!(String::from("hardlink")))))
     }
 
+    #[test]
+    fn parse_tagval_out_of_bounds() {
+        let store = b"hello\0\0\0\0\0\0\x01";
+        let tag = |tagtype, offset, count| TagEntry { tagid: 1000, tagtype, offset, count };
+        assert_eq!(parse_tagval(store, &tag(TagType::INT32, 8, 1)),
+                   IResult::Done(&store[..], TagValue::Int32(vec![1])));
+        assert_eq!(parse_tagval(store, &tag(TagType::INT32, 13, 1)), IResult::Error(ErrorKind::Eof));
+        assert_eq!(parse_tagval(store, &tag(TagType::STRING, 0xffffffff, 1)), IResult::Error(ErrorKind::Eof));
+        assert_eq!(parse_tagval(store, &tag(TagType::INT64, 0, 2)), IResult::Error(ErrorKind::Eof));
+        assert_eq!(parse_tagval(store, &tag(TagType::BIN, 0, 0xffffffff)), IResult::Error(ErrorKind::Eof));
+        assert_eq!(parse_tagval(store, &tag(TagType::STRING_ARRAY, 0, 0xffffffff)),
+                   IResult::Error(ErrorKind::Eof));
+        // a string that runs off the end of the store
+        assert!(!parse_tagval(&store[..5], &tag(TagType::STRING, 0, 1)).is_done());
+    }
+
+    #[test]
+    fn parse_section_data_bad_sizes() {
+        let data = &BINRPM1[0x70..0x1578];
+        for &(count, size) in [(usize::MAX, 0), (0x10000, 0x1484), (8, 0x10000), (8, usize::MAX)].iter() {
+            let err = parse_section_data(data, count, size).unwrap_err();
+            match err.kind {
+                RPMFileError::Truncated => (),
+                ref e => panic!("unexpected error: {}", e),
+            }
+            assert_eq!((err.offset, err.actual), (0, Some(data.len() as u64)));
+        }
+        let h = HeaderSectionHeader { version: 1, count: u32::MAX, size: u32::MAX };
+        assert_eq!(h.datasize(), 16 * (u32::MAX as usize) + u32::MAX as usize);
+    }
+
+    // A section with a NAME, and the store with the name in it
+    fn section(ttype: u32, offset: u32, count: u32, store: &[u8]) -> Vec<u8> {
+        let mut data = Vec::new();
+        for n in [1000, ttype, offset, count].iter() {
+            data.extend_from_slice(&n.to_be_bytes());
+        }
+        data.extend_from_slice(store);
+        data
+    }
+
+    #[test]
+    fn parse_section_data_bad_values() {
+        let check = |data: &[u8], msg, offset, sizes| {
+            let err = parse_section_data(data, 1, data.len() - 16).unwrap_err();
+            match err.kind {
+                RPMFileError::BadValue(m) => assert_eq!(m, msg),
+                ref e => panic!("unexpected error: {}", e),
+            }
+            assert_eq!(err.tag, Some(1000));
+            assert_eq!(err.offset, offset);
+            assert_eq!(err.expected.zip(err.actual), sizes);
+        };
+        assert!(parse_section_data(&section(6, 0, 1, b"hello\0"), 1, 6).is_ok());
+        check(&section(8, 2, 2, b"hi\0\xff\0"), "string isn't valid UTF-8", 16 + 3, None);
+        check(&section(6, 1, 1, b"hello"), "string runs past the end of the store", 16 + 1, None);
+        check(&section(6, 9, 1, b"hello"), "offset is past the end of the store", 0, Some((9, 5)));
+        check(&section(4, 4, 1, b"hello"), "value runs past the end of the store", 16 + 4, Some((8, 5)));
+        check(&section(31, 0, 1, b"hello"), "unknown tag type", 0, None);
+        let err = parse_section_data(&section(31, 0, 1, b""), 1, 0).unwrap_err();
+        assert_eq!(err.tagtype, Some(31));
+        assert_eq!(err.to_string(), "Bad tag value: unknown tag type in header at offset 0x0, tag 1000 (type 31)");
+    }
+
     #[test]
     fn test_parse_header_ok() {
         let (_, h) = parse_section_header(&BINRPM1[0x60..0x70]).unwrap();
@@ -0,0 +1,189 @@
+/* payload.rs - compression and decompression for RPM payloads
+ *
+ * Copyright (c) 2017, Red Hat, Inc.
+ *
+ * This library is free software; you can redistribute it and/or modify it
+ * under the terms and conditions of the GNU Lesser General Public License
+ * as published by the Free Software Foundation; either version 2.1 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU Lesser General Public License for
+ * more details.
+ *
+ * Authors:
+ *   Will Woods <wwoods@redhat.com>
+ */
+use std::io;
+use std::io::Write;
+
+use bzip2;
+use bzip2::read::MultiBzDecoder;
+use bzip2::write::BzEncoder;
+use flate2;
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use xz2::read::XzDecoder;
+use xz2::write::XzEncoder;
+use xz2::stream::{Stream, LzmaOptions};
+use zstd::stream::read::Decoder as ZstdDecoder;
+use zstd::stream::encode_all as zstd_encode_all;
+
+use header::Header;
+use tag::{Tag, TagID, TagValue};
+use error::{RPMError, RPMFileError};
+use Result;
+
+/// The compression used for an RPM payload, as named by PAYLOADCOMPRESSOR.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum Compressor {
+    None,
+    Gzip,
+    Bzip2,
+    Xz,
+    Lzma,
+    Zstd,
+}
+
+impl Compressor {
+    /// Look up a compressor by the name RPM uses for it.
+    pub fn from_name(name: &str) -> Option<Compressor> {
+        match name {
+            "identity" => Some(Compressor::None),
+            "gzip"     => Some(Compressor::Gzip),
+            "bzip2"    => Some(Compressor::Bzip2),
+            "xz"       => Some(Compressor::Xz),
+            "lzma"     => Some(Compressor::Lzma),
+            "zstd"     => Some(Compressor::Zstd),
+            _          => None,
+        }
+    }
+
+    /// The name RPM uses for this compressor, for PAYLOADCOMPRESSOR.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Compressor::None  => "identity",
+            Compressor::Gzip  => "gzip",
+            Compressor::Bzip2 => "bzip2",
+            Compressor::Xz    => "xz",
+            Compressor::Lzma  => "lzma",
+            Compressor::Zstd  => "zstd",
+        }
+    }
+
+    /// The compression level `compress()` uses; it goes in PAYLOADFLAGS.
+    /// These are the levels rpm uses by default (or Fedora does, for zstd).
+    pub fn level(&self) -> u32 {
+        match *self {
+            Compressor::None => 0,
+            Compressor::Gzip | Compressor::Bzip2 => 9,
+            Compressor::Xz | Compressor::Lzma => 2,
+            Compressor::Zstd => 19,
+        }
+    }
+
+    /// Figure out the payload compressor from the main header.
+    /// Packages without PAYLOADCOMPRESSOR are gzipped, like rpm assumes.
+    // NOTE: PAYLOADFLAGS only holds the compression level (and thread count,
+    // for zstd) the package was built with; we don't need it to decompress.
+    pub fn from_header(hdr: &Header) -> Result<Compressor> {
+        match hdr.get(&(Tag::PAYLOADCOMPRESSOR as TagID)) {
+            None => Ok(Compressor::Gzip),
+            Some(TagValue::String(v)) if v.len() == 1 => {
+                Compressor::from_name(&v[0]).ok_or_else(||
+                    RPMError::File(RPMFileError::UnknownCompressor(v[0].clone())))
+            },
+            Some(_) => Err(RPMError::File(RPMFileError::BadHeader)),
+        }
+    }
+
+    /// Wrap the given (compressed) payload reader in a decompressor.
+    pub fn decompress<'a, R: io::Read + 'a>(&self, rdr: R) -> Result<Box<dyn io::Read + 'a>> {
+        Ok(match *self {
+            Compressor::None  => Box::new(rdr),
+            Compressor::Gzip  => Box::new(MultiGzDecoder::new(rdr)),
+            Compressor::Bzip2 => Box::new(MultiBzDecoder::new(rdr)),
+            Compressor::Xz    => Box::new(XzDecoder::new_multi_decoder(rdr)),
+            Compressor::Lzma  => {
+                let stream = Stream::new_lzma_decoder(u64::MAX).map_err(io::Error::from)?;
+                Box::new(XzDecoder::new_stream(rdr, stream))
+            },
+            Compressor::Zstd  => Box::new(ZstdDecoder::new(rdr)?),
+        })
+    }
+
+    /// Compress a payload.
+    pub fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let level = self.level();
+        Ok(match *self {
+            Compressor::None  => data.to_vec(),
+            Compressor::Gzip  => {
+                let mut enc = GzEncoder::new(Vec::new(), flate2::Compression::new(level));
+                enc.write_all(data)?;
+                enc.finish()?
+            },
+            Compressor::Bzip2 => {
+                let mut enc = BzEncoder::new(Vec::new(), bzip2::Compression::new(level));
+                enc.write_all(data)?;
+                enc.finish()?
+            },
+            Compressor::Xz    => {
+                let mut enc = XzEncoder::new(Vec::new(), level);
+                enc.write_all(data)?;
+                enc.finish()?
+            },
+            Compressor::Lzma  => {
+                let options = LzmaOptions::new_preset(level).map_err(io::Error::from)?;
+                let stream = Stream::new_lzma_encoder(&options).map_err(io::Error::from)?;
+                let mut enc = XzEncoder::new_stream(Vec::new(), stream);
+                enc.write_all(data)?;
+                enc.finish()?
+            },
+            Compressor::Zstd  => zstd_encode_all(data, level as i32)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Compressor;
+    use std::io::Read;
+    use header::Header;
+    use tag::{Tag, TagID, TagValue};
+    use error::{RPMError, RPMFileError};
+
+    fn hdr_with_compressor(name: &str) -> Header {
+        let mut hdr = Header::new();
+        hdr.insert(Tag::PAYLOADCOMPRESSOR as TagID, TagValue::String(vec![String::from(name)]));
+        hdr
+    }
+
+    #[test]
+    fn compressor_from_header() {
+        assert_eq!(Compressor::from_header(&hdr_with_compressor("xz")).unwrap(), Compressor::Xz);
+        assert_eq!(Compressor::from_header(&hdr_with_compressor("zstd")).unwrap(), Compressor::Zstd);
+        assert_eq!(Compressor::from_header(&Header::new()).unwrap(), Compressor::Gzip);
+    }
+
+    #[test]
+    fn compress_round_trip() {
+        let data = b"Hello, world! Hello, world! Hello, world!".repeat(100);
+        for &c in [Compressor::None, Compressor::Gzip, Compressor::Bzip2,
+                   Compressor::Xz, Compressor::Lzma, Compressor::Zstd].iter() {
+            assert_eq!(Compressor::from_name(c.name()), Some(c));
+            let compressed = c.compress(&data).unwrap();
+            let mut out = Vec::new();
+            c.decompress(&compressed[..]).unwrap().read_to_end(&mut out).unwrap();
+            assert_eq!(out, data, "{:?}", c);
+        }
+    }
+
+    #[test]
+    fn compressor_unknown() {
+        match Compressor::from_header(&hdr_with_compressor("lolzip")).unwrap_err() {
+            RPMError::File(RPMFileError::UnknownCompressor(ref name)) => assert_eq!(name, "lolzip"),
+            e => panic!("unexpected error: {}", e),
+        }
+    }
+}
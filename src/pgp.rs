@@ -0,0 +1,929 @@
+/* pgp.rs - just enough OpenPGP to check package signatures
+ *
+ * Copyright (c) 2017, Red Hat, Inc.
+ *
+ * This library is free software; you can redistribute it and/or modify it
+ * under the terms and conditions of the GNU Lesser General Public License
+ * as published by the Free Software Foundation; either version 2.1 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU Lesser General Public License for
+ * more details.
+ *
+ * Authors:
+ *   Will Woods <wwoods@redhat.com>
+ */
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use digest::DynDigest;
+use sha1::{Sha1, Digest};
+use md5::Md5;
+use sha2::{Sha224, Sha256, Sha384, Sha512};
+use rsa::{self, RsaPublicKey, RsaPrivateKey, Pkcs1v15Sign};
+use rsa::traits::PublicKeyParts;
+use dsa;
+use p256;
+use p384;
+use p521;
+use ed25519_dalek;
+use rsa::signature::{Signer, Verifier};
+use rsa::signature::hazmat::{PrehashSigner, PrehashVerifier};
+
+use verify::{hasher, PGPHASHALGO_MD5, PGPHASHALGO_SHA1, PGPHASHALGO_SHA224,
+             PGPHASHALGO_SHA256, PGPHASHALGO_SHA384, PGPHASHALGO_SHA512};
+use error::{RPMError, RPMFileError};
+use Result;
+
+// Packet tags (RFC 4880, 4.3)
+const PKT_SIGNATURE: u8 = 2;
+const PKT_SECRET_KEY: u8 = 5;
+const PKT_PUBLIC_KEY: u8 = 6;
+const PKT_SECRET_SUBKEY: u8 = 7;
+const PKT_PUBLIC_SUBKEY: u8 = 14;
+
+// The signature type for a signature over some binary data
+const SIGTYPE_BINARY: u8 = 0x00;
+
+// Public key algorithms (RFC 4880, 9.1, and RFC 6637)
+pub const PGPPUBKEYALGO_RSA: u8 = 1;
+pub const PGPPUBKEYALGO_RSA_SIGN: u8 = 3;
+pub const PGPPUBKEYALGO_DSA: u8 = 17;
+pub const PGPPUBKEYALGO_ECDSA: u8 = 19;
+pub const PGPPUBKEYALGO_EDDSA: u8 = 22;
+
+// Signature subpackets we care about
+const SUBPKT_CREATION_TIME: u8 = 2;
+const SUBPKT_ISSUER: u8 = 16;
+const SUBPKT_ISSUER_FPR: u8 = 33;
+
+// Curve OIDs, as they appear in key packets
+const OID_NIST_P256: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+const OID_NIST_P384: &[u8] = &[0x2b, 0x81, 0x04, 0x00, 0x22];
+const OID_NIST_P521: &[u8] = &[0x2b, 0x81, 0x04, 0x00, 0x23];
+const OID_ED25519: &[u8] = &[0x2b, 0x06, 0x01, 0x04, 0x01, 0xda, 0x47, 0x0f, 0x01];
+
+pub(crate) fn bad_pgp(msg: &'static str) -> RPMError {
+    RPMError::File(RPMFileError::BadPGP(msg))
+}
+
+/// Name of an OpenPGP public key algorithm, like rpm shows it.
+pub fn pubkey_algo_name(algo: u8) -> &'static str {
+    match algo {
+        PGPPUBKEYALGO_RSA | PGPPUBKEYALGO_RSA_SIGN => "RSA",
+        PGPPUBKEYALGO_DSA => "DSA",
+        PGPPUBKEYALGO_ECDSA => "ECDSA",
+        PGPPUBKEYALGO_EDDSA => "EdDSA",
+        _ => "UNKNOWN",
+    }
+}
+
+/// Name of an OpenPGP hash algorithm, like rpm shows it.
+pub fn hash_algo_name(algo: u8) -> &'static str {
+    match algo as u32 {
+        PGPHASHALGO_MD5 => "MD5",
+        PGPHASHALGO_SHA1 => "SHA1",
+        PGPHASHALGO_SHA224 => "SHA224",
+        PGPHASHALGO_SHA256 => "SHA256",
+        PGPHASHALGO_SHA384 => "SHA384",
+        PGPHASHALGO_SHA512 => "SHA512",
+        _ => "UNKNOWN",
+    }
+}
+
+// A little cursor for picking apart packets
+struct Bytes<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Bytes<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        if n > self.data.len() {
+            return Err(bad_pgp("truncated packet"));
+        }
+        let (head, rest) = self.data.split_at(n);
+        self.data = rest;
+        Ok(head)
+    }
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+    fn u16(&mut self) -> Result<usize> {
+        let b = self.take(2)?;
+        Ok((b[0] as usize) << 8 | b[1] as usize)
+    }
+    fn u32(&mut self) -> Result<u32> {
+        let b = self.take(4)?;
+        Ok((b[0] as u32) << 24 | (b[1] as u32) << 16 | (b[2] as u32) << 8 | b[3] as u32)
+    }
+    // A multiprecision integer: a 16-bit length in bits, then the bytes
+    fn mpi(&mut self) -> Result<&'a [u8]> {
+        let bits = self.u16()?;
+        self.take(bits.div_ceil(8))
+    }
+}
+
+// Split some binary OpenPGP data into (tag, body) packets.
+fn packets(data: &[u8]) -> Result<Vec<(u8, &[u8])>> {
+    let mut b = Bytes { data };
+    let mut packets = Vec::new();
+    while !b.data.is_empty() {
+        let ctb = b.u8()?;
+        if ctb & 0x80 == 0 {
+            return Err(bad_pgp("not an OpenPGP packet"));
+        }
+        let (tag, len) = if ctb & 0x40 == 0 {
+            // old format: the length type is in the low bits
+            let len = match ctb & 0x03 {
+                0 => b.u8()? as usize,
+                1 => b.u16()?,
+                2 => b.u32()? as usize,
+                _ => b.data.len(),
+            };
+            ((ctb >> 2) & 0x0f, len)
+        } else {
+            let len = match b.u8()? {
+                n @ 0..=191 => n as usize,
+                n @ 192..=223 => ((n as usize - 192) << 8) + b.u8()? as usize + 192,
+                255 => b.u32()? as usize,
+                _ => return Err(bad_pgp("partial body lengths aren't supported")),
+            };
+            (ctb & 0x3f, len)
+        };
+        packets.push((tag, b.take(len)?));
+    }
+    Ok(packets)
+}
+
+// CRC-24, for the armor checksum (RFC 4880, 6.1)
+fn crc24(data: &[u8]) -> u32 {
+    let mut crc: u32 = 0xb704ce;
+    for &byte in data {
+        crc ^= (byte as u32) << 16;
+        for _ in 0..8 {
+            crc <<= 1;
+            if crc & 0x1000000 != 0 {
+                crc ^= 0x1864cfb;
+            }
+        }
+    }
+    crc & 0xffffff
+}
+
+/// Decode the ASCII-armored blocks in `text` into binary OpenPGP data.
+pub fn dearmor(text: &str) -> Result<Vec<Vec<u8>>> {
+    let mut blocks = Vec::new();
+    let mut lines = text.lines().map(str::trim);
+    while let Some(line) = lines.next() {
+        if !line.starts_with("-----BEGIN PGP ") {
+            continue;
+        }
+        // skip the armor headers, up to the first blank line
+        for line in lines.by_ref() {
+            if line.is_empty() {
+                break;
+            }
+        }
+        let mut body = String::new();
+        let mut checksum = None;
+        for line in lines.by_ref() {
+            if line.starts_with("-----END PGP ") {
+                break;
+            } else if let Some(crc) = line.strip_prefix('=') {
+                checksum = Some(crc);
+            } else {
+                body.push_str(line);
+            }
+        }
+        let data = BASE64.decode(&body).map_err(|_| bad_pgp("bad armor"))?;
+        if let Some(checksum) = checksum {
+            let crc = BASE64.decode(checksum).map_err(|_| bad_pgp("bad armor checksum"))?;
+            let expected = crc24(&data);
+            if crc != [(expected >> 16) as u8, (expected >> 8) as u8, expected as u8] {
+                return Err(bad_pgp("armor checksum mismatch"));
+            }
+        }
+        blocks.push(data);
+    }
+    if blocks.is_empty() {
+        return Err(bad_pgp("no armored data found"));
+    }
+    Ok(blocks)
+}
+
+/// A parsed OpenPGP signature packet (version 3 or 4), like the ones in
+/// the RSA, DSA, PGP and GPG signature tags.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct PgpSignature {
+    pub version: u8,
+    pub sig_type: u8,
+    pub pubkey_algo: u8,
+    pub hash_algo: u8,
+    /// Creation time, in seconds since the epoch
+    pub created: u32,
+    /// ID of the key that made it
+    pub key_id: u64,
+    // The first two bytes of the digest, for a quick check
+    hash_left: [u8; 2],
+    // What gets hashed after the signed data
+    trailer: Vec<u8>,
+    mpis: Vec<Vec<u8>>,
+}
+
+impl PgpSignature {
+    /// Parse a binary signature packet.
+    pub fn parse(data: &[u8]) -> Result<PgpSignature> {
+        let body = match packets(data)?.first() {
+            Some(&(PKT_SIGNATURE, body)) => body,
+            _ => return Err(bad_pgp("not a signature packet")),
+        };
+        let mut b = Bytes { data: body };
+        let version = b.u8()?;
+        let (sig_type, pubkey_algo, hash_algo, created, key_id, trailer);
+        match version {
+            3 => {
+                if b.u8()? != 5 {
+                    return Err(bad_pgp("bad v3 signature"));
+                }
+                let hashed = b.take(5)?;
+                sig_type = hashed[0];
+                created = Bytes { data: &hashed[1..] }.u32()?;
+                key_id = key_id_from(b.take(8)?);
+                pubkey_algo = b.u8()?;
+                hash_algo = b.u8()?;
+                trailer = hashed.to_vec();
+            },
+            4 => {
+                sig_type = b.u8()?;
+                pubkey_algo = b.u8()?;
+                hash_algo = b.u8()?;
+                let hashed_len = b.u16()?;
+                let hashed = b.take(hashed_len)?;
+                let unhashed_len = b.u16()?;
+                let unhashed = b.take(unhashed_len)?;
+                let (mut ctime, mut issuer) = (None, None);
+                for (kind, critical, data) in subpackets_of(hashed)? {
+                    match kind {
+                        // anyone can change the unhashed area, so the
+                        // creation time only counts if it's hashed
+                        SUBPKT_CREATION_TIME if data.len() == 4 => {
+                            ctime = ctime.or(Some(Bytes { data }.u32()?));
+                        },
+                        SUBPKT_ISSUER | SUBPKT_ISSUER_FPR | SUBPKT_CREATION_TIME => (),
+                        // like rpm, refuse anything critical we don't understand
+                        _ if critical => return Err(bad_pgp("unknown critical subpacket")),
+                        _ => (),
+                    }
+                }
+                for &subpackets in &[hashed, unhashed] {
+                    for (kind, _, data) in subpackets_of(subpackets)? {
+                        match kind {
+                            SUBPKT_ISSUER if data.len() == 8 => issuer = issuer.or(Some(key_id_from(data))),
+                            SUBPKT_ISSUER_FPR if data.len() > 8 => {
+                                issuer = issuer.or(Some(key_id_from(&data[data.len()-8..])));
+                            },
+                            _ => (),
+                        }
+                    }
+                }
+                created = ctime.ok_or_else(|| bad_pgp("signature has no creation time"))?;
+                key_id = issuer.ok_or_else(|| bad_pgp("signature has no issuer"))?;
+                // the version through the hashed subpackets, then a final trailer
+                let hashed_part = &body[..6 + hashed_len];
+                let mut t = hashed_part.to_vec();
+                t.extend_from_slice(&[4, 0xff]);
+                t.extend_from_slice(&(hashed_part.len() as u32).to_be_bytes());
+                trailer = t;
+            },
+            _ => return Err(bad_pgp("unsupported signature version")),
+        }
+        // rpm only ever signs binary documents
+        if sig_type != SIGTYPE_BINARY {
+            return Err(bad_pgp("not a binary document signature"));
+        }
+        let left = b.take(2)?;
+        let mut mpis = Vec::new();
+        while !b.data.is_empty() {
+            mpis.push(b.mpi()?.to_vec());
+        }
+        Ok(PgpSignature {
+            version, sig_type, pubkey_algo, hash_algo, created, key_id,
+            hash_left: [left[0], left[1]], trailer, mpis,
+        })
+    }
+
+    /// A hasher for this signature's hash algorithm. Feed it the signed
+    /// data, then pass it to `verify()`.
+    pub fn hasher(&self) -> Result<Box<dyn DynDigest>> {
+        hasher(self.hash_algo as u32)
+    }
+
+    /// Check the signature, given a hasher that's been fed the signed data.
+    pub fn verify(&self, mut hasher: Box<dyn DynDigest>, key: &PublicKey) -> bool {
+        hasher.update(&self.trailer);
+        let digest = hasher.finalize();
+        if digest.len() < 2 || digest[..2] != self.hash_left || key.algo != self.pubkey_algo {
+            return false;
+        }
+        key.verify(self.hash_algo, &digest, &self.mpis)
+    }
+}
+
+// Split a subpacket area into (type, critical, data) triples.
+fn subpackets_of(data: &[u8]) -> Result<Vec<(u8, bool, &[u8])>> {
+    let mut b = Bytes { data };
+    let mut subpackets = Vec::new();
+    while !b.data.is_empty() {
+        let len = match b.u8()? {
+            n @ 0..=191 => n as usize,
+            n @ 192..=254 => ((n as usize - 192) << 8) + b.u8()? as usize + 192,
+            _ => b.u32()? as usize,
+        };
+        if len == 0 {
+            return Err(bad_pgp("bad subpacket"));
+        }
+        let sub = b.take(len)?;
+        // the high bit of the type is the "critical" flag
+        subpackets.push((sub[0] & 0x7f, sub[0] & 0x80 != 0, &sub[1..]));
+    }
+    Ok(subpackets)
+}
+
+fn key_id_from(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0, |id, &b| id << 8 | b as u64)
+}
+
+// RSA signatures are PKCS#1 v1.5, which needs to know the hash
+fn pkcs1_scheme(hash_algo: u8) -> Option<Pkcs1v15Sign> {
+    match hash_algo as u32 {
+        PGPHASHALGO_MD5 => Some(Pkcs1v15Sign::new::<Md5>()),
+        PGPHASHALGO_SHA1 => Some(Pkcs1v15Sign::new::<Sha1>()),
+        PGPHASHALGO_SHA224 => Some(Pkcs1v15Sign::new::<Sha224>()),
+        PGPHASHALGO_SHA256 => Some(Pkcs1v15Sign::new::<Sha256>()),
+        PGPHASHALGO_SHA384 => Some(Pkcs1v15Sign::new::<Sha384>()),
+        PGPHASHALGO_SHA512 => Some(Pkcs1v15Sign::new::<Sha512>()),
+        _ => None,
+    }
+}
+
+// Write a number as an MPI, dropping any leading zeros.
+fn write_mpi(out: &mut Vec<u8>, n: &[u8]) {
+    let n = &n[n.iter().take_while(|&&b| b == 0).count()..];
+    let bits = match n.first() {
+        Some(b) => n.len() * 8 - b.leading_zeros() as usize,
+        None => 0,
+    };
+    out.extend_from_slice(&(bits as u16).to_be_bytes());
+    out.extend_from_slice(n);
+}
+
+// Left-pad a number to the given size, since MPIs drop leading zeros.
+fn pad(bytes: &[u8], size: usize) -> Vec<u8> {
+    let mut padded = vec![0; size.saturating_sub(bytes.len())];
+    padded.extend_from_slice(bytes);
+    padded
+}
+
+#[derive(Clone)]
+enum KeyMaterial {
+    Rsa(RsaPublicKey),
+    Dsa(dsa::VerifyingKey),
+    P256(p256::ecdsa::VerifyingKey),
+    P384(p384::ecdsa::VerifyingKey),
+    P521(p521::ecdsa::VerifyingKey),
+    Ed25519(ed25519_dalek::VerifyingKey),
+    // A key type we can't check signatures with
+    Unsupported,
+}
+
+// Not all of the key types can be printed, and the numbers aren't much use
+impl fmt::Debug for KeyMaterial {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            KeyMaterial::Rsa(_) => "Rsa",
+            KeyMaterial::Dsa(_) => "Dsa",
+            KeyMaterial::P256(_) => "P256",
+            KeyMaterial::P384(_) => "P384",
+            KeyMaterial::P521(_) => "P521",
+            KeyMaterial::Ed25519(_) => "Ed25519",
+            KeyMaterial::Unsupported => "Unsupported",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// An OpenPGP public key (or subkey).
+#[derive(Debug,Clone)]
+pub struct PublicKey {
+    pub key_id: u64,
+    pub algo: u8,
+    /// Creation time, in seconds since the epoch
+    pub created: u32,
+    // v4 keys have a fingerprint; v3 keys don't
+    fingerprint: Option<Vec<u8>>,
+    material: KeyMaterial,
+}
+
+impl PublicKey {
+    // Parse the body of a public key packet.
+    fn parse(body: &[u8]) -> Result<PublicKey> {
+        PublicKey::parse_prefix(body).map(|(key, _)| key)
+    }
+
+    // Parse the public key at the start of a (public or secret) key
+    // packet, returning the rest of the packet.
+    fn parse_prefix(body: &[u8]) -> Result<(PublicKey, &[u8])> {
+        let mut b = Bytes { data: body };
+        let version = b.u8()?;
+        let created = b.u32()?;
+        if version == 2 || version == 3 {
+            let _validity = b.u16()?;
+        } else if version != 4 {
+            return Err(bad_pgp("unsupported key version"));
+        }
+        let algo = b.u8()?;
+        let bad_key = |_| bad_pgp("bad public key");
+        let material = match algo {
+            PGPPUBKEYALGO_RSA | PGPPUBKEYALGO_RSA_SIGN => {
+                let n = rsa::BigUint::from_bytes_be(b.mpi()?);
+                let e = rsa::BigUint::from_bytes_be(b.mpi()?);
+                KeyMaterial::Rsa(RsaPublicKey::new_with_max_size(n, e, 16384).map_err(|_| bad_pgp("bad RSA key"))?)
+            },
+            PGPPUBKEYALGO_DSA => {
+                let mut next = || b.mpi().map(dsa::BigUint::from_bytes_be);
+                let (p, q, g, y) = (next()?, next()?, next()?, next()?);
+                let components = dsa::Components::from_components(p, q, g).map_err(bad_key)?;
+                KeyMaterial::Dsa(dsa::VerifyingKey::from_components(components, y).map_err(bad_key)?)
+            },
+            PGPPUBKEYALGO_ECDSA | PGPPUBKEYALGO_EDDSA => {
+                let oid_len = b.u8()? as usize;
+                let oid = b.take(oid_len)?;
+                let point = b.mpi()?;
+                match (algo, oid) {
+                    (PGPPUBKEYALGO_ECDSA, OID_NIST_P256) =>
+                        KeyMaterial::P256(p256::ecdsa::VerifyingKey::from_sec1_bytes(point).map_err(bad_key)?),
+                    (PGPPUBKEYALGO_ECDSA, OID_NIST_P384) =>
+                        KeyMaterial::P384(p384::ecdsa::VerifyingKey::from_sec1_bytes(point).map_err(bad_key)?),
+                    (PGPPUBKEYALGO_ECDSA, OID_NIST_P521) =>
+                        KeyMaterial::P521(p521::ecdsa::VerifyingKey::from_sec1_bytes(point).map_err(bad_key)?),
+                    // EdDSA points have a 0x40 prefix
+                    (PGPPUBKEYALGO_EDDSA, OID_ED25519) if point.len() == 33 && point[0] == 0x40 => {
+                        let mut bytes = [0; 32];
+                        bytes.copy_from_slice(&point[1..]);
+                        KeyMaterial::Ed25519(ed25519_dalek::VerifyingKey::from_bytes(&bytes).map_err(bad_key)?)
+                    },
+                    _ => KeyMaterial::Unsupported,
+                }
+            },
+            _ => KeyMaterial::Unsupported,
+        };
+        let public = &body[..body.len() - b.data.len()];
+        let (key_id, fingerprint) = match material {
+            // v3 keys use the low 64 bits of the modulus
+            KeyMaterial::Rsa(ref key) if version < 4 => {
+                let n = key.n().to_bytes_be();
+                (key_id_from(&n[n.len().saturating_sub(8)..]), None)
+            },
+            _ => {
+                // v4 keys use the low 64 bits of the fingerprint
+                let mut sha1 = Sha1::new();
+                Digest::update(&mut sha1, [0x99, (public.len() >> 8) as u8, public.len() as u8]);
+                Digest::update(&mut sha1, public);
+                let fingerprint = sha1.finalize().to_vec();
+                (key_id_from(&fingerprint[12..]), Some(fingerprint))
+            },
+        };
+        Ok((PublicKey { key_id, algo, created, fingerprint, material }, b.data))
+    }
+
+    // Check a signature's MPIs over the given digest.
+    fn verify(&self, hash_algo: u8, digest: &[u8], mpis: &[Vec<u8>]) -> bool {
+        match (&self.material, mpis) {
+            (KeyMaterial::Rsa(key), [s]) => match pkcs1_scheme(hash_algo) {
+                Some(scheme) => key.verify(scheme, digest, &pad(s, key.size())).is_ok(),
+                None => false,
+            },
+            (KeyMaterial::Dsa(key), [r, s]) => {
+                let r = dsa::BigUint::from_bytes_be(r);
+                let s = dsa::BigUint::from_bytes_be(s);
+                match dsa::Signature::from_components(r, s) {
+                    Ok(sig) => key.verify_prehash(digest, &sig).is_ok(),
+                    Err(_) => false,
+                }
+            },
+            (KeyMaterial::P256(key), [r, s]) => {
+                let sig = [pad(r, 32), pad(s, 32)].concat();
+                p256::ecdsa::Signature::from_slice(&sig).map(|sig| key.verify_prehash(digest, &sig).is_ok()).unwrap_or(false)
+            },
+            (KeyMaterial::P384(key), [r, s]) => {
+                let sig = [pad(r, 48), pad(s, 48)].concat();
+                p384::ecdsa::Signature::from_slice(&sig).map(|sig| key.verify_prehash(digest, &sig).is_ok()).unwrap_or(false)
+            },
+            (KeyMaterial::P521(key), [r, s]) => {
+                let sig = [pad(r, 66), pad(s, 66)].concat();
+                p521::ecdsa::Signature::from_slice(&sig).map(|sig| key.verify_prehash(digest, &sig).is_ok()).unwrap_or(false)
+            },
+            (KeyMaterial::Ed25519(key), [r, s]) => {
+                // OpenPGP's EdDSA signs the digest, not the data itself
+                let sig = [pad(r, 32), pad(s, 32)].concat();
+                ed25519_dalek::Signature::from_slice(&sig).map(|sig| key.verify(digest, &sig).is_ok()).unwrap_or(false)
+            },
+            _ => false,
+        }
+    }
+}
+
+#[derive(Clone)]
+enum SecretMaterial {
+    Rsa(RsaPrivateKey),
+    Dsa(dsa::SigningKey),
+    P256(p256::ecdsa::SigningKey),
+    P384(p384::ecdsa::SigningKey),
+    P521(p521::ecdsa::SigningKey),
+    Ed25519(ed25519_dalek::SigningKey),
+}
+
+/// An OpenPGP secret key, for signing packages.
+///
+/// Only unprotected keys can be used, like the ones exported with
+/// `gpg --export-secret-keys` from a key that has no passphrase.
+#[derive(Clone)]
+pub struct SecretKey {
+    public: PublicKey,
+    material: SecretMaterial,
+}
+
+// Don't print the secret parts, even by accident
+impl fmt::Debug for SecretKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SecretKey").field("public", &self.public).finish()
+    }
+}
+
+impl SecretKey {
+    // Parse the body of a secret key packet. Returns None for keys that
+    // can't sign things, like an ECDH encryption subkey.
+    fn parse(body: &[u8]) -> Result<Option<SecretKey>> {
+        let (public, rest) = PublicKey::parse_prefix(body)?;
+        // don't look any further at keys we couldn't use anyway; they
+        // might well be encrypted, or in some format we don't know
+        if let KeyMaterial::Unsupported = public.material {
+            return Ok(None);
+        }
+        let mut b = Bytes { data: rest };
+        if b.u8()? != 0 {
+            return Err(bad_pgp("encrypted secret keys aren't supported"));
+        }
+        // a simple checksum of the secret MPIs follows them
+        let secret = b.data;
+        let bad_key = |_| bad_pgp("bad secret key");
+        let material = match public.material {
+            KeyMaterial::Rsa(ref key) => {
+                let mut next = || b.mpi().map(rsa::BigUint::from_bytes_be);
+                let (d, p, q, _u) = (next()?, next()?, next()?, next()?);
+                let key = RsaPrivateKey::from_components(key.n().clone(), key.e().clone(), d, vec![p, q]);
+                SecretMaterial::Rsa(key.map_err(|_| bad_pgp("bad RSA key"))?)
+            },
+            KeyMaterial::Dsa(ref key) => {
+                let x = dsa::BigUint::from_bytes_be(b.mpi()?);
+                SecretMaterial::Dsa(dsa::SigningKey::from_components(key.clone(), x).map_err(bad_key)?)
+            },
+            KeyMaterial::P256(_) =>
+                SecretMaterial::P256(p256::ecdsa::SigningKey::from_slice(&pad(b.mpi()?, 32)).map_err(bad_key)?),
+            KeyMaterial::P384(_) =>
+                SecretMaterial::P384(p384::ecdsa::SigningKey::from_slice(&pad(b.mpi()?, 48)).map_err(bad_key)?),
+            KeyMaterial::P521(_) =>
+                SecretMaterial::P521(p521::ecdsa::SigningKey::from_slice(&pad(b.mpi()?, 66)).map_err(bad_key)?),
+            KeyMaterial::Ed25519(_) => {
+                let mut seed = [0; 32];
+                let bytes = b.mpi()?;
+                if bytes.len() > 32 {
+                    return Err(bad_pgp("bad secret key"));
+                }
+                seed[32 - bytes.len()..].copy_from_slice(bytes);
+                SecretMaterial::Ed25519(ed25519_dalek::SigningKey::from_bytes(&seed))
+            },
+            KeyMaterial::Unsupported => unreachable!(),
+        };
+        let mpis = &secret[..secret.len() - b.data.len()];
+        let checksum = mpis.iter().fold(0u16, |sum, &byte| sum.wrapping_add(byte as u16));
+        if b.u16()? != checksum as usize {
+            return Err(bad_pgp("secret key checksum mismatch"));
+        }
+        Ok(Some(SecretKey { public, material }))
+    }
+
+    /// Read the first secret key (or subkey) that can make signatures from
+    /// some binary OpenPGP data.
+    pub fn from_bytes(data: &[u8]) -> Result<SecretKey> {
+        for (tag, body) in packets(data)? {
+            if tag == PKT_SECRET_KEY || tag == PKT_SECRET_SUBKEY {
+                if let Some(key) = SecretKey::parse(body)? {
+                    return Ok(key);
+                }
+            }
+        }
+        Err(bad_pgp("no usable secret key found"))
+    }
+
+    /// Read a secret key from an ASCII-armored key block.
+    pub fn from_armored(text: &str) -> Result<SecretKey> {
+        match dearmor(text)?.first() {
+            Some(data) => SecretKey::from_bytes(data),
+            None => Err(bad_pgp("no usable secret key found")),
+        }
+    }
+
+    /// Load a secret key from an ASCII-armored file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<SecretKey> {
+        SecretKey::from_armored(&fs::read_to_string(path)?)
+    }
+
+    /// The public half of the key, for checking its signatures.
+    pub fn public_key(&self) -> &PublicKey {
+        &self.public
+    }
+    pub fn key_id(&self) -> u64 {
+        self.public.key_id
+    }
+
+    /// Sign everything read from `data`, returning a binary v4 signature
+    /// packet like the ones in the RSA, DSA, PGP and GPG signature tags.
+    pub fn sign<R: io::Read>(&self, mut data: R, hash_algo: u8, created: u32) -> Result<Vec<u8>> {
+        let mut hasher = hasher(hash_algo as u32)?;
+        io::copy(&mut data, &mut HashWriter(&mut hasher))?;
+
+        // creation time and issuer fingerprint go in the hashed part
+        let mut hashed = vec![5, SUBPKT_CREATION_TIME];
+        hashed.extend_from_slice(&created.to_be_bytes());
+        if let Some(ref fingerprint) = self.public.fingerprint {
+            hashed.extend_from_slice(&[fingerprint.len() as u8 + 2, SUBPKT_ISSUER_FPR, 4]);
+            hashed.extend_from_slice(fingerprint);
+        }
+        let mut body = vec![4, SIGTYPE_BINARY, self.public.algo, hash_algo];
+        body.extend_from_slice(&(hashed.len() as u16).to_be_bytes());
+        body.extend(hashed);
+        let hashed_len = body.len();
+        hasher.update(&body);
+        hasher.update(&[4, 0xff]);
+        hasher.update(&(hashed_len as u32).to_be_bytes());
+        let digest = hasher.finalize();
+
+        // and the issuer key ID goes in the unhashed part
+        body.extend_from_slice(&[0, 10, 9, SUBPKT_ISSUER]);
+        body.extend_from_slice(&self.key_id().to_be_bytes());
+        body.extend_from_slice(&digest[..2]);
+        let failed = |_| bad_pgp("signing failed");
+        match self.material {
+            SecretMaterial::Rsa(ref key) => {
+                let scheme = pkcs1_scheme(hash_algo).ok_or(bad_pgp("unsupported hash algorithm"))?;
+                write_mpi(&mut body, &key.sign(scheme, &digest).map_err(|_| bad_pgp("signing failed"))?);
+            },
+            SecretMaterial::Dsa(ref key) => {
+                let sig: dsa::Signature = key.sign_prehash(&digest).map_err(failed)?;
+                write_mpi(&mut body, &sig.r().to_bytes_be());
+                write_mpi(&mut body, &sig.s().to_bytes_be());
+            },
+            SecretMaterial::P256(ref key) => {
+                let sig: p256::ecdsa::Signature = key.sign_prehash(&digest).map_err(failed)?;
+                write_mpi(&mut body, &sig.r().to_bytes());
+                write_mpi(&mut body, &sig.s().to_bytes());
+            },
+            SecretMaterial::P384(ref key) => {
+                let sig: p384::ecdsa::Signature = key.sign_prehash(&digest).map_err(failed)?;
+                write_mpi(&mut body, &sig.r().to_bytes());
+                write_mpi(&mut body, &sig.s().to_bytes());
+            },
+            SecretMaterial::P521(ref key) => {
+                let sig: p521::ecdsa::Signature = key.sign_prehash(&digest).map_err(failed)?;
+                write_mpi(&mut body, &sig.r().to_bytes());
+                write_mpi(&mut body, &sig.s().to_bytes());
+            },
+            SecretMaterial::Ed25519(ref key) => {
+                let sig = key.sign(&digest);
+                write_mpi(&mut body, sig.r_bytes());
+                write_mpi(&mut body, sig.s_bytes());
+            },
+        }
+        // an old-style packet header, with a two-byte length, like gpg makes
+        let mut packet = vec![0x80 | PKT_SIGNATURE << 2 | 1];
+        packet.extend_from_slice(&(body.len() as u16).to_be_bytes());
+        packet.extend(body);
+        Ok(packet)
+    }
+}
+
+// Lets io::copy() feed a hasher
+struct HashWriter<'a>(&'a mut Box<dyn DynDigest>);
+
+impl<'a> io::Write for HashWriter<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.update(buf);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A set of public keys to check signatures against, like rpm's keyring.
+#[derive(Debug,Clone,Default)]
+pub struct Keyring {
+    keys: Vec<PublicKey>,
+}
+
+impl Keyring {
+    pub fn new() -> Keyring {
+        Keyring::default()
+    }
+
+    /// Add the public keys (and subkeys) in some binary OpenPGP data.
+    /// Returns how many were added.
+    pub fn add_keys(&mut self, data: &[u8]) -> Result<usize> {
+        let mut count = 0;
+        for (tag, body) in packets(data)? {
+            if tag == PKT_PUBLIC_KEY || tag == PKT_PUBLIC_SUBKEY {
+                self.keys.push(PublicKey::parse(body)?);
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    /// Add the public keys in an ASCII-armored key block, like the ones in
+    /// /etc/pki/rpm-gpg. Returns how many were added.
+    pub fn add_armored(&mut self, text: &str) -> Result<usize> {
+        let mut count = 0;
+        for block in dearmor(text)? {
+            count += self.add_keys(&block)?;
+        }
+        Ok(count)
+    }
+
+    /// Load the keys from an ASCII-armored file.
+    pub fn load<P: AsRef<Path>>(&mut self, path: P) -> Result<usize> {
+        self.add_armored(&fs::read_to_string(path)?)
+    }
+
+    /// Find the key with the given ID.
+    pub fn find(&self, key_id: u64) -> Option<&PublicKey> {
+        self.keys.iter().find(|k| k.key_id == key_id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+}
+
+/// The result of checking a signature.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum Verdict {
+    /// The signature is good.
+    Ok,
+    /// The signature doesn't match the data (or the key).
+    Bad,
+    /// The key that made the signature isn't in the keyring.
+    NoKey,
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Verdict::Ok => write!(f, "OK"),
+            Verdict::Bad => write!(f, "BAD"),
+            Verdict::NoKey => write!(f, "NOKEY"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static RSA_KEY: &str = include_str!("../tests/keys/rsa.asc");
+    static EDDSA_KEY: &str = include_str!("../tests/keys/eddsa.asc");
+    static RSA_SIG: &[u8] = include_bytes!("../tests/keys/rsa.hdr.sig");
+
+    #[test]
+    fn crc24_check() {
+        // the initial value, and the usual check value for CRC-24/OpenPGP
+        assert_eq!(crc24(b""), 0xb704ce);
+        assert_eq!(crc24(b"123456789"), 0x21cf02);
+    }
+
+    #[test]
+    fn load_keys() {
+        let mut keyring = Keyring::new();
+        assert_eq!(keyring.add_armored(RSA_KEY).unwrap(), 1);
+        assert_eq!(keyring.add_armored(EDDSA_KEY).unwrap(), 1);
+        let rsa = keyring.find(0xd519a4b109b0e0d9).unwrap();
+        assert_eq!(rsa.algo, PGPPUBKEYALGO_RSA);
+        let eddsa = keyring.find(0xbb51f3d433ce5a5e).unwrap();
+        assert_eq!(eddsa.algo, PGPPUBKEYALGO_EDDSA);
+        assert!(keyring.find(0x1234).is_none());
+    }
+
+    #[test]
+    fn bad_armor() {
+        assert!(dearmor("no keys here").is_err());
+        let broken = RSA_KEY.replacen("m", "n", 1);
+        assert!(Keyring::new().add_armored(&broken).is_err());
+    }
+
+    #[test]
+    fn parse_v4_signature() {
+        let sig = PgpSignature::parse(RSA_SIG).unwrap();
+        assert_eq!(sig.version, 4);
+        assert_eq!(sig.pubkey_algo, PGPPUBKEYALGO_RSA);
+        assert_eq!(sig.hash_algo as u32, PGPHASHALGO_SHA256);
+        assert_eq!(sig.key_id, 0xd519a4b109b0e0d9);
+        assert_eq!(sig.created, 1792282263);
+        // the same signature, as a text document signature instead
+        let mut text_sig = RSA_SIG.to_vec();
+        // the type comes right after the 3-byte packet header and the version
+        text_sig[4] = 0x01;
+        assert!(PgpSignature::parse(&text_sig).is_err());
+    }
+
+    // A v4 RSA signature packet with the given subpacket areas
+    fn v4_signature(hashed: &[u8], unhashed: &[u8]) -> Vec<u8> {
+        let mut body = vec![4, SIGTYPE_BINARY, PGPPUBKEYALGO_RSA, PGPHASHALGO_SHA256 as u8];
+        body.extend_from_slice(&(hashed.len() as u16).to_be_bytes());
+        body.extend_from_slice(hashed);
+        body.extend_from_slice(&(unhashed.len() as u16).to_be_bytes());
+        body.extend_from_slice(unhashed);
+        body.extend_from_slice(&[0x12, 0x34, 0, 8, 0xff]);
+        let mut packet = vec![0x89];
+        packet.extend_from_slice(&(body.len() as u16).to_be_bytes());
+        packet.extend(body);
+        packet
+    }
+
+    #[test]
+    fn parse_v4_subpackets() {
+        let created = [5, SUBPKT_CREATION_TIME, 0x5a, 0, 0, 0];
+        let issuer = [9, SUBPKT_ISSUER, 1, 2, 3, 4, 5, 6, 7, 8];
+        let sig = PgpSignature::parse(&v4_signature(&created, &issuer)).unwrap();
+        assert_eq!((sig.created, sig.key_id), (0x5a000000, 0x0102030405060708));
+
+        // a creation time in the unhashed area doesn't count
+        let mut unhashed = issuer.to_vec();
+        unhashed.extend_from_slice(&created);
+        assert!(PgpSignature::parse(&v4_signature(&[], &unhashed)).is_err());
+
+        // critical subpackets are fine if we know them, but not otherwise
+        let mut hashed = created.to_vec();
+        hashed[1] |= 0x80;
+        assert!(PgpSignature::parse(&v4_signature(&hashed, &issuer)).is_ok());
+        hashed.extend_from_slice(&[2, 0x80 | 100, 0]);
+        assert!(PgpSignature::parse(&v4_signature(&hashed, &issuer)).is_err());
+        let mut unhashed = issuer.to_vec();
+        unhashed.extend_from_slice(&[2, 0x80 | 100, 0]);
+        assert!(PgpSignature::parse(&v4_signature(&created, &unhashed)).is_ok());
+    }
+
+    #[test]
+    fn sign_and_verify() {
+        for name in ["rsa", "dsa", "ecdsa", "eddsa"].iter() {
+            let key = SecretKey::load(format!("tests/keys/{}.sec.asc", name)).unwrap();
+            let mut keyring = Keyring::new();
+            keyring.load(format!("tests/keys/{}.asc", name)).unwrap();
+            assert!(keyring.find(key.key_id()).is_some(), "{} key", name);
+
+            let packet = key.sign(&b"some data"[..], PGPHASHALGO_SHA256 as u8, 1500000000).unwrap();
+            let sig = PgpSignature::parse(&packet).unwrap();
+            assert_eq!((sig.version, sig.key_id, sig.created), (4, key.key_id(), 1500000000));
+            let pubkey = keyring.find(sig.key_id).unwrap();
+            let mut hasher = sig.hasher().unwrap();
+            hasher.update(b"some data");
+            assert!(sig.verify(hasher, pubkey), "{} signature", name);
+            let mut hasher = sig.hasher().unwrap();
+            hasher.update(b"other data");
+            assert!(!sig.verify(hasher, pubkey), "{} signature", name);
+        }
+    }
+
+    #[test]
+    fn secret_key_checks() {
+        // a public key isn't a secret key
+        assert!(SecretKey::from_armored(RSA_KEY).is_err());
+        let text = fs::read_to_string("tests/keys/eddsa.sec.asc").unwrap();
+        let key = SecretKey::from_armored(&text).unwrap();
+        assert_eq!(key.key_id(), 0xbb51f3d433ce5a5e);
+        // an encrypted ECDH (18) subkey in front of it gets skipped
+        let mut data = vec![0x9c, 7, 4, 0, 0, 0, 0, 18, 0xfe];
+        data.extend_from_slice(&dearmor(&text).unwrap()[0]);
+        assert_eq!(SecretKey::from_bytes(&data).unwrap().key_id(), 0xbb51f3d433ce5a5e);
+        // the secret parts stay out of debug output
+        assert!(format!("{:?}", key).starts_with("SecretKey { public: PublicKey {"));
+    }
+}
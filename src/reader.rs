@@ -19,17 +19,75 @@ use std::io::prelude::*;
 use std::io;
 use std::fs;
 use std::path::Path;
+use nom::IResult;
+use memmap2::Mmap;
 
 use header::{Lead, Header};
-use parser::{HeaderSectionHeader, parse_lead, parse_section_header, parse_section_data};
-use error::RPMError;
+use borrowed::HeaderRef;
+use lazy::LazyHeader;
+use signature::SignatureHeader;
+use parser::{HeaderSectionHeader, parse_lead, parse_section_ref, check_section_header, check_header, region_error};
+pub use parser::Limits;
+use payload::Compressor;
+use archive::Archive;
+use error::{RPMError, RPMFileError, ParseError, Section};
 use Result;
 
+const LEAD_MAGIC: [u8; 4] = [0xED, 0xAB, 0xEE, 0xDB];
+// The lead's a fixed size, so the signature header always starts here
+const SIGNATURE_OFFSET: u64 = 0x60;
+
+/// Where each part of an RPM file starts, in bytes from the start of the
+/// file. See `Reader::offsets()`.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub struct Offsets {
+    pub lead: u64,
+    pub signature: u64,
+    pub header: u64,
+    /// The (still compressed) payload
+    pub payload: u64,
+}
+
 /// An RPM reader.
 pub struct Reader<R: io::Read> {
     rdr: R,
     did_sig: bool,
     did_hdr: bool,
+    limits: Limits,
+    pos: u64,
+    // where the main header and payload start, once we know
+    header_at: Option<u64>,
+    payload_at: Option<u64>,
+}
+
+// Read as much of `buf` as the reader has, returning how much that was.
+pub(crate) fn read_full<R: io::Read>(rdr: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+        match rdr.read(&mut buf[n..]) {
+            Ok(0) => break,
+            Ok(len) => n += len,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(n)
+}
+
+// Parse a lead, saying what's wrong with it if it's bad
+pub(crate) fn check_lead(buf: &[u8]) -> Result<Lead> {
+    let bad = |kind, offset| ParseError::new(kind, Section::Lead, offset);
+    if buf.len() < 0x60 {
+        return Err(RPMError::Parse(bad(RPMFileError::Truncated, 0).sizes(0x60, buf.len() as u64)));
+    }
+    if buf[..4] != LEAD_MAGIC {
+        return Err(RPMError::Parse(bad(RPMFileError::BadMagic, 0)));
+    }
+    match parse_lead(buf) {
+        IResult::Done(_, lead) => Ok(lead),
+        // the magic's fine and the rest is fixed-size, so it's the name
+        _ => Err(RPMError::Parse(bad(RPMFileError::BadHeader, 10))),
+    }
 }
 
 impl<R:io::Read> Reader<R> {
@@ -40,45 +98,284 @@ impl<R:io::Read> Reader<R> {
             rdr: rdr,
             did_sig: false,
             did_hdr: false,
+            limits: Limits::default(),
+            pos: 0,
+            header_at: None,
+            payload_at: None,
         }
     }
+    /// Use the given size limits for header sections, instead of the
+    /// defaults.
+    pub fn with_limits(mut self, limits: Limits) -> Reader<R> {
+        self.limits = limits;
+        self
+    }
     /// Parse the rpm Lead.
     pub fn lead(&mut self) -> Result<Lead> {
+        self.raw_lead().and_then(|buf| check_lead(&buf))
+    }
+    // Read the lead without parsing it
+    pub(crate) fn raw_lead(&mut self) -> Result<[u8; 0x60]> {
         let mut buf = [0;0x60];
-        try!(self.rdr.read_exact(&mut buf));
-        Ok(try!(parse_lead(&buf).to_result()))
+        let n = self.read(&mut buf)?;
+        if n < buf.len() {
+            check_lead(&buf[..n])?; // which says it's truncated
+        }
+        Ok(buf)
     }
-    // Grab a section header, so we can figure out how much to read
-    fn section_header(&mut self) -> Result<HeaderSectionHeader> {
-        let mut buf = [0;0x10];
-        try!(self.rdr.read_exact(&mut buf));
-        Ok(try!(parse_section_header(&buf).to_result()))
+    // Read into `buf`, keeping track of where we are; returns how much
+    // there was to read.
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let n = read_full(&mut self.rdr, buf)?;
+        self.pos += n as u64;
+        Ok(n)
     }
-    // Read and parse an RPM Header section.
+    /// Read and parse the signature header, which comes right after the
+    /// lead.
+    pub fn signature(&mut self) -> Result<SignatureHeader> {
+        if self.did_sig {
+            return Err(RPMError::OutOfOrder);
+        }
+        self.header().map(SignatureHeader::new)
+    }
+    /// Read and parse the next RPM Header section. The first one is the
+    /// signature header, which is better read with `signature()`, since
+    /// its tags aren't numbered like the main header's.
     pub fn header(&mut self) -> Result<Header> {
-        // grab the header section header
-        let hdr = try!(self.section_header());
-        // Figure out how much data to read.
+        self.raw_header().map(|(hdr, _)| hdr)
+    }
+    /// Like `header()`, but also return the raw bytes of the section as
+    /// they are in the file (minus any padding). That's what the digests
+    /// in the signature header are computed over.
+    ///
+    /// Headers with a bad region (see `Header::region()`) aren't read, and
+    /// neither are headers bigger than the Reader's `Limits`.
+    pub fn raw_header(&mut self) -> Result<(Header, Vec<u8>)> {
+        let (section, base) = (self.next_section(), self.pos);
+        let (mut raw, hdr, datasize) = self.section_header()?;
+        let mut buf = self.section_data(datasize)?;
+        let header = check_header(&buf, &hdr, section, base + 0x10)?;
+        buf.truncate(hdr.datasize());
+        raw.extend(buf);
+        Ok((header, raw))
+    }
+    /// Like `header()`, but only the section's index is read up front;
+    /// each value is decoded when it's first asked for. See `LazyHeader`.
+    pub fn lazy_header(&mut self) -> Result<LazyHeader> {
+        let (section, base) = (self.next_section(), self.pos);
+        let (_, hdr, datasize) = self.section_header()?;
+        let buf = self.section_data(datasize)?;
+        let header = LazyHeader::from_section(buf, hdr.count as usize, hdr.size as usize)
+            .map_err(|e| match e {
+                RPMError::Parse(e) => RPMError::Parse(e.relative_to(section, base + 0x10)),
+                e => e,
+            })?
+            .located(section, base + 0x10);
+        let store_at = base + 0x10 + hdr.count as u64 * 16;
+        header.region().map_err(|e| region_error(e, section, store_at, header.entries()))?;
+        Ok(header)
+    }
+    // Read the data that follows a section header. The buffer only grows
+    // as the data actually shows up, so a short file can't make us allocate
+    // the whole thing.
+    fn section_data(&mut self, datasize: usize) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        (&mut self.rdr).take(datasize as u64).read_to_end(&mut buf)?;
+        self.pos += buf.len() as u64;
+        Ok(buf)
+    }
+    // Read the header section header, and figure out how much data follows
+    // it (counting the signature header's padding).
+    fn section_header(&mut self) -> Result<(Vec<u8>, HeaderSectionHeader, usize)> {
+        let mut raw = vec![0;0x10];
+        let n = self.read(&mut raw)?;
+        raw.truncate(n);
+        self.got_section_header(raw)
+    }
+    // The rest of `section_header()`, for a section header we've just read.
+    fn got_section_header(&mut self, raw: Vec<u8>) -> Result<(Vec<u8>, HeaderSectionHeader, usize)> {
+        let (section, base) = (self.next_section(), self.pos - raw.len() as u64);
+        let hdr = check_section_header(&raw, section, base, &self.limits)?;
         // If this is the signature header, pad to an 8-byte-aligned size
         let padsize = if !self.did_sig { hdr.padsize() } else { 0 };
-        let datasize = hdr.datasize() + padsize;
-        // Okay, make a buffer and fill it up
-        let mut buf = Vec::with_capacity(datasize);
-        try!((&mut self.rdr).take(datasize as u64).read_to_end(&mut buf));
-        // Mark whether that was the sig or the hdr section
+        // Mark whether this is the sig or the hdr section
         if !self.did_sig { self.did_sig = true } else { self.did_hdr = true };
-        // And now: parse the buffer into the Header we're returning
-        let count = hdr.count as usize;
-        let size = hdr.size as usize;
-        // XXX: can't do try!() here without type inference probs?
-        parse_section_data(&buf, count, size).to_result().map_err(RPMError::from)
+        let datasize = hdr.datasize() + padsize;
+        // and remember where the next section starts
+        let end = base + 0x10 + datasize as u64;
+        match section {
+            Section::Signature => self.header_at = Some(end),
+            _ => {
+                self.header_at = Some(base);
+                self.payload_at = Some(end);
+            }
+        }
+        Ok((raw, hdr, datasize))
+    }
+    // Which header section is up next
+    fn next_section(&self) -> Section {
+        if !self.did_sig { Section::Signature } else { Section::Header }
+    }
+    /// Get the (still compressed) payload, which starts right after the
+    /// main header. Both header sections must have been read first.
+    pub fn payload(&mut self) -> Result<&mut R> {
+        if !(self.did_sig && self.did_hdr) {
+            return Err(RPMError::OutOfOrder);
+        }
+        Ok(&mut self.rdr)
+    }
+    /// Get the payload, decompressed according to the PAYLOADCOMPRESSOR
+    /// in the given main header.
+    pub fn decompressed_payload<'a>(&'a mut self, hdr: &Header) -> Result<Box<dyn io::Read + 'a>> {
+        let compressor = Compressor::from_header(hdr)?;
+        compressor.decompress(self.payload()?)
+    }
+    /// Get the cpio archive inside the payload, so the files can be read.
+    pub fn archive<'a>(&'a mut self, hdr: &Header) -> Result<Archive<Box<dyn io::Read + 'a>>> {
+        let payload = self.decompressed_payload(hdr)?;
+        Archive::new(payload, hdr)
+    }
+    /// Read a file that's just headers, one after another, with no lead or
+    /// signature header: a bare header file, or an old-style hdlist. Each
+    /// one needs its section magic (see `Header::from_blob()` for headers
+    /// from the rpmdb, which don't have it).
+    pub fn headers(mut self) -> Headers<R> {
+        // no signature header means no padding
+        self.did_sig = true;
+        Headers { rdr: self, done: false }
+    }
+}
+
+/// An iterator over the headers in a file of headers; see
+/// `Reader::headers()`. It stops at the end of the file, or after the
+/// first error.
+pub struct Headers<R: io::Read> {
+    rdr: Reader<R>,
+    done: bool,
+}
+
+impl<R: io::Read> Headers<R> {
+    // Read the next header, if the file doesn't end first
+    fn next_header(&mut self) -> Result<Option<Header>> {
+        let r = &mut self.rdr;
+        let base = r.pos;
+        let mut raw = vec![0;0x10];
+        let n = r.read(&mut raw)?;
+        if n == 0 {
+            return Ok(None);
+        }
+        raw.truncate(n);
+        let (_, hdr, datasize) = r.got_section_header(raw)?;
+        let buf = r.section_data(datasize)?;
+        check_header(&buf, &hdr, Section::Header, base + 0x10).map(Some)
+    }
+}
+
+impl<R: io::Read> Iterator for Headers<R> {
+    type Item = Result<Header>;
+    fn next(&mut self) -> Option<Result<Header>> {
+        if self.done {
+            return None;
+        }
+        let next = self.next_header();
+        self.done = !matches!(next, Ok(Some(_)));
+        next.transpose()
+    }
+}
+
+impl<R: io::Read + io::Seek> Reader<R> {
+    /// Find where each section of the file starts. Whatever the Reader has
+    /// already read is remembered; the rest is found by reading just the
+    /// section headers and seeking over their data, so the payload is never
+    /// read. The Reader is left where it was.
+    ///
+    /// The offsets are from the start of the stream, and the seeking
+    /// methods expect the RPM to start there too.
+    pub fn offsets(&mut self) -> Result<Offsets> {
+        let saved = (self.pos, self.did_sig, self.did_hdr);
+        let found = self.find_offsets();
+        // put things back, whether or not that worked
+        self.seek(saved.0)?;
+        self.did_sig = saved.1;
+        self.did_hdr = saved.2;
+        found
+    }
+    fn find_offsets(&mut self) -> Result<Offsets> {
+        let header = match self.header_at {
+            Some(at) => at,
+            None => self.skip_section(SIGNATURE_OFFSET, Section::Signature)?,
+        };
+        let payload = match self.payload_at {
+            Some(at) => at,
+            None => self.skip_section(header, Section::Header)?,
+        };
+        Ok(Offsets { lead: 0, signature: SIGNATURE_OFFSET, header, payload })
+    }
+    // Read the section header at `at`, returning where the section ends
+    fn skip_section(&mut self, at: u64, section: Section) -> Result<u64> {
+        self.seek(at)?;
+        self.did_sig = section != Section::Signature;
+        let (_, _, datasize) = self.section_header()?;
+        Ok(at + 0x10 + datasize as u64)
+    }
+    /// Move to the start of the given section, so that's what gets read
+    /// next: `seek_to(Section::Header)` and then `header()` reads the main
+    /// header, even if the payload has already been read. For
+    /// `Section::Payload`, that's the start of the compressed payload, as
+    /// returned by `payload()`.
+    pub fn seek_to(&mut self, section: Section) -> Result<()> {
+        let at = match section {
+            Section::Lead => 0,
+            Section::Signature => SIGNATURE_OFFSET,
+            Section::Header => self.offsets()?.header,
+            Section::Payload => self.offsets()?.payload,
+        };
+        self.seek(at)?;
+        self.did_sig = section == Section::Header || section == Section::Payload;
+        self.did_hdr = section == Section::Payload;
+        Ok(())
+    }
+    /// Skip to the end of the payload without reading it, returning how
+    /// big it is.
+    pub fn skip_payload(&mut self) -> Result<u64> {
+        let payload = self.offsets()?.payload;
+        self.pos = self.rdr.seek(io::SeekFrom::End(0))?;
+        self.did_sig = true;
+        self.did_hdr = true;
+        Ok(self.pos.saturating_sub(payload))
+    }
+    fn seek(&mut self, pos: u64) -> Result<()> {
+        self.pos = self.rdr.seek(io::SeekFrom::Start(pos))?;
+        Ok(())
+    }
+}
+
+impl<T: AsRef<[u8]>> Reader<io::Cursor<T>> {
+    /// Like `header()`, but the values are borrowed from the Reader's
+    /// buffer instead of being copied out of it, which is a lot quicker.
+    /// Use `HeaderRef::to_header()` to get a `Header` from it.
+    pub fn header_ref(&mut self) -> Result<HeaderRef<'_>> {
+        let (section, base) = (self.next_section(), self.pos);
+        let (_, hdr, datasize) = self.section_header()?;
+        // take the data straight out of the buffer
+        let start = self.rdr.position() as usize;
+        let len = self.rdr.get_ref().as_ref().len().saturating_sub(start).min(datasize);
+        self.rdr.set_position((start + len) as u64);
+        self.pos += len as u64;
+        let buf = &self.rdr.get_ref().as_ref()[start..start + len];
+        let (_, header) = parse_section_ref(buf, hdr.count as usize, hdr.size as usize)
+            .map_err(|e| RPMError::Parse(e.relative_to(section, base + 0x10)))?;
+        let store_at = base + 0x10 + hdr.count as u64 * 16;
+        header.region_in(Some(hdr.size as usize))
+            .map_err(|e| region_error(e, section, store_at, header.entries().map(|(entry, _)| entry)))?;
+        Ok(header)
     }
 }
 
 impl Reader<fs::File> {
     /// Creates a RPM reader for the RPM at the path given.
     pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Reader<fs::File>> {
-        Ok(Reader::from_reader(try!(fs::File::open(path))))
+        Ok(Reader::from_reader(fs::File::open(path)?))
     }
 }
 
@@ -96,12 +393,38 @@ impl Reader<io::Cursor<Vec<u8>>> {
     }
 }
 
+impl<'a> Reader<io::Cursor<&'a [u8]>> {
+    /// Creates a RPM reader for an in-memory buffer, without copying it.
+    pub fn from_slice(bytes: &'a [u8]) -> Reader<io::Cursor<&'a [u8]>> {
+        Reader::from_reader(io::Cursor::new(bytes))
+    }
+}
+
+impl Reader<io::Cursor<Mmap>> {
+    /// Creates a RPM reader that memory-maps the RPM at the path given,
+    /// so `header_ref()` can read headers straight out of the file.
+    ///
+    /// # Safety
+    ///
+    /// The file mustn't be changed (or truncated) by anything, in this
+    /// process or any other, for as long as the Reader (or anything
+    /// borrowed from it) is around. If it is, the mapped bytes change out
+    /// from under the Reader, which is undefined behavior; reads can
+    /// return garbage or crash the process with SIGBUS.
+    pub unsafe fn from_mmap<P: AsRef<Path>>(path: P) -> Result<Reader<io::Cursor<Mmap>>> {
+        let file = fs::File::open(path)?;
+        let map = Mmap::map(&file)?;
+        Ok(Reader::from_reader(io::Cursor::new(map)))
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::Reader;
-    use header::Lead;
-    use std::io::Read;
-    use error::{RPMError, RPMFileError};
+    use super::{Reader, Limits, Offsets};
+    use header::{Lead, Header};
+    use Result;
+    use std::io::{self, Read, Seek};
+    use error::{RPMError, RPMFileError, ParseError, Section};
     static BINRPM1: &'static [u8] = include_bytes!("../tests/rpms/binary.x86_64.rpm");
 
     #[test]
@@ -124,7 +447,8 @@ mod tests {
     fn read_short_lead() {
         let mut r = Reader::from_bytes(&BINRPM1[0..10]);
         match r.lead().unwrap_err() {
-            RPMError::Io(_) => (),
+            RPMError::Parse(ParseError { kind: RPMFileError::Truncated, section: Section::Lead,
+                                         expected: Some(0x60), actual: Some(10), .. }) => (),
             e => panic!("unexpected error: {}", e),
         }
     }
@@ -134,7 +458,20 @@ mod tests {
         let bad_bytes = &[0; 0x60];
         let mut r = Reader::from_bytes(&bad_bytes[..]);
         match r.lead().unwrap_err() {
-            RPMError::File(RPMFileError::BadMagic) => (),
+            RPMError::Parse(ParseError { kind: RPMFileError::BadMagic, offset: 0, .. }) => (),
+            e => panic!("unexpected error: {}", e),
+        }
+    }
+
+    #[test]
+    fn read_lead_without_nul() {
+        let mut bytes = BINRPM1[..0x60].to_vec();
+        for b in bytes[10..].iter_mut() {
+            *b = b'x';
+        }
+        let mut r = Reader::from_bytes(bytes);
+        match r.lead().unwrap_err() {
+            RPMError::Parse(ParseError { kind: RPMFileError::BadHeader, offset: 10, .. }) => (),
             e => panic!("unexpected error: {}", e),
         }
     }
@@ -144,7 +481,8 @@ mod tests {
         let mut r = Reader::from_bytes(&BINRPM1[..0x66]);
         let _ = r.lead(); // toss that junk
         match r.header().unwrap_err() {
-            RPMError::Io(_) => (),
+            RPMError::Parse(ParseError { kind: RPMFileError::Truncated, section: Section::Signature,
+                                         offset: 0x60, actual: Some(6), .. }) => (),
             e => panic!("unexpected error: {}", e),
         }
     }
@@ -154,7 +492,83 @@ mod tests {
         let bad_bytes = [0; 0x20];
         let mut r = Reader::from_bytes(&bad_bytes[..]);
         match r.header().unwrap_err() {
-            RPMError::File(RPMFileError::BadMagic) => (),
+            RPMError::Parse(ParseError { kind: RPMFileError::BadMagic, offset: 0, .. }) => (),
+            e => panic!("unexpected error: {}", e),
+        }
+    }
+
+    #[test]
+    fn read_header_limits() {
+        let limits = Limits { max_entries: 8, max_data: 0x1500, ..Limits::default() };
+        let mut r = Reader::from_bytes(BINRPM1).with_limits(limits);
+        r.lead().unwrap();
+        assert_eq!(r.header().unwrap().len(), 8);
+        // the main header has 62 entries
+        match r.header().unwrap_err() {
+            RPMError::Parse(ParseError { kind: RPMFileError::HeaderSize, section: Section::Header,
+                                         offset: 0x1580, expected: Some(8), actual: Some(62), .. }) => (),
+            e => panic!("unexpected error: {}", e),
+        }
+    }
+
+    #[test]
+    fn read_bad_value() {
+        // make the main header's NAME (at 0x1968 + 2) not UTF-8
+        let mut bytes = BINRPM1.to_vec();
+        bytes[0x196a] = 0xff;
+        let mut r = Reader::from_bytes(bytes);
+        r.lead().unwrap();
+        r.signature().unwrap();
+        let err = match r.header().unwrap_err() {
+            RPMError::Parse(err) => err,
+            e => panic!("unexpected error: {}", e),
+        };
+        assert_eq!((err.section, err.offset, err.tag), (Section::Header, 0x196a, Some(1000)));
+        assert_eq!(err.to_string(), "Bad tag value: string isn't valid UTF-8 in header at offset 0x196a, \
+                                     tag 1000 (STRING)");
+    }
+
+    #[test]
+    fn read_bad_region() {
+        // the main header's store is at 0x1968, and the HEADERIMMUTABLE
+        // trailer is at the offset given by the first index entry
+        let offset = u32::from_be_bytes([BINRPM1[0x1590], BINRPM1[0x1591], BINRPM1[0x1592], BINRPM1[0x1593]]);
+        let trailer = 0x1968 + offset as usize;
+        let mut bytes = BINRPM1.to_vec();
+        bytes[trailer + 3] = 62;
+        let mut r = Reader::from_bytes(&bytes[..]);
+        r.lead().unwrap();
+        r.signature().unwrap();
+        match r.header().unwrap_err() {
+            RPMError::Parse(ParseError { kind: RPMFileError::BadRegion(_), section: Section::Header,
+                                         offset, tag: Some(63), .. }) if offset == trailer as u64 => (),
+            e => panic!("unexpected error: {}", e),
+        }
+        // same thing when the values are borrowed, or read lazily
+        let mut r = Reader::from_bytes(&bytes[..]);
+        r.lead().unwrap();
+        r.signature().unwrap();
+        match r.header_ref().unwrap_err() {
+            RPMError::Parse(ParseError { offset, tag: Some(63), .. }) if offset == trailer as u64 => (),
+            e => panic!("unexpected error: {}", e),
+        }
+        let mut r = Reader::from_bytes(&bytes[..]);
+        r.lead().unwrap();
+        r.signature().unwrap();
+        match r.lazy_header().unwrap_err() {
+            RPMError::Parse(ParseError { offset, tag: Some(63), .. }) if offset == trailer as u64 => (),
+            e => panic!("unexpected error: {}", e),
+        }
+    }
+
+    #[test]
+    fn read_truncated_header() {
+        let mut r = Reader::from_bytes(&BINRPM1[..0x2000]);
+        r.lead().unwrap();
+        r.signature().unwrap();
+        match r.header().unwrap_err() {
+            RPMError::Parse(ParseError { kind: RPMFileError::Truncated, section: Section::Header,
+                                         offset: 0x1588, expected: Some(0x1bb2), actual: Some(0xa78), .. }) => (),
             e => panic!("unexpected error: {}", e),
         }
     }
@@ -171,4 +585,177 @@ mod tests {
         r.rdr.read_exact(&mut magic).unwrap();
         assert_eq!(magic[..], b"\xfd7zX"[..]);
     }
+
+    #[test]
+    fn read_payload() {
+        let mut r = Reader::from_bytes(BINRPM1);
+        let _ = r.lead();
+        let _ = r.header().unwrap();
+        let _ = r.header().unwrap();
+        let mut payload = Vec::new();
+        r.payload().unwrap().read_to_end(&mut payload).unwrap();
+        assert_eq!(payload.len(), BINRPM1.len() - 0x313a);
+        assert_eq!(payload[..4], b"\xfd7zX"[..]);
+    }
+
+    #[test]
+    fn read_decompressed_payload() {
+        let mut r = Reader::from_bytes(BINRPM1);
+        let _ = r.lead();
+        let _ = r.header().unwrap();
+        let hdr = r.header().unwrap();
+        let mut payload = Vec::new();
+        r.decompressed_payload(&hdr).unwrap().read_to_end(&mut payload).unwrap();
+        // it's a cpio archive, which ends with a TRAILER!!! entry
+        assert_eq!(payload[..6], b"070701"[..]);
+        assert!(payload.windows(10).any(|w| w == b"TRAILER!!!"));
+    }
+
+    #[test]
+    fn read_payload_before_headers() {
+        let mut r = Reader::from_bytes(BINRPM1);
+        let _ = r.lead();
+        let _ = r.header().unwrap(); // only the signature header
+        match r.payload().unwrap_err() {
+            RPMError::OutOfOrder => (),
+            e => panic!("unexpected error: {}", e),
+        }
+    }
+
+    // Counts how much gets read through it
+    struct Counting<R> {
+        rdr: R,
+        count: usize,
+    }
+
+    impl<R: Read> Read for Counting<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.rdr.read(buf)?;
+            self.count += n;
+            Ok(n)
+        }
+    }
+
+    impl<R: Seek> Seek for Counting<R> {
+        fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+            self.rdr.seek(pos)
+        }
+    }
+
+    #[test]
+    fn read_offsets() {
+        let expected = Offsets { lead: 0, signature: 0x60, header: 0x1578, payload: 0x313a };
+        // without reading anything
+        let mut r = Reader::from_bytes(BINRPM1);
+        assert_eq!(r.offsets().unwrap(), expected);
+        // it's still at the start
+        r.lead().unwrap();
+        r.signature().unwrap();
+        assert_eq!(r.offsets().unwrap(), expected);
+        // and it's still right after the signature
+        assert_eq!(r.header().unwrap().len(), 62);
+    }
+
+    #[test]
+    fn seek_to_header() {
+        let mut r = Reader::from_reader(Counting { rdr: io::Cursor::new(BINRPM1), count: 0 });
+        r.seek_to(Section::Header).unwrap();
+        let hdr = r.header().unwrap();
+        assert_eq!(hdr.len(), 62);
+        // just the two section headers (to find things), and the main header
+        let count = 0x10 + 0x10 + 0x313a - 0x1578;
+        assert_eq!(r.rdr.count, count);
+        assert_eq!(r.skip_payload().unwrap(), BINRPM1.len() as u64 - 0x313a);
+        assert_eq!(r.rdr.count, count);
+    }
+
+    #[test]
+    fn reread_header() {
+        let mut r = Reader::from_bytes(BINRPM1);
+        r.lead().unwrap();
+        r.signature().unwrap();
+        let (hdr, raw) = r.raw_header().unwrap();
+        let mut payload = Vec::new();
+        r.payload().unwrap().read_to_end(&mut payload).unwrap();
+        assert_eq!(payload.len(), BINRPM1.len() - 0x313a);
+        // go back for the header, and then the payload again
+        r.seek_to(Section::Header).unwrap();
+        assert_eq!(r.raw_header().unwrap(), (hdr, raw));
+        let mut again = Vec::new();
+        r.payload().unwrap().read_to_end(&mut again).unwrap();
+        assert_eq!(payload, again);
+        // and the signature header's still readable, in order
+        r.seek_to(Section::Signature).unwrap();
+        assert!(r.payload().is_err());
+        assert_eq!(r.signature().unwrap().len(), 8);
+        r.seek_to(Section::Lead).unwrap();
+        assert!(r.lead().is_ok());
+    }
+
+    #[test]
+    fn offsets_of_bad_header() {
+        let mut r = Reader::from_bytes(&BINRPM1[..0x1580]);
+        match r.seek_to(Section::Payload).unwrap_err() {
+            RPMError::Parse(ParseError { kind: RPMFileError::Truncated, section: Section::Header,
+                                         offset: 0x1578, .. }) => (),
+            e => panic!("unexpected error: {}", e),
+        }
+        // it hasn't moved
+        assert!(r.lead().is_ok());
+    }
+
+    #[test]
+    fn read_bare_headers() {
+        let mut r = Reader::from_bytes(BINRPM1);
+        r.lead().unwrap();
+        r.signature().unwrap();
+        let (hdr, raw) = r.raw_header().unwrap();
+        // a .hdr file has the magic, and the rpmdb leaves it off
+        assert_eq!(Header::from_bytes(&raw).unwrap(), hdr);
+        assert_eq!(Header::from_blob(&raw[8..]).unwrap(), hdr);
+        match Header::from_bytes(&raw[8..]).unwrap_err() {
+            RPMError::Parse(ParseError { kind: RPMFileError::BadMagic, offset: 0, .. }) => (),
+            e => panic!("unexpected error: {}", e),
+        }
+        let mut long = raw[8..].to_vec();
+        long.push(0);
+        match Header::from_blob(&long).unwrap_err() {
+            RPMError::Parse(ParseError { kind: RPMFileError::HeaderSize, offset: 0x1bba,
+                                         expected: Some(0x1bba), actual: Some(0x1bbb), .. }) => (),
+            e => panic!("unexpected error: {}", e),
+        }
+        match Header::from_blob(&raw[8..0x100]).unwrap_err() {
+            RPMError::Parse(ParseError { kind: RPMFileError::Truncated, offset: 8, .. }) => (),
+            e => panic!("unexpected error: {}", e),
+        }
+    }
+
+    #[test]
+    fn read_hdlist() {
+        let mut r = Reader::from_bytes(BINRPM1);
+        r.lead().unwrap();
+        let sig = r.header().unwrap();
+        let (hdr, raw) = r.raw_header().unwrap();
+        // three of the same header, one after another
+        let hdlist = [&raw[..], &raw[..], &raw[..]].concat();
+        let headers: Vec<Header> = Reader::from_bytes(&hdlist[..]).headers()
+            .collect::<Result<_>>().unwrap();
+        assert_eq!(headers, vec![hdr.clone(), hdr.clone(), hdr]);
+        assert_eq!(Reader::from_bytes(&b""[..]).headers().count(), 0);
+        // the signature header isn't padded on its own
+        let unpadded = &BINRPM1[0x60..0x60 + 0x10 + 8 * 16 + 0x1484];
+        let headers: Vec<_> = Reader::from_bytes(unpadded).headers().collect();
+        assert_eq!(headers.len(), 1);
+        assert_eq!(headers[0].as_ref().unwrap(), &sig);
+        // and a bad one is the last one
+        let bad = [&raw[..], &raw[..10]].concat();
+        let mut headers = Reader::from_bytes(bad).headers();
+        assert!(headers.next().unwrap().is_ok());
+        match headers.next().unwrap().unwrap_err() {
+            RPMError::Parse(ParseError { kind: RPMFileError::Truncated, offset: 0x1bc2,
+                                         actual: Some(10), .. }) => (),
+            e => panic!("unexpected error: {}", e),
+        }
+        assert!(headers.next().is_none());
+    }
 }
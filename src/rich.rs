@@ -0,0 +1,493 @@
+/* rich.rs - rich (boolean) dependencies
+ *
+ * Copyright (c) 2017, Red Hat, Inc.
+ *
+ * This library is free software; you can redistribute it and/or modify it
+ * under the terms and conditions of the GNU Lesser General Public License
+ * as published by the Free Software Foundation; either version 2.1 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU Lesser General Public License for
+ * more details.
+ *
+ * Authors:
+ *   Will Woods <wwoods@redhat.com>
+ */
+use std::fmt;
+use std::collections::BTreeSet;
+
+use deps::{Dependency, DepFlags, DepKind};
+use package::Package;
+use version::Evr;
+use reader::Limits;
+use error::{RPMError, RPMFileError};
+use Result;
+
+/// A parsed rich dependency (rpm >= 4.13), like "(foo >= 1.0 if bar else baz)".
+///
+/// `and`, `or` and `with` can be chained, as in "(a and b and c)", but
+/// mixing different operators needs parentheses, just like in rpm.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub enum RichDep {
+    /// A plain dependency, like "foo >= 1.0"
+    Dep(Dependency),
+    /// All of them
+    And(Vec<RichDep>),
+    /// Any of them
+    Or(Vec<RichDep>),
+    /// The first one, if the second one is satisfied; otherwise the third
+    /// one (if there is one)
+    If(Box<RichDep>, Box<RichDep>, Option<Box<RichDep>>),
+    /// The first one, unless the second one is satisfied; otherwise the
+    /// third one (if there is one)
+    Unless(Box<RichDep>, Box<RichDep>, Option<Box<RichDep>>),
+    /// One package that satisfies all of them
+    With(Vec<RichDep>),
+    /// A package that satisfies the first one but not the second one
+    Without(Box<RichDep>, Box<RichDep>),
+}
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+enum Op { And, Or, If, Unless, With, Without, Else }
+
+const OPS: &[(&str, Op)] = &[
+    ("and", Op::And), ("or", Op::Or), ("if", Op::If), ("unless", Op::Unless),
+    ("with", Op::With), ("without", Op::Without), ("else", Op::Else),
+];
+
+const COMPARISONS: &[(&str, u32)] = &[
+    // longest first, so "<=" isn't read as "<"
+    ("<=", DepFlags::LESS.bits() | DepFlags::EQUAL.bits()),
+    (">=", DepFlags::GREATER.bits() | DepFlags::EQUAL.bits()),
+    ("==", DepFlags::EQUAL.bits()),
+    ("<", DepFlags::LESS.bits()),
+    (">", DepFlags::GREATER.bits()),
+    ("=", DepFlags::EQUAL.bits()),
+];
+
+struct Parser<'a> {
+    s: &'a str,
+    pos: usize,
+    // how many parentheses we're inside, and how many we'll go into.
+    // Rich dependencies come straight out of package headers, so a limit
+    // keeps a hostile one from blowing the stack.
+    depth: usize,
+    max_depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error<T>(&self, msg: &'static str) -> Result<T> {
+        Err(RPMError::File(RPMFileError::BadRichDep(String::from(self.s), self.pos, msg)))
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.s[self.pos..]
+    }
+
+    fn skip_space(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    // Take everything up to the next space or unbalanced ')'.
+    fn word(&mut self) -> &'a str {
+        let rest = self.rest();
+        let mut depth = 0;
+        let len = rest.char_indices().find(|&(_, c)| {
+            match c {
+                '(' => depth += 1,
+                ')' if depth == 0 => return true,
+                ')' => depth -= 1,
+                c if c.is_whitespace() => return true,
+                _ => (),
+            }
+            false
+        }).map(|(i, _)| i).unwrap_or(rest.len());
+        self.pos += len;
+        &rest[..len]
+    }
+
+    // "(" term [op term ...] ")"
+    fn rich(&mut self) -> Result<RichDep> {
+        if !self.rest().starts_with('(') {
+            return self.error("expected '('");
+        }
+        if self.depth == self.max_depth {
+            return self.error("nested too deeply");
+        }
+        self.depth += 1;
+        self.pos += 1;
+        let first = self.term()?;
+        let mut terms = vec![first];
+        let mut op = None;
+        let mut else_term = None;
+        loop {
+            self.skip_space();
+            if self.rest().starts_with(')') {
+                self.pos += 1;
+                break;
+            }
+            let next = match self.op() {
+                Some(next) => next,
+                None => return self.error("expected an operator or ')'"),
+            };
+            match (op, next) {
+                (_, Op::Else) if else_term.is_some() => return self.error("'else' used twice"),
+                (Some(Op::If), Op::Else) | (Some(Op::Unless), Op::Else) => {
+                    else_term = Some(Box::new(self.term()?));
+                    continue;
+                },
+                (_, Op::Else) => return self.error("'else' without 'if' or 'unless'"),
+                (None, _) => op = Some(next),
+                (Some(prev), _) if prev != next => return self.error("can't mix operators without parentheses"),
+                (Some(Op::And), _) | (Some(Op::Or), _) | (Some(Op::With), _) => (),
+                (Some(_), _) => return self.error("operator can't be chained"),
+            }
+            terms.push(self.term()?);
+        }
+        self.depth -= 1;
+
+        let is_simple = |t: &RichDep| matches!(*t, RichDep::Dep(_));
+        Ok(match op {
+            None => terms.remove(0),
+            Some(Op::And) => RichDep::And(terms),
+            Some(Op::Or) => RichDep::Or(terms),
+            Some(Op::With) => {
+                if !terms.iter().all(|t| matches!(*t, RichDep::Dep(_) | RichDep::With(_))) {
+                    return self.error("'with' only works on simple dependencies");
+                }
+                RichDep::With(terms)
+            },
+            Some(binop) => {
+                let b = Box::new(terms.pop().unwrap());
+                let a = Box::new(terms.pop().unwrap());
+                match binop {
+                    Op::If => RichDep::If(a, b, else_term),
+                    Op::Unless => RichDep::Unless(a, b, else_term),
+                    _ if !is_simple(&a) || !is_simple(&b) => {
+                        return self.error("'without' only works on simple dependencies");
+                    },
+                    _ => RichDep::Without(a, b),
+                }
+            },
+        })
+    }
+
+    // Either a nested rich dependency or a simple one.
+    fn term(&mut self) -> Result<RichDep> {
+        self.skip_space();
+        if self.rest().starts_with('(') {
+            return self.rich();
+        }
+        let name = self.word();
+        if name.is_empty() {
+            return self.error("expected a dependency");
+        }
+        if OPS.iter().any(|&(word, _)| word == name) {
+            return self.error("expected a dependency, not an operator");
+        }
+        // see if there's a version comparison after the name
+        let start = self.pos;
+        self.skip_space();
+        for &(cmp, flags) in COMPARISONS {
+            if self.rest().starts_with(cmp) {
+                self.pos += cmp.len();
+                self.skip_space();
+                let evr = self.word();
+                if evr.is_empty() {
+                    return self.error("expected a version");
+                }
+                let flags = DepFlags::from_bits_truncate(flags);
+                return Ok(RichDep::Dep(Dependency::new(name, flags, Some(Evr::parse(evr)))));
+            }
+        }
+        self.pos = start;
+        Ok(RichDep::Dep(Dependency::new(name, DepFlags::ANY, None)))
+    }
+
+    // An operator keyword, followed by a space or '('.
+    fn op(&mut self) -> Option<Op> {
+        let rest = self.rest();
+        for &(word, op) in OPS {
+            if let Some(after) = rest.strip_prefix(word) {
+                if after.starts_with(|c: char| c.is_whitespace() || c == '(') {
+                    self.pos += word.len();
+                    return Some(op);
+                }
+            }
+        }
+        None
+    }
+}
+
+impl RichDep {
+    /// Parse a rich dependency, like "(a and (b or c))".
+    pub fn parse(s: &str) -> Result<RichDep> {
+        RichDep::parse_with_limits(s, &Limits::default())
+    }
+
+    /// Parse a rich dependency, refusing ones nested more deeply than
+    /// `limits.max_rich_depth`.
+    pub fn parse_with_limits(s: &str, limits: &Limits) -> Result<RichDep> {
+        let mut p = Parser { s, pos: 0, depth: 0, max_depth: limits.max_rich_depth };
+        p.skip_space();
+        let dep = p.rich()?;
+        p.skip_space();
+        if !p.rest().is_empty() {
+            return p.error("unexpected text after the end of the dependency");
+        }
+        Ok(dep)
+    }
+
+    /// Check whether the packages in `providers` satisfy this dependency.
+    pub fn eval(&self, providers: &ProviderSet) -> bool {
+        match *self {
+            RichDep::Dep(ref dep) => providers.provides(dep),
+            RichDep::And(ref deps) => deps.iter().all(|d| d.eval(providers)),
+            RichDep::Or(ref deps) => deps.iter().any(|d| d.eval(providers)),
+            RichDep::If(ref then, ref cond, ref otherwise) => {
+                if cond.eval(providers) {
+                    then.eval(providers)
+                } else {
+                    otherwise.as_ref().map(|d| d.eval(providers)).unwrap_or(true)
+                }
+            },
+            RichDep::Unless(ref then, ref cond, ref otherwise) => {
+                if !cond.eval(providers) {
+                    then.eval(providers)
+                } else {
+                    otherwise.as_ref().map(|d| d.eval(providers)).unwrap_or(true)
+                }
+            },
+            RichDep::With(_) | RichDep::Without(..) => !self.packages(providers).is_empty(),
+        }
+    }
+
+    // The packages that satisfy this, for 'with' and 'without'; the parser
+    // makes sure those only contain simple deps and other 'with's.
+    fn packages(&self, providers: &ProviderSet) -> BTreeSet<usize> {
+        match *self {
+            RichDep::Dep(ref dep) => providers.packages(dep),
+            RichDep::With(ref deps) => {
+                let mut sets = deps.iter().map(|d| d.packages(providers));
+                let first = sets.next().unwrap_or_default();
+                sets.fold(first, |acc, s| acc.intersection(&s).cloned().collect())
+            },
+            RichDep::Without(ref a, ref b) => {
+                a.packages(providers).difference(&b.packages(providers)).cloned().collect()
+            },
+            _ => BTreeSet::new(),
+        }
+    }
+}
+
+impl fmt::Display for RichDep {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn join(f: &mut fmt::Formatter, deps: &[RichDep], op: &str) -> fmt::Result {
+            write!(f, "(")?;
+            for (i, dep) in deps.iter().enumerate() {
+                if i > 0 {
+                    write!(f, " {} ", op)?;
+                }
+                write!(f, "{}", dep)?;
+            }
+            write!(f, ")")
+        }
+        match *self {
+            RichDep::Dep(ref dep) => write!(f, "{}", dep),
+            RichDep::And(ref deps) => join(f, deps, "and"),
+            RichDep::Or(ref deps) => join(f, deps, "or"),
+            RichDep::With(ref deps) => join(f, deps, "with"),
+            RichDep::Without(ref a, ref b) => write!(f, "({} without {})", a, b),
+            RichDep::If(ref a, ref b, ref c) | RichDep::Unless(ref a, ref b, ref c) => {
+                let op = if let RichDep::If(..) = *self { "if" } else { "unless" };
+                write!(f, "({} {} {}", a, op, b)?;
+                if let Some(ref c) = *c {
+                    write!(f, " else {}", c)?;
+                }
+                write!(f, ")")
+            },
+        }
+    }
+}
+
+/// A set of packages (or really, the things they provide) to check
+/// dependencies against.
+#[derive(Debug,Clone,Default)]
+pub struct ProviderSet {
+    packages: Vec<Vec<Dependency>>,
+}
+
+impl ProviderSet {
+    pub fn new() -> ProviderSet {
+        ProviderSet::default()
+    }
+
+    /// Add a package that provides the given things.
+    pub fn add(&mut self, provides: Vec<Dependency>) {
+        self.packages.push(provides);
+    }
+
+    /// Add a package's provides, and its files, which rpm also counts.
+    pub fn add_package(&mut self, pkg: &Package) -> Result<()> {
+        let mut provides = pkg.provides()?;
+        for file in pkg.files()? {
+            provides.push(Dependency::new(file.path, DepFlags::ANY, None));
+        }
+        self.add(provides);
+        Ok(())
+    }
+
+    // The indexes of the packages that satisfy dep
+    fn packages(&self, dep: &Dependency) -> BTreeSet<usize> {
+        self.packages.iter().enumerate()
+            .filter(|&(_, provides)| provides.iter().any(|p| dep.matches(p)))
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Whether any package satisfies the given simple dependency.
+    pub fn provides(&self, dep: &Dependency) -> bool {
+        self.packages.iter().any(|provides| provides.iter().any(|p| dep.matches(p)))
+    }
+
+    /// Whether the dependency is satisfied. Rich dependencies are parsed
+    /// and evaluated; anything else has to be provided by some package.
+    pub fn satisfies(&self, dep: &Dependency) -> Result<bool> {
+        if dep.is_rich() {
+            Ok(RichDep::parse(&dep.name)?.eval(self))
+        } else {
+            Ok(self.provides(dep))
+        }
+    }
+
+    /// Check all of a package's requirements, returning the ones that
+    /// aren't satisfied.
+    pub fn unsatisfied(&self, pkg: &Package) -> Result<Vec<Dependency>> {
+        let mut missing = Vec::new();
+        for dep in pkg.dependencies(DepKind::Require)? {
+            if dep.flags.contains(DepFlags::RPMLIB) {
+                continue;
+            }
+            if !self.satisfies(&dep)? {
+                missing.push(dep);
+            }
+        }
+        Ok(missing)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dep(name: &str) -> Dependency {
+        Dependency::new(name, DepFlags::ANY, None)
+    }
+
+    fn providers() -> ProviderSet {
+        let mut set = ProviderSet::new();
+        let versioned = |name: &str, evr: &str| Dependency::new(name, DepFlags::EQUAL, Some(Evr::parse(evr)));
+        set.add(vec![versioned("foo", "1.5-1"), dep("foo-api")]);
+        set.add(vec![versioned("bar", "2.0-1"), dep("foo-api")]);
+        set.add(vec![dep("baz")]);
+        set
+    }
+
+    fn eval(s: &str) -> bool {
+        RichDep::parse(s).unwrap().eval(&providers())
+    }
+
+    #[test]
+    fn parse_rich() {
+        let parsed = RichDep::parse("(foo >= 1.0 if bar else baz)").unwrap();
+        let foo = Dependency::new("foo", DepFlags::GREATER | DepFlags::EQUAL, Some(Evr::parse("1.0")));
+        assert_eq!(parsed, RichDep::If(Box::new(RichDep::Dep(foo)), Box::new(RichDep::Dep(dep("bar"))),
+                                       Some(Box::new(RichDep::Dep(dep("baz"))))));
+
+        let parsed = RichDep::parse("(a and (b or c) and perl(Foo::Bar) >= 1:2.0-1)").unwrap();
+        match parsed {
+            RichDep::And(ref deps) => {
+                assert_eq!(deps.len(), 3);
+                assert_eq!(deps[1], RichDep::Or(vec![RichDep::Dep(dep("b")), RichDep::Dep(dep("c"))]));
+                assert_eq!(deps[2].to_string(), "perl(Foo::Bar) >= 1:2.0-1");
+            },
+            ref d => panic!("unexpected parse: {:?}", d),
+        }
+    }
+
+    #[test]
+    fn rich_display_roundtrip() {
+        for s in &["(a and (b or c))", "(foo >= 1.0 if bar else baz)", "(a unless b)",
+                   "(a with b with c)", "(a without b)", "(foo < 2 or (bar = 1:1.0-1 and baz))"] {
+            assert_eq!(RichDep::parse(s).unwrap().to_string(), *s);
+        }
+        // extra whitespace doesn't matter
+        assert_eq!(RichDep::parse(" ( a  and(b or c) ) ").unwrap().to_string(), "(a and (b or c))");
+    }
+
+    #[test]
+    fn parse_rich_errors() {
+        for s in &["foo", "(foo", "(a and b or c)", "(a if b if c)", "(a else b)", "(a if b else c else d)",
+                   "(a and)", "(and a)", "(a >= )", "(a and b) c", "(a xor b)", "((a or b) with c)",
+                   "((a and b) without c)"] {
+            match RichDep::parse(s) {
+                Err(RPMError::File(RPMFileError::BadRichDep(ref d, _, _))) => assert_eq!(d, s),
+                r => panic!("unexpected result for {:?}: {:?}", s, r),
+            }
+        }
+    }
+
+    #[test]
+    fn parse_rich_nested() {
+        let nested = |n| "(".repeat(n) + "a" + &")".repeat(n);
+        let max = Limits::default().max_rich_depth;
+        assert_eq!(RichDep::parse(&nested(max)).unwrap(), RichDep::Dep(dep("a")));
+        for &n in &[max + 1, 200000] {
+            match RichDep::parse(&nested(n)) {
+                Err(RPMError::File(RPMFileError::BadRichDep(_, pos, "nested too deeply"))) => {
+                    assert_eq!(pos, max)
+                },
+                r => panic!("unexpected result: {:?}", r),
+            }
+        }
+        // it's how deep they go that counts, not how many there are
+        let wide = format!("(a and {})", vec![nested(max - 1); 1000].join(" and "));
+        assert!(RichDep::parse(&wide).is_ok());
+        // and the limit can be changed
+        let limits = Limits { max_rich_depth: 2, ..Limits::default() };
+        assert!(RichDep::parse_with_limits("((a or b) and c)", &limits).is_ok());
+        assert!(RichDep::parse_with_limits("(((a or b) and c) or d)", &limits).is_err());
+    }
+
+    #[test]
+    fn eval_rich() {
+        assert!(eval("(foo)"));
+        assert!(eval("(foo and bar)"));
+        assert!(!eval("(foo and nope)"));
+        assert!(eval("(nope or baz)"));
+        assert!(eval("(foo >= 1.0 if bar else nope)"));
+        assert!(!eval("(foo >= 2.0 if bar else baz)"));
+        assert!(eval("(nope if nothing)"));
+        assert!(eval("(nope if nothing else baz)"));
+        assert!(eval("(nope unless bar)"));
+        assert!(!eval("(nope unless nothing)"));
+        assert!(eval("(nope unless bar else foo < 2)"));
+        // 'with' needs a single package that does both
+        assert!(eval("(foo-api with bar)"));
+        assert!(!eval("(baz with bar)"));
+        assert!(eval("(foo-api without foo)"));
+        assert!(!eval("(baz without baz)"));
+    }
+
+    #[test]
+    fn satisfies_rich_name() {
+        // without parsing, this would look for a package called "(foo"
+        let dep = dep("(foo or nope)");
+        assert!(dep.is_rich());
+        assert!(providers().satisfies(&dep).unwrap());
+        assert!(!providers().satisfies(&Dependency::new("(nope and foo)", DepFlags::ANY, None)).unwrap());
+    }
+}
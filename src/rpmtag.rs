@@ -1,4 +1,4 @@
-/* generated from /usr/include/rpm/rpmtag.h by scripts/gen-rpmtag */
+/* generated from /usr/include/rpm/rpmtag.h (plus scripts/rpmtag-extra) by scripts/gen-rpmtag */
 
 /* automatically generated by rust-bindgen */
 
@@ -42,6 +42,7 @@ pub enum Tag {
     SHA1HEADER = 269,
     LONGSIGSIZE = 270,
     LONGARCHIVESIZE = 271,
+    SHA256HEADER = 273,
     NAME = 1000,
     VERSION = 1001,
     RELEASE = 1002,
@@ -321,7 +322,9 @@ pub enum Tag {
     TRANSFILETRIGGERTYPE = 5089,
     FILESIGNATURES = 5090,
     FILESIGNATURELENGTH = 5091,
-    FIRSTFREE_TAG = 5092,
+    PAYLOADDIGEST = 5092,
+    PAYLOADDIGESTALGO = 5093,
+    FIRSTFREE_TAG = 5094,
 }
 #[derive(Copy, Clone)]
 #[repr(u32)]
@@ -369,6 +372,7 @@ pub enum SigTag {
     RSA = 268,
     LONGSIZE = 270,
     LONGARCHIVESIZE = 271,
+    SHA256 = 273,
 }
 #[derive(Copy, Clone)]
 #[repr(u32)]
@@ -414,7 +418,7 @@ pub enum TagReturnType {
 }
 
 use tag::TagInfo;
-pub const TABLESIZE:usize = 239;
+pub const TABLESIZE:usize = 242;
 pub static TAG_INFO_TABLE: [TagInfo;TABLESIZE] = [
     TagInfo {name:"ARCHIVESIZE", shortname:"Archivesize", id:Tag::ARCHIVESIZE, ttype:TagType::INT32, retype:TagReturnType::SCALAR, extension:false},
     TagInfo {name:"ARCH", shortname:"Arch", id:Tag::ARCH, ttype:TagType::STRING, retype:TagReturnType::SCALAR, extension:false},
@@ -554,6 +558,8 @@ pub static TAG_INFO_TABLE: [TagInfo;TABLESIZE] = [
     TagInfo {name:"PATCHESVERSION", shortname:"Patchesversion", id:Tag::PATCHESVERSION, ttype:TagType::STRING_ARRAY, retype:TagReturnType::ARRAY, extension:false},
     TagInfo {name:"PATCH", shortname:"Patch", id:Tag::PATCH, ttype:TagType::STRING_ARRAY, retype:TagReturnType::ARRAY, extension:false},
     TagInfo {name:"PAYLOADCOMPRESSOR", shortname:"Payloadcompressor", id:Tag::PAYLOADCOMPRESSOR, ttype:TagType::STRING, retype:TagReturnType::SCALAR, extension:false},
+    TagInfo {name:"PAYLOADDIGESTALGO", shortname:"Payloaddigestalgo", id:Tag::PAYLOADDIGESTALGO, ttype:TagType::INT32, retype:TagReturnType::SCALAR, extension:false},
+    TagInfo {name:"PAYLOADDIGEST", shortname:"Payloaddigest", id:Tag::PAYLOADDIGEST, ttype:TagType::STRING_ARRAY, retype:TagReturnType::ARRAY, extension:false},
     TagInfo {name:"PAYLOADFLAGS", shortname:"Payloadflags", id:Tag::PAYLOADFLAGS, ttype:TagType::STRING, retype:TagReturnType::SCALAR, extension:false},
     TagInfo {name:"PAYLOADFORMAT", shortname:"Payloadformat", id:Tag::PAYLOADFORMAT, ttype:TagType::STRING, retype:TagReturnType::SCALAR, extension:false},
     TagInfo {name:"PKGID", shortname:"Pkgid", id:Tag::SIGMD5, ttype:TagType::BIN, retype:TagReturnType::SCALAR, extension:false},
@@ -606,6 +612,7 @@ pub static TAG_INFO_TABLE: [TagInfo;TABLESIZE] = [
     TagInfo {name:"RSAHEADER", shortname:"Rsaheader", id:Tag::RSAHEADER, ttype:TagType::BIN, retype:TagReturnType::SCALAR, extension:false},
     TagInfo {name:"R", shortname:"R", id:Tag::RELEASE, ttype:TagType::STRING, retype:TagReturnType::SCALAR, extension:false},
     TagInfo {name:"SHA1HEADER", shortname:"Sha1header", id:Tag::SHA1HEADER, ttype:TagType::STRING, retype:TagReturnType::SCALAR, extension:false},
+    TagInfo {name:"SHA256HEADER", shortname:"Sha256header", id:Tag::SHA256HEADER, ttype:TagType::STRING, retype:TagReturnType::SCALAR, extension:false},
     TagInfo {name:"SIGGPG", shortname:"Siggpg", id:Tag::SIGGPG, ttype:TagType::BIN, retype:TagReturnType::SCALAR, extension:false},
     TagInfo {name:"SIGMD5", shortname:"Sigmd5", id:Tag::SIGMD5, ttype:TagType::BIN, retype:TagReturnType::SCALAR, extension:false},
     TagInfo {name:"SIGPGP", shortname:"Sigpgp", id:Tag::SIGPGP, ttype:TagType::BIN, retype:TagReturnType::SCALAR, extension:false},
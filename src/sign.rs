@@ -0,0 +1,245 @@
+/* sign.rs - signing packages, like rpmsign
+ *
+ * Copyright (c) 2017, Red Hat, Inc.
+ *
+ * This library is free software; you can redistribute it and/or modify it
+ * under the terms and conditions of the GNU Lesser General Public License
+ * as published by the Free Software Foundation; either version 2.1 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU Lesser General Public License for
+ * more details.
+ *
+ * Authors:
+ *   Will Woods <wwoods@redhat.com>
+ */
+use std::io;
+use std::io::prelude::*;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use sha2::{Sha256, Digest};
+
+use signature::SignatureHeader;
+use reader::{Reader, check_lead};
+use pgp::{SecretKey, PGPPUBKEYALGO_DSA};
+use verify::{hex, PGPHASHALGO_SHA256};
+use tag::{SigTag, TagValue};
+use Result;
+
+/// Signs packages with a local OpenPGP secret key, like `rpmsign
+/// --addsign`.
+///
+/// Signing replaces any signatures the package already had. Only the
+/// signature header changes; the lead, the main header and the payload
+/// are copied as-is.
+#[derive(Debug,Clone)]
+pub struct Signer<'a> {
+    key: &'a SecretKey,
+    hash_algo: u8,
+    created: Option<u32>,
+    header_payload: bool,
+}
+
+impl<'a> Signer<'a> {
+    /// A signer that makes SHA256 header-only signatures, like rpm 4.14
+    /// and later.
+    pub fn new(key: &'a SecretKey) -> Signer<'a> {
+        Signer { key, hash_algo: PGPHASHALGO_SHA256 as u8, created: None, header_payload: false }
+    }
+    /// Use a different OpenPGP hash algorithm, like 10 for SHA512.
+    pub fn hash_algo(mut self, algo: u8) -> Signer<'a> {
+        self.hash_algo = algo;
+        self
+    }
+    /// Use a fixed signature creation time, in seconds since the epoch,
+    /// rather than the current time.
+    pub fn created(mut self, time: u32) -> Signer<'a> {
+        self.created = Some(time);
+        self
+    }
+    /// Also make a header+payload signature, for older versions of rpm
+    /// that don't check the payload digest.
+    pub fn header_payload(mut self, yes: bool) -> Signer<'a> {
+        self.header_payload = yes;
+        self
+    }
+
+    /// Sign a package's main header (given as its raw bytes, from
+    /// `Reader::raw_header()`), and its payload if `header_payload` is set,
+    /// putting the signatures in `sig` in place of any old ones.
+    pub fn sign_signature_header<R: io::Read>(&self, sig: &mut SignatureHeader, raw_hdr: &[u8],
+                                              payload: R) -> Result<()> {
+        let created = match self.created {
+            Some(time) => time,
+            None => SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as u32).unwrap_or(0),
+        };
+        // DSA signatures go in their own tags; everything else uses the RSA ones
+        let (header_tag, payload_tag) = match self.key.public_key().algo {
+            PGPPUBKEYALGO_DSA => (SigTag::DSA, SigTag::GPG),
+            _ => (SigTag::RSA, SigTag::PGP),
+        };
+        sig.remove_signatures();
+        let header_sig = self.key.sign(raw_hdr, self.hash_algo, created)?;
+        sig.insert(header_tag, TagValue::Binary(header_sig));
+        if self.header_payload {
+            let payload_sig = self.key.sign(raw_hdr.chain(payload), self.hash_algo, created)?;
+            sig.insert(payload_tag, TagValue::Binary(payload_sig));
+        }
+        // rpm adds a SHA256 header digest when signing, if there isn't one
+        if sig.sha256()?.is_none() {
+            let digest = hex(&Sha256::digest(raw_hdr));
+            sig.insert(SigTag::SHA256, TagValue::String(vec![digest]));
+        }
+        Ok(())
+    }
+
+    /// Read a package from `rdr` and write a signed copy of it to `out`.
+    ///
+    /// `rdr` has to be seekable because the payload gets read twice when
+    /// making a header+payload signature: once to sign it, since the
+    /// signature header comes first, and then again to copy it.
+    pub fn sign_package<R: io::Read + io::Seek, W: io::Write>(&self, rdr: R, out: W) -> Result<()> {
+        let (lead, mut r) = read_lead(rdr)?;
+        let mut sig = r.signature()?;
+        let size = sig.to_bytes().len();
+        let (_, raw_hdr) = r.raw_header()?;
+        let payload = r.payload()?;
+        if self.header_payload {
+            let start = payload.stream_position()?;
+            self.sign_signature_header(&mut sig, &raw_hdr, &mut *payload)?;
+            payload.seek(io::SeekFrom::Start(start))?;
+        } else {
+            self.sign_signature_header(&mut sig, &raw_hdr, io::empty())?;
+        }
+        write_package(out, &lead, &mut sig, size, &raw_hdr, payload)
+    }
+}
+
+/// Read a package from `rdr` and write a copy of it to `out` without any
+/// signatures, like `rpmsign --delsign`. The digests are left alone.
+pub fn remove_signatures<R: io::Read, W: io::Write>(rdr: R, out: W) -> Result<()> {
+    let (lead, mut r) = read_lead(rdr)?;
+    let mut sig = r.signature()?;
+    let size = sig.to_bytes().len();
+    let (_, raw_hdr) = r.raw_header()?;
+    sig.remove_signatures();
+    write_package(out, &lead, &mut sig, size, &raw_hdr, r.payload()?)
+}
+
+// Read the raw lead, which gets copied as-is, and set up a Reader for the
+// rest of the package.
+fn read_lead<R: io::Read>(rdr: R) -> Result<([u8; 0x60], Reader<R>)> {
+    let mut r = Reader::from_reader(rdr);
+    let lead = r.raw_lead()?;
+    check_lead(&lead)?;
+    Ok((lead, r))
+}
+
+// Write a package with a new signature header. If the signature header has
+// reserved space, it's resized so the header is still `size` bytes (where
+// possible), so the rest of the package doesn't move.
+fn write_package<R: io::Read, W: io::Write>(mut out: W, lead: &[u8], sig: &mut SignatureHeader,
+                                            size: usize, raw_hdr: &[u8], mut payload: R) -> Result<()> {
+    let mut sig_bytes = sig.to_bytes();
+    if let Some(TagValue::Binary(reserved)) = sig.get(SigTag::RESERVEDSPACE) {
+        // RESERVEDSPACE is the last thing in the data store, so it's easy
+        let wanted = (reserved.len() + size) as isize - sig_bytes.len() as isize;
+        if wanted >= 0 && wanted as usize != reserved.len() {
+            sig.insert(SigTag::RESERVEDSPACE, TagValue::Binary(vec![0; wanted as usize]));
+            sig_bytes = sig.to_bytes();
+        }
+    }
+    out.write_all(lead)?;
+    out.write_all(&sig_bytes)?;
+    out.write_all(raw_hdr)?;
+    io::copy(&mut payload, &mut out)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pgp::{Keyring, Verdict};
+    use verify::verify_package_signatures;
+    static BINRPM1: &[u8] = include_bytes!("../tests/rpms/binary.x86_64.rpm");
+    static KEYS: [&str; 4] = ["rsa", "dsa", "ecdsa", "eddsa"];
+
+    fn keyring() -> Keyring {
+        let mut keyring = Keyring::new();
+        for key in KEYS.iter() {
+            keyring.load(format!("tests/keys/{}.asc", key)).unwrap();
+        }
+        keyring
+    }
+
+    fn secret_key(name: &str) -> SecretKey {
+        SecretKey::load(format!("tests/keys/{}.sec.asc", name)).unwrap()
+    }
+
+    fn sign(rpm: &[u8], signer: &Signer) -> Vec<u8> {
+        let mut out = Vec::new();
+        signer.sign_package(io::Cursor::new(rpm), &mut out).unwrap();
+        out
+    }
+
+    // The signature tags in a package, and their verdicts
+    fn signatures(rpm: &[u8]) -> Vec<(SigTag, Verdict)> {
+        let v = verify_package_signatures(rpm, &keyring()).unwrap();
+        v.signatures.iter().map(|s| (s.tag, s.verdict)).collect()
+    }
+
+    #[test]
+    fn sign_header_only() {
+        for name in KEYS.iter() {
+            let key = secret_key(name);
+            let rpm = sign(BINRPM1, &Signer::new(&key));
+            let tag = if *name == "dsa" { SigTag::DSA } else { SigTag::RSA };
+            assert_eq!(signatures(&rpm), vec![(tag, Verdict::Ok)], "{} key", name);
+            // the reserved space soaks up the difference, so nothing else moves
+            assert_eq!(rpm.len(), BINRPM1.len());
+            assert_eq!(rpm[..0x60], BINRPM1[..0x60]);
+            assert_eq!(rpm[0x1578..], BINRPM1[0x1578..]);
+        }
+    }
+
+    #[test]
+    fn sign_header_payload() {
+        let key = secret_key("dsa");
+        let rpm = sign(BINRPM1, &Signer::new(&key).header_payload(true));
+        assert_eq!(signatures(&rpm), vec![(SigTag::DSA, Verdict::Ok), (SigTag::GPG, Verdict::Ok)]);
+        let v = verify_package_signatures(&rpm[..], &keyring()).unwrap();
+        assert!(v.is_ok());
+        assert_eq!(v.digests.len(), 3); // SHA256 was added
+    }
+
+    #[test]
+    fn resign() {
+        let rsa = secret_key("rsa");
+        let eddsa = secret_key("eddsa");
+        let signer = Signer::new(&rsa).header_payload(true).created(1500000000).hash_algo(10);
+        let once = sign(BINRPM1, &signer);
+        assert_eq!(signatures(&once), vec![(SigTag::RSA, Verdict::Ok), (SigTag::PGP, Verdict::Ok)]);
+        // same key, same time, same signatures
+        assert_eq!(sign(&once, &signer), once);
+
+        let twice = sign(&once, &Signer::new(&eddsa));
+        let v = verify_package_signatures(&twice[..], &keyring()).unwrap();
+        assert_eq!(v.signatures.len(), 1);
+        assert_eq!(v.signatures[0].signature.key_id, eddsa.key_id());
+        assert_eq!(v.signatures[0].verdict, Verdict::Ok);
+    }
+
+    #[test]
+    fn remove_all_signatures() {
+        let mut rpm = Vec::new();
+        remove_signatures(BINRPM1, &mut rpm).unwrap();
+        assert_eq!(signatures(&rpm), vec![]);
+        assert_eq!(rpm.len(), BINRPM1.len());
+        assert_eq!(rpm[0x1578..], BINRPM1[0x1578..]);
+        let v = verify_package_signatures(&rpm[..], &keyring()).unwrap();
+        assert_eq!(v.digests.len(), 2);
+        assert!(!v.is_ok());
+    }
+}
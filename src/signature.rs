@@ -0,0 +1,308 @@
+/* signature.rs - the signature header
+ *
+ * Copyright (c) 2017, Red Hat, Inc.
+ *
+ * This library is free software; you can redistribute it and/or modify it
+ * under the terms and conditions of the GNU Lesser General Public License
+ * as published by the Free Software Foundation; either version 2.1 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU Lesser General Public License for
+ * more details.
+ *
+ * Authors:
+ *   Will Woods <wwoods@redhat.com>
+ */
+use header::Header;
+use tag::{Tag, SigTag, TagID, TagInfo, TagType, TagValue};
+use writer::{write_header_section, value_type};
+use error::{RPMError, RPMFileError};
+use Result;
+
+// A struct of info for each signature tag, like TagInfo
+#[derive(Debug,PartialEq,Eq)]
+pub struct SigTagInfo {
+    pub name: &'static str,
+    pub id: SigTag,
+    pub ttype: TagType,
+}
+
+// The signature tags, from rpmSigTag_e in rpm/lib/rpmtag.h.
+pub static SIG_TAG_INFO_TABLE: [SigTagInfo;17] = [
+    SigTagInfo {name:"BADSHA1_1", id:SigTag::BADSHA1_1, ttype:TagType::STRING},
+    SigTagInfo {name:"BADSHA1_2", id:SigTag::BADSHA1_2, ttype:TagType::STRING},
+    SigTagInfo {name:"DSA", id:SigTag::DSA, ttype:TagType::BIN},
+    SigTagInfo {name:"GPG", id:SigTag::GPG, ttype:TagType::BIN},
+    SigTagInfo {name:"LEMD5_1", id:SigTag::LEMD5_1, ttype:TagType::BIN},
+    SigTagInfo {name:"LEMD5_2", id:SigTag::LEMD5_2, ttype:TagType::BIN},
+    SigTagInfo {name:"LONGARCHIVESIZE", id:SigTag::LONGARCHIVESIZE, ttype:TagType::INT64},
+    SigTagInfo {name:"LONGSIZE", id:SigTag::LONGSIZE, ttype:TagType::INT64},
+    SigTagInfo {name:"MD5", id:SigTag::MD5, ttype:TagType::BIN},
+    SigTagInfo {name:"PAYLOADSIZE", id:SigTag::PAYLOADSIZE, ttype:TagType::INT32},
+    SigTagInfo {name:"PGP", id:SigTag::PGP, ttype:TagType::BIN},
+    SigTagInfo {name:"PGP5", id:SigTag::PGP5, ttype:TagType::BIN},
+    SigTagInfo {name:"RESERVEDSPACE", id:SigTag::RESERVEDSPACE, ttype:TagType::BIN},
+    SigTagInfo {name:"RSA", id:SigTag::RSA, ttype:TagType::BIN},
+    SigTagInfo {name:"SHA1", id:SigTag::SHA1, ttype:TagType::STRING},
+    SigTagInfo {name:"SHA256", id:SigTag::SHA256, ttype:TagType::STRING},
+    SigTagInfo {name:"SIZE", id:SigTag::SIZE, ttype:TagType::INT32},
+];
+
+impl SigTagInfo {
+    // look up SigTagInfo for the given name. Matching is case-insensitive.
+    pub fn from_name(name: &str) -> Option<&'static SigTagInfo> {
+        let name = name.to_uppercase();
+        SIG_TAG_INFO_TABLE.iter().find(|ti| ti.name == name)
+    }
+    // Look up SigTagInfo for the given TagID. Note that signature tags
+    // >= 1000 have the same numbers as unrelated main header tags, like
+    // SigTag::SIZE and Tag::NAME.
+    pub fn from_id(id: TagID) -> Option<&'static SigTagInfo> {
+        SIG_TAG_INFO_TABLE.iter().find(|ti| ti.id as TagID == id)
+    }
+}
+
+/// The signature header, which holds the package's digests and signatures.
+///
+/// It's a header section just like the main header, but its tags are
+/// numbered differently; see `SigTag`. Accessors return `None` if the tag
+/// isn't there, since any of them can be missing (say, in an unsigned
+/// package), and `RPMFileError::WrongSigTagType` if it has the wrong type.
+#[derive(Debug,Default)]
+pub struct SignatureHeader {
+    header: Header,
+}
+
+fn wrong_type(tag: SigTag) -> RPMError {
+    RPMError::File(RPMFileError::WrongSigTagType(tag))
+}
+
+impl SignatureHeader {
+    /// Wraps a parsed signature header section.
+    pub fn new(header: Header) -> SignatureHeader {
+        SignatureHeader { header }
+    }
+    /// The underlying header.
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+    /// Unwrap the underlying header.
+    pub fn into_header(self) -> Header {
+        self.header
+    }
+    /// Number of tags in the header.
+    pub fn len(&self) -> usize {
+        self.header.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.header.is_empty()
+    }
+
+    /// The name of a tag in the signature header. Tags below 1000 are
+    /// shared with the main header (like the HEADERSIGNATURES region tag).
+    pub fn tag_name(id: TagID) -> Option<&'static str> {
+        match SigTagInfo::from_id(id) {
+            Some(ti) => Some(ti.name),
+            None if id < 1000 => TagInfo::from_id(id).map(|ti| ti.name),
+            None => None,
+        }
+    }
+
+    /// Get the raw value of a tag.
+    pub fn get(&self, tag: SigTag) -> Option<&TagValue> {
+        self.header.get(&(tag as TagID))
+    }
+    // The type a tag is supposed to have, like tag_name()
+    fn tag_type(id: TagID) -> Option<TagType> {
+        match SigTagInfo::from_id(id) {
+            Some(ti) => Some(ti.ttype),
+            None if id < 1000 => TagInfo::from_id(id).map(|ti| ti.ttype),
+            None => None,
+        }
+    }
+
+    /// Set the value of a tag, returning the old value if there was one.
+    pub fn insert(&mut self, tag: SigTag, value: TagValue) -> Option<TagValue> {
+        let id = tag as TagID;
+        let expected = match self.header.entry(&id) {
+            Some(entry) => Some(entry.tagtype),
+            None => SignatureHeader::tag_type(id),
+        };
+        self.header.insert_with_type(id, value_type(expected, &value), value)
+    }
+    /// Remove a tag, returning its value if it was there.
+    pub fn remove(&mut self, tag: SigTag) -> Option<TagValue> {
+        self.header.remove(&(tag as TagID))
+    }
+    /// Remove all the OpenPGP signatures, leaving the digests alone.
+    pub fn remove_signatures(&mut self) {
+        for &tag in &[SigTag::RSA, SigTag::DSA, SigTag::PGP, SigTag::PGP5, SigTag::GPG] {
+            self.remove(tag);
+        }
+    }
+
+    /// Write the signature header out the way it is in a package: a header
+    /// section, in tag order, in a HEADERSIGNATURES region, padded to a
+    /// multiple of 8 bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let region = Tag::HEADERSIGNATURES as TagID;
+        let mut section = write_header_section(&self.header, Some(region));
+        // The section header and index entries are 16 bytes each, so padding
+        // the whole section to 8 bytes pads the data store the same way.
+        let size = section.len();
+        section.resize(size + (8 - size % 8) % 8, 0);
+        section
+    }
+    fn get_binary(&self, tag: SigTag) -> Result<Option<&[u8]>> {
+        match self.get(tag) {
+            None => Ok(None),
+            Some(TagValue::Binary(v)) => Ok(Some(v)),
+            Some(_) => Err(wrong_type(tag)),
+        }
+    }
+    fn get_string(&self, tag: SigTag) -> Result<Option<&str>> {
+        match self.get(tag) {
+            None => Ok(None),
+            Some(TagValue::String(v)) if v.len() == 1 => Ok(Some(&v[0])),
+            Some(_) => Err(wrong_type(tag)),
+        }
+    }
+    fn get_u64(&self, tag: SigTag) -> Result<Option<u64>> {
+        match self.get(tag) {
+            None => Ok(None),
+            Some(TagValue::Int32(v)) if v.len() == 1 => Ok(Some(v[0] as u64)),
+            Some(TagValue::Int64(v)) if v.len() == 1 => Ok(Some(v[0])),
+            Some(_) => Err(wrong_type(tag)),
+        }
+    }
+    // The 64-bit tag if it's there, otherwise the 32-bit one
+    fn get_size(&self, long: SigTag, short: SigTag) -> Result<Option<u64>> {
+        match self.get_u64(long)? {
+            Some(size) => Ok(Some(size)),
+            None => self.get_u64(short),
+        }
+    }
+
+    /// Size of the main header plus the (compressed) payload.
+    pub fn size(&self) -> Result<Option<u64>> {
+        self.get_size(SigTag::LONGSIZE, SigTag::SIZE)
+    }
+    /// Size of the uncompressed payload.
+    pub fn payload_size(&self) -> Result<Option<u64>> {
+        self.get_size(SigTag::LONGARCHIVESIZE, SigTag::PAYLOADSIZE)
+    }
+    /// MD5 digest of the main header plus the payload.
+    pub fn md5(&self) -> Result<Option<&[u8]>> {
+        self.get_binary(SigTag::MD5)
+    }
+    /// Hex SHA1 digest of the main header.
+    pub fn sha1(&self) -> Result<Option<&str>> {
+        self.get_string(SigTag::SHA1)
+    }
+    /// Hex SHA256 digest of the main header.
+    pub fn sha256(&self) -> Result<Option<&str>> {
+        self.get_string(SigTag::SHA256)
+    }
+    /// OpenPGP RSA signature of the main header.
+    pub fn rsa(&self) -> Result<Option<&[u8]>> {
+        self.get_binary(SigTag::RSA)
+    }
+    /// OpenPGP DSA signature of the main header.
+    pub fn dsa(&self) -> Result<Option<&[u8]>> {
+        self.get_binary(SigTag::DSA)
+    }
+    /// OpenPGP RSA signature of the main header plus the payload.
+    pub fn pgp(&self) -> Result<Option<&[u8]>> {
+        self.get_binary(SigTag::PGP)
+    }
+    /// OpenPGP DSA signature of the main header plus the payload.
+    pub fn gpg(&self) -> Result<Option<&[u8]>> {
+        self.get_binary(SigTag::GPG)
+    }
+}
+
+impl From<Header> for SignatureHeader {
+    fn from(header: Header) -> SignatureHeader {
+        SignatureHeader::new(header)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reader::Reader;
+    use tag::Tag;
+    static BINRPM1: &[u8] = include_bytes!("../tests/rpms/binary.x86_64.rpm");
+
+    #[test]
+    fn sig_tag_info() {
+        assert_eq!(SigTagInfo::from_id(1000).unwrap().id, SigTag::SIZE);
+        assert_eq!(SigTagInfo::from_name("md5").unwrap().id, SigTag::MD5);
+        assert_eq!(SignatureHeader::tag_name(1004), Some("MD5"));
+        assert_eq!(SignatureHeader::tag_name(Tag::HEADERSIGNATURES as TagID), Some("HEADERSIGNATURES"));
+        assert_eq!(SignatureHeader::tag_name(1999), None);
+        // the table is sorted, like TAG_INFO_TABLE
+        assert!(SIG_TAG_INFO_TABLE.windows(2).all(|w| w[0].name < w[1].name));
+    }
+
+    #[test]
+    fn sig_accessors() {
+        let mut r = Reader::from_bytes(BINRPM1);
+        let _ = r.lead();
+        let sig = r.signature().unwrap();
+        assert_eq!(sig.len(), 8);
+        // the main header starts at 0x1578, and runs to the end with the payload
+        assert_eq!(sig.size().unwrap(), Some(BINRPM1.len() as u64 - 0x1578));
+        assert_eq!(sig.md5().unwrap().map(|d| d.len()), Some(16));
+        assert_eq!(sig.sha1().unwrap().map(|d| d.len()), Some(40));
+        assert_eq!(sig.sha256().unwrap(), None);
+        assert!(sig.rsa().unwrap().is_some());
+        assert!(sig.pgp().unwrap().is_some());
+        assert_eq!(sig.dsa().unwrap(), None);
+        assert!(sig.payload_size().unwrap().is_some());
+    }
+
+    #[test]
+    fn sig_round_trip() {
+        let mut r = Reader::from_bytes(BINRPM1);
+        let _ = r.lead();
+        let sig = r.signature().unwrap();
+        // the signature header, and its padding, runs up to the main header
+        assert_eq!(sig.to_bytes(), &BINRPM1[0x60..0x1578]);
+    }
+
+    #[test]
+    fn sig_remove_signatures() {
+        let mut r = Reader::from_bytes(BINRPM1);
+        let _ = r.lead();
+        let mut sig = r.signature().unwrap();
+        sig.remove_signatures();
+        assert_eq!(sig.len(), 6);
+        assert_eq!(sig.rsa().unwrap(), None);
+        assert_eq!(sig.pgp().unwrap(), None);
+        assert!(sig.md5().unwrap().is_some());
+
+        // and it still reads back in
+        let bytes = sig.to_bytes();
+        assert_eq!(bytes.len() % 8, 0);
+        let mut r = Reader::from_bytes(bytes);
+        let mut sig2 = r.signature().unwrap();
+        // except for the region trailer, which now covers fewer tags
+        let region = Tag::HEADERSIGNATURES as TagID;
+        assert_ne!(sig2.header.remove(&region), sig.header.remove(&region));
+        assert_eq!(sig2.header(), sig.header());
+    }
+
+    #[test]
+    fn sig_wrong_tag_type() {
+        let mut hdr = Header::new();
+        hdr.insert(SigTag::MD5 as TagID, TagValue::String(vec![String::from("abc")]));
+        let sig = SignatureHeader::new(hdr);
+        match sig.md5() {
+            Err(RPMError::File(RPMFileError::WrongSigTagType(tag))) => assert_eq!(tag, SigTag::MD5),
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+}
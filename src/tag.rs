@@ -18,7 +18,7 @@
 // RPM tags are identified by an i32
 pub type TagID = i32;
 // the Tag enum has all the known tags
-pub use rpmtag::{Tag, TagType};
+pub use rpmtag::{Tag, SigTag, TagType};
 // TagReturnType tells us what the value should be
 use rpmtag::TagReturnType;
 
@@ -34,7 +34,7 @@ pub struct TagInfo {
 }
 
 // Represents the possible variants for a TagValue
-#[derive(Debug,PartialEq,Eq)]
+#[derive(Debug,Clone,PartialEq,Eq)]
 pub enum TagValue {
     Null,
     Char(Vec<u8>),          // C unsigned char == uint8_t
@@ -0,0 +1,243 @@
+/* totar.rs - convert an RPM payload into a tar archive (like rpm2archive)
+ *
+ * Copyright (c) 2017, Red Hat, Inc.
+ *
+ * This library is free software; you can redistribute it and/or modify it
+ * under the terms and conditions of the GNU Lesser General Public License
+ * as published by the Free Software Foundation; either version 2.1 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU Lesser General Public License for
+ * more details.
+ *
+ * Authors:
+ *   Will Woods <wwoods@redhat.com>
+ */
+use std::io::prelude::*;
+use std::io;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+use tar::{Builder, EntryType, Header as TarHeader};
+
+use archive::Archive;
+use files::{FileEntry, S_IFMT, S_IFDIR, S_IFREG, S_IFLNK, S_IFCHR, S_IFBLK, S_IFIFO};
+use header::Header;
+use error::{RPMError, RPMFileError};
+use Result;
+
+// The biggest size that fits in a ustar header's 11 octal digits
+const USTAR_MAX_SIZE: u64 = 0o77777777777;
+// ...and the longest user/group name
+const USTAR_MAX_NAME: usize = 32;
+
+// Format a single pax extended header record: "<len> <key>=<value>\n",
+// where <len> counts the whole record, including its own digits.
+fn pax_record(key: &str, value: &[u8]) -> Vec<u8> {
+    let rest = key.len() + value.len() + 3;
+    let mut len = rest + 1;
+    while len != rest + len.to_string().len() {
+        len = rest + len.to_string().len();
+    }
+    let mut rec = format!("{} {}=", len, key).into_bytes();
+    rec.extend_from_slice(value);
+    rec.push(b'\n');
+    rec
+}
+
+// A short name to put in the ustar header when the real one is in the pax
+// header; readers that don't understand pax get at least the file's name.
+fn short_name(path: &str) -> &Path {
+    let name = Path::new(path).file_name().map(|n| n.as_bytes()).unwrap_or(b"");
+    let start = name.len().saturating_sub(99);
+    Path::new(OsStr::from_bytes(&name[start..]))
+}
+
+// Tar entries are relative to the root of the package.
+fn tar_path(path: &str) -> String {
+    String::from(path.trim_start_matches('/'))
+}
+
+struct TarWriter<W: io::Write> {
+    builder: Builder<W>,
+}
+
+impl<W: io::Write> TarWriter<W> {
+    // Write one entry, adding a pax header first for anything that ustar
+    // can't hold (long paths and link targets, big files, long names).
+    fn append<R: io::Read>(&mut self, path: &str, file: &FileEntry, kind: EntryType,
+                           size: u64, linkto: Option<&str>, data: R) -> Result<()> {
+        let mut pax = Vec::new();
+        let mut hdr = TarHeader::new_ustar();
+        hdr.set_entry_type(kind);
+        hdr.set_mode(file.mode & 0o7777);
+        hdr.set_mtime(file.mtime as u64);
+        // numeric ids aren't in the header; the user/group names are what count
+        hdr.set_uid(0);
+        hdr.set_gid(0);
+        hdr.set_size(size);
+        if size > USTAR_MAX_SIZE {
+            pax.extend(pax_record("size", size.to_string().as_bytes()));
+        }
+        if hdr.set_path(path).is_err() {
+            pax.extend(pax_record("path", path.as_bytes()));
+            hdr.set_path(short_name(path))?;
+        }
+        if let Some(linkto) = linkto {
+            if hdr.set_link_name_literal(linkto).is_err() {
+                pax.extend(pax_record("linkpath", linkto.as_bytes()));
+                hdr.set_link_name_literal(short_name(linkto).as_os_str().as_bytes())?;
+            }
+        }
+        if file.user.len() > USTAR_MAX_NAME {
+            pax.extend(pax_record("uname", file.user.as_bytes()));
+        } else {
+            hdr.set_username(&file.user)?;
+        }
+        if file.group.len() > USTAR_MAX_NAME {
+            pax.extend(pax_record("gname", file.group.as_bytes()));
+        } else {
+            hdr.set_groupname(&file.group)?;
+        }
+        if file.mode & S_IFMT == S_IFCHR || file.mode & S_IFMT == S_IFBLK {
+            hdr.set_device_major(file.rdev_major())?;
+            hdr.set_device_minor(file.rdev_minor())?;
+        }
+
+        if !pax.is_empty() {
+            let mut xhdr = TarHeader::new_ustar();
+            xhdr.set_entry_type(EntryType::XHeader);
+            xhdr.set_path(Path::new("PaxHeaders").join(short_name(path)))?;
+            xhdr.set_mode(0o644);
+            xhdr.set_mtime(file.mtime as u64);
+            xhdr.set_size(pax.len() as u64);
+            xhdr.set_cksum();
+            self.builder.append(&xhdr, &pax[..])?;
+        }
+        hdr.set_cksum();
+        self.builder.append(&hdr, data)?;
+        Ok(())
+    }
+}
+
+/// Convert a package's payload archive into a POSIX (pax) tar archive,
+/// written to `out`. `hdr` is the package's main header.
+///
+/// Modes, mtimes and owner/group names come from the header, and ghost
+/// files are left out. The output only depends on the package, so the
+/// same RPM always gives the same tar, byte for byte.
+pub fn to_tar<R, W>(archive: &mut Archive<R>, hdr: &Header, out: W) -> Result<W>
+        where R: io::Read, W: io::Write {
+    let files = FileEntry::from_header(hdr)?;
+    let mut tar = TarWriter { builder: Builder::new(out) };
+    // hardlinks that are waiting for the link that has the data
+    let mut links: HashMap<(u32, u32), Vec<usize>> = HashMap::new();
+
+    while let Some(mut entry) = archive.next_entry()? {
+        let index = entry.index;
+        let file = &files[index];
+        let path = tar_path(&entry.path);
+        match entry.mode & S_IFMT {
+            S_IFDIR => tar.append(&path, file, EntryType::Directory, 0, None, io::empty())?,
+            S_IFREG if !file.content => {
+                links.entry((file.device, file.inode)).or_default().push(index);
+            },
+            S_IFREG => {
+                let size = entry.size;
+                tar.append(&path, file, EntryType::Regular, size, None, &mut entry)?;
+                for link in links.remove(&(file.device, file.inode)).unwrap_or_default() {
+                    let link = &files[link];
+                    tar.append(&tar_path(&link.path), link, EntryType::Link, 0, Some(&path), io::empty())?;
+                }
+            },
+            S_IFLNK => {
+                let mut linkto = String::new();
+                entry.read_to_string(&mut linkto)?;
+                tar.append(&path, file, EntryType::Symlink, 0, Some(&linkto), io::empty())?;
+            },
+            S_IFCHR => tar.append(&path, file, EntryType::Char, 0, None, io::empty())?,
+            S_IFBLK => tar.append(&path, file, EntryType::Block, 0, None, io::empty())?,
+            S_IFIFO => tar.append(&path, file, EntryType::Fifo, 0, None, io::empty())?,
+            _ => return Err(RPMError::File(RPMFileError::UnknownFiletype)),
+        }
+    }
+    Ok(tar.builder.into_inner()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tar;
+    use reader::Reader;
+    use tag::{Tag, TagID, TagValue};
+    static BINRPM1: &[u8] = include_bytes!("../tests/rpms/binary.x86_64.rpm");
+
+    fn convert() -> Vec<u8> {
+        let mut r = Reader::from_bytes(BINRPM1);
+        let _ = r.lead();
+        let _ = r.header().unwrap();
+        let hdr = r.header().unwrap();
+        let mut archive = r.archive(&hdr).unwrap();
+        to_tar(&mut archive, &hdr, Vec::new()).unwrap()
+    }
+
+    #[test]
+    fn pax_record_length() {
+        assert_eq!(pax_record("path", b"foo"), b"12 path=foo\n".to_vec());
+        // adding a digit to the length makes it need another digit
+        let rec = pax_record("path", &[b'x'; 91]);
+        assert_eq!(&rec[..4], b"101 ");
+        assert_eq!(rec.len(), 101);
+    }
+
+    #[test]
+    fn rpm_to_tar() {
+        let out = convert();
+        let mut tarball = tar::Archive::new(&out[..]);
+        let entries: Vec<_> = tarball.entries().unwrap().map(|e| {
+            let e = e.unwrap();
+            let h = e.header();
+            (h.path().unwrap().into_owned(), h.mode().unwrap(), h.size().unwrap(),
+             h.mtime().unwrap(), h.username().unwrap().map(String::from))
+        }).collect();
+        assert_eq!(entries.len(), 4);
+        assert_eq!(entries[0], (Path::new("usr/sbin/hardlink").to_path_buf(), 0o755, 15720,
+                                1454553867, Some(String::from("root"))));
+        assert_eq!(entries[1].0, Path::new("usr/share/licenses/hardlink"));
+        assert_eq!(entries[1].1, 0o755);
+    }
+
+    #[test]
+    fn long_path_to_tar() {
+        let name = "x".repeat(150);
+        let mut hdr = Header::new();
+        hdr.insert(Tag::OLDFILENAMES as TagID, TagValue::String(vec![format!("/{}", name)]));
+        hdr.insert(Tag::FILEMODES as TagID, TagValue::Int16(vec![0o100600]));
+        hdr.insert(Tag::FILESIZES as TagID, TagValue::Int32(vec![2]));
+        hdr.insert(Tag::FILEUSERNAME as TagID, TagValue::String(vec!["u".repeat(40)]));
+        let payload = format!("07070X00000000\0\0hi\0\0070701{:088x}{:08x}{:08x}TRAILER!!!\0", 0, 11, 0);
+        let mut archive = Archive::new(payload.as_bytes(), &hdr).unwrap();
+        let out = to_tar(&mut archive, &hdr, Vec::new()).unwrap();
+
+        let mut tarball = tar::Archive::new(&out[..]);
+        let mut entries = tarball.entries().unwrap();
+        let mut e = entries.next().unwrap().unwrap();
+        assert_eq!(e.path().unwrap(), Path::new(&name));
+        let mut data = String::new();
+        e.read_to_string(&mut data).unwrap();
+        assert_eq!(data, "hi");
+        let uname = e.pax_extensions().unwrap().unwrap()
+                     .map(|x| x.unwrap()).find(|x| x.key().unwrap() == "uname").unwrap();
+        assert_eq!(uname.value().unwrap(), "u".repeat(40));
+        assert!(entries.next().is_none());
+    }
+
+    #[test]
+    fn rpm_to_tar_reproducible() {
+        assert_eq!(convert(), convert());
+    }
+}
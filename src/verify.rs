@@ -0,0 +1,480 @@
+/* verify.rs - check the digests in an RPM (like rpm -K --nosignature)
+ *
+ * Copyright (c) 2017, Red Hat, Inc.
+ *
+ * This library is free software; you can redistribute it and/or modify it
+ * under the terms and conditions of the GNU Lesser General Public License
+ * as published by the Free Software Foundation; either version 2.1 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU Lesser General Public License for
+ * more details.
+ *
+ * Authors:
+ *   Will Woods <wwoods@redhat.com>
+ */
+use std::io;
+use std::fmt;
+
+use digest::DynDigest;
+use md5::Md5;
+use sha1::Sha1;
+use sha2::{Sha224, Sha256, Sha384, Sha512};
+
+use header::Header;
+use signature::SignatureHeader;
+use reader::Reader;
+use files::{strings, ints};
+use tag::{Tag, SigTag};
+use pgp::{PgpSignature, Keyring, Verdict, pubkey_algo_name, hash_algo_name};
+use error::{RPMError, RPMFileError};
+use Result;
+
+/// The digests that protect a package's contents.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum DigestKind {
+    /// SHA1 of the main header (SigTag::SHA1)
+    HeaderSHA1,
+    /// SHA256 of the main header (SigTag::SHA256)
+    HeaderSHA256,
+    /// MD5 of the main header plus the payload (SigTag::MD5)
+    HeaderPayloadMD5,
+    /// Digest of the compressed payload, from the main header's
+    /// PAYLOADDIGEST, using PAYLOADDIGESTALGO
+    Payload,
+}
+
+impl fmt::Display for DigestKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DigestKind::HeaderSHA1 => write!(f, "header SHA1"),
+            DigestKind::HeaderSHA256 => write!(f, "header SHA256"),
+            DigestKind::HeaderPayloadMD5 => write!(f, "header+payload MD5"),
+            DigestKind::Payload => write!(f, "payload"),
+        }
+    }
+}
+
+// OpenPGP hash algorithm ids (RFC 4880, 9.4), which rpm uses everywhere
+pub(crate) const PGPHASHALGO_MD5: u32 = 1;
+pub(crate) const PGPHASHALGO_SHA1: u32 = 2;
+pub(crate) const PGPHASHALGO_SHA256: u32 = 8;
+pub(crate) const PGPHASHALGO_SHA384: u32 = 9;
+pub(crate) const PGPHASHALGO_SHA512: u32 = 10;
+pub(crate) const PGPHASHALGO_SHA224: u32 = 11;
+
+// Get a hasher for the given OpenPGP hash algorithm.
+pub(crate) fn hasher(algo: u32) -> Result<Box<dyn DynDigest>> {
+    Ok(match algo {
+        PGPHASHALGO_MD5 => Box::new(Md5::default()),
+        PGPHASHALGO_SHA1 => Box::new(Sha1::default()),
+        PGPHASHALGO_SHA256 => Box::new(Sha256::default()),
+        PGPHASHALGO_SHA384 => Box::new(Sha384::default()),
+        PGPHASHALGO_SHA512 => Box::new(Sha512::default()),
+        PGPHASHALGO_SHA224 => Box::new(Sha224::default()),
+        _ => return Err(RPMError::File(RPMFileError::UnknownDigestAlgo(algo))),
+    })
+}
+
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// A digest we're computing, and the hex digest it's supposed to come out to
+struct Check {
+    kind: DigestKind,
+    hasher: Box<dyn DynDigest>,
+    expected: String,
+}
+
+impl Check {
+    fn new(kind: DigestKind, algo: u32, expected: String) -> Result<Check> {
+        Ok(Check { kind, hasher: hasher(algo)?, expected: expected.to_lowercase() })
+    }
+    fn finish(self) -> Result<DigestKind> {
+        if hex(&self.hasher.finalize()) == self.expected {
+            Ok(self.kind)
+        } else {
+            Err(RPMError::File(RPMFileError::DigestMismatch(self.kind)))
+        }
+    }
+}
+
+// Feeds everything written to it into a bunch of hashers
+struct Hashers<'a>(Vec<&'a mut Box<dyn DynDigest>>);
+
+impl<'a> io::Write for Hashers<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for hasher in self.0.iter_mut() {
+            hasher.update(buf);
+        }
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The result of checking one of a package's OpenPGP signatures.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct SignatureCheck {
+    /// The signature tag it came from
+    pub tag: SigTag,
+    pub signature: PgpSignature,
+    pub verdict: Verdict,
+}
+
+impl SignatureCheck {
+    /// True if the signature covers only the main header.
+    pub fn is_header_only(&self) -> bool {
+        header_only(self.tag)
+    }
+}
+
+fn header_only(tag: SigTag) -> bool {
+    tag == SigTag::RSA || tag == SigTag::DSA
+}
+
+// Like rpm -Kv: "Header V4 RSA/SHA256 Signature, key ID 81b46521: OK"
+impl fmt::Display for SignatureCheck {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sig = &self.signature;
+        write!(f, "{}V{} {}/{} Signature, key ID {:08x}: {}",
+               if self.is_header_only() { "Header " } else { "" },
+               sig.version, pubkey_algo_name(sig.pubkey_algo), hash_algo_name(sig.hash_algo),
+               sig.key_id & 0xffffffff, self.verdict)
+    }
+}
+
+/// Everything that was checked in a package.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct Verification {
+    /// The digests that were checked (and matched)
+    pub digests: Vec<DigestKind>,
+    /// The signatures that were found, and what came of checking them
+    pub signatures: Vec<SignatureCheck>,
+}
+
+impl Verification {
+    /// True if the package had at least one signature, and every one of
+    /// them was good.
+    pub fn is_ok(&self) -> bool {
+        !self.signatures.is_empty() && self.signatures.iter().all(|s| s.verdict == Verdict::Ok)
+    }
+}
+
+// A signature we're checking, and the hasher for the data it covers
+struct SigCheck {
+    tag: SigTag,
+    signature: PgpSignature,
+    hasher: Option<Box<dyn DynDigest>>,
+}
+
+impl SigCheck {
+    fn new(tag: SigTag, data: &[u8], keyring: &Keyring) -> Result<SigCheck> {
+        let signature = PgpSignature::parse(data)?;
+        // no point hashing anything if we don't have the key
+        let hasher = match keyring.find(signature.key_id) {
+            Some(_) => Some(signature.hasher()?),
+            None => None,
+        };
+        Ok(SigCheck { tag, signature, hasher })
+    }
+    fn finish(self, keyring: &Keyring) -> SignatureCheck {
+        let verdict = match (self.hasher, keyring.find(self.signature.key_id)) {
+            (Some(hasher), Some(key)) => {
+                if self.signature.verify(hasher, key) { Verdict::Ok } else { Verdict::Bad }
+            },
+            _ => Verdict::NoKey,
+        };
+        SignatureCheck { tag: self.tag, signature: self.signature, verdict }
+    }
+}
+
+fn check_package<R: io::Read>(sig: &SignatureHeader, hdr: &Header, raw_hdr: &[u8],
+                              mut payload: R, keyring: Option<&Keyring>) -> Result<Verification> {
+    let mut header_checks = Vec::new();
+    if let Some(sha1) = sig.sha1()? {
+        header_checks.push(Check::new(DigestKind::HeaderSHA1, PGPHASHALGO_SHA1, sha1.into())?);
+    }
+    if let Some(sha256) = sig.sha256()? {
+        header_checks.push(Check::new(DigestKind::HeaderSHA256, PGPHASHALGO_SHA256, sha256.into())?);
+    }
+    let mut payload_checks = Vec::new();
+    if let Some(md5) = sig.md5()? {
+        payload_checks.push(Check::new(DigestKind::HeaderPayloadMD5, PGPHASHALGO_MD5, hex(md5))?);
+    }
+    if let Some(digest) = strings(hdr, Tag::PAYLOADDIGEST)?.first() {
+        // rpm has only ever written SHA256 payload digests, if it's not said
+        let algo = ints(hdr, Tag::PAYLOADDIGESTALGO)?.first().map(|&a| a as u32);
+        let algo = algo.unwrap_or(PGPHASHALGO_SHA256);
+        payload_checks.push(Check::new(DigestKind::Payload, algo, digest.clone())?);
+    }
+    let mut sig_checks = Vec::new();
+    if let Some(keyring) = keyring {
+        let tags = [(SigTag::RSA, sig.rsa()?), (SigTag::DSA, sig.dsa()?),
+                    (SigTag::PGP, sig.pgp()?), (SigTag::GPG, sig.gpg()?)];
+        for &(tag, data) in tags.iter() {
+            if let Some(data) = data {
+                sig_checks.push(SigCheck::new(tag, data, keyring)?);
+            }
+        }
+    }
+    // rpm doesn't call a package with nothing to check OK, and neither do we
+    if header_checks.is_empty() && payload_checks.is_empty() && sig_checks.is_empty() {
+        return Err(RPMError::File(RPMFileError::NoDigests));
+    }
+
+    let mut digests = Vec::new();
+    for mut check in header_checks {
+        check.hasher.update(raw_hdr);
+        digests.push(check.finish()?);
+    }
+    {
+        // Everything else covers the main header; only some cover the payload.
+        let mut payload_hashers = Vec::new();
+        for check in payload_checks.iter_mut() {
+            if check.kind == DigestKind::HeaderPayloadMD5 {
+                check.hasher.update(raw_hdr);
+            }
+            payload_hashers.push(&mut check.hasher);
+        }
+        for check in sig_checks.iter_mut() {
+            if let Some(ref mut hasher) = check.hasher {
+                hasher.update(raw_hdr);
+                if !header_only(check.tag) {
+                    payload_hashers.push(hasher);
+                }
+            }
+        }
+        io::copy(&mut payload, &mut Hashers(payload_hashers))?;
+    }
+    for check in payload_checks {
+        digests.push(check.finish()?);
+    }
+    let signatures = match keyring {
+        Some(keyring) => sig_checks.into_iter().map(|c| c.finish(keyring)).collect(),
+        None => Vec::new(),
+    };
+    Ok(Verification { digests, signatures })
+}
+
+/// Check the digests in a package, given its signature header, its main
+/// header (and the raw bytes of it, from `Reader::raw_header()`), and the
+/// (still compressed) payload, which is read to the end.
+///
+/// The header-only digests are checked before the payload is read, so a
+/// corrupt header is caught early. Returns the digests that were checked;
+/// the first one that doesn't match gives `RPMFileError::DigestMismatch`,
+/// and a package without any digests gives `RPMFileError::NoDigests`.
+pub fn verify_digests<R: io::Read>(sig: &SignatureHeader, hdr: &Header, raw_hdr: &[u8],
+                                   payload: R) -> Result<Vec<DigestKind>> {
+    Ok(check_package(sig, hdr, raw_hdr, payload, None)?.digests)
+}
+
+/// Read a whole package from `rdr`, checking its header and payload
+/// digests along the way; see `verify_digests()`. Signatures aren't
+/// checked, like `rpm -K --nosignature`.
+pub fn verify_package<R: io::Read>(rdr: R) -> Result<Vec<DigestKind>> {
+    let mut r = Reader::from_reader(rdr);
+    r.lead()?;
+    let sig = r.signature()?;
+    let (hdr, raw) = r.raw_header()?;
+    verify_digests(&sig, &hdr, &raw, r.payload()?)
+}
+
+/// Like `verify_digests()`, but also check the package's OpenPGP
+/// signatures against the keys in `keyring`.
+///
+/// A digest mismatch is still an error, but a signature that doesn't
+/// check out is just reported, with a `Verdict` for each one; see
+/// `Verification::is_ok()`. A package with neither digests nor signatures
+/// gives `RPMFileError::NoDigests`.
+pub fn verify_signatures<R: io::Read>(sig: &SignatureHeader, hdr: &Header, raw_hdr: &[u8],
+                                      payload: R, keyring: &Keyring) -> Result<Verification> {
+    check_package(sig, hdr, raw_hdr, payload, Some(keyring))
+}
+
+/// Read a whole package from `rdr`, checking its digests and signatures,
+/// like `rpm -Kv`; see `verify_signatures()`.
+pub fn verify_package_signatures<R: io::Read>(rdr: R, keyring: &Keyring) -> Result<Verification> {
+    let mut r = Reader::from_reader(rdr);
+    r.lead()?;
+    let sig = r.signature()?;
+    let (hdr, raw) = r.raw_header()?;
+    verify_signatures(&sig, &hdr, &raw, r.payload()?, keyring)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tag::{TagID, TagValue};
+    use pgp::Keyring;
+    static BINRPM1: &[u8] = include_bytes!("../tests/rpms/binary.x86_64.rpm");
+
+    fn mismatch(r: Result<Vec<DigestKind>>) -> DigestKind {
+        match r {
+            Err(RPMError::File(RPMFileError::DigestMismatch(kind))) => kind,
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn verify_rpm() {
+        assert_eq!(verify_package(BINRPM1).unwrap(),
+                   vec![DigestKind::HeaderSHA1, DigestKind::HeaderPayloadMD5]);
+    }
+
+    #[test]
+    fn verify_corrupt_payload() {
+        let mut rpm = BINRPM1.to_vec();
+        let last = rpm.len() - 1;
+        rpm[last] ^= 0xff;
+        assert_eq!(mismatch(verify_package(&rpm[..])), DigestKind::HeaderPayloadMD5);
+    }
+
+    #[test]
+    fn verify_corrupt_header() {
+        let mut rpm = BINRPM1.to_vec();
+        let summary = b"Create a tree of hardlinks";
+        let pos = rpm.windows(summary.len()).position(|w| w == summary).unwrap();
+        rpm[pos] = b'c';
+        assert_eq!(mismatch(verify_package(&rpm[..])), DigestKind::HeaderSHA1);
+    }
+
+    #[test]
+    fn verify_no_digests() {
+        let mut r = Reader::from_bytes(BINRPM1);
+        r.lead().unwrap();
+        let mut sig = r.signature().unwrap();
+        for &tag in [SigTag::SHA1, SigTag::SHA256, SigTag::MD5].iter() {
+            sig.remove(tag);
+        }
+        // the fixture has no PAYLOADDIGEST to begin with
+        let mut rpm = BINRPM1[..96].to_vec();
+        rpm.extend_from_slice(&sig.to_bytes());
+        rpm.extend_from_slice(&BINRPM1[0x1578..]);
+        let v = verify_package_signatures(&rpm[..], &Keyring::new()).unwrap();
+        assert!(v.digests.is_empty());
+        assert_eq!(verdicts(&v), vec![Verdict::NoKey, Verdict::NoKey]);
+
+        sig.remove_signatures();
+        let mut rpm = BINRPM1[..96].to_vec();
+        rpm.extend_from_slice(&sig.to_bytes());
+        rpm.extend_from_slice(&BINRPM1[0x1578..]);
+        match verify_package(&rpm[..]) {
+            Err(RPMError::File(RPMFileError::NoDigests)) => (),
+            r => panic!("unexpected result: {:?}", r),
+        }
+        match verify_package_signatures(&rpm[..], &Keyring::new()) {
+            Err(RPMError::File(RPMFileError::NoDigests)) => (),
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn verify_payload_digest() {
+        let sig = SignatureHeader::default();
+        let mut hdr = Header::new();
+        // sha256("abc")
+        let digest = "BA7816BF8F01CFEA414140DE5DAE2223B00361A396177A9CB410FF61F20015AD";
+        hdr.insert(Tag::PAYLOADDIGEST as TagID, TagValue::String(vec![String::from(digest)]));
+        hdr.insert(Tag::PAYLOADDIGESTALGO as TagID, TagValue::Int32(vec![PGPHASHALGO_SHA256]));
+        assert_eq!(verify_digests(&sig, &hdr, b"", &b"abc"[..]).unwrap(), vec![DigestKind::Payload]);
+        assert_eq!(mismatch(verify_digests(&sig, &hdr, b"", &b"abd"[..])), DigestKind::Payload);
+
+        hdr.insert(Tag::PAYLOADDIGESTALGO as TagID, TagValue::Int32(vec![99]));
+        match verify_digests(&sig, &hdr, b"", &b"abc"[..]) {
+            Err(RPMError::File(RPMFileError::UnknownDigestAlgo(99))) => (),
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    // The fixture's main header and payload, with the given signatures
+    // in an otherwise empty signature header
+    fn signed(sigs: &[(SigTag, &[u8])]) -> (SignatureHeader, Header, Vec<u8>, &'static [u8]) {
+        let mut r = Reader::from_bytes(BINRPM1);
+        r.lead().unwrap();
+        r.signature().unwrap();
+        let (hdr, raw) = r.raw_header().unwrap();
+        let mut sig = Header::new();
+        for &(tag, data) in sigs {
+            sig.insert(tag as TagID, TagValue::Binary(data.to_vec()));
+        }
+        (SignatureHeader::new(sig), hdr, raw, &BINRPM1[0x313a..])
+    }
+
+    fn keyring() -> Keyring {
+        let mut keyring = Keyring::new();
+        for key in ["rsa", "dsa", "ecdsa", "eddsa"].iter() {
+            keyring.load(format!("tests/keys/{}.asc", key)).unwrap();
+        }
+        keyring
+    }
+
+    fn verdicts(v: &Verification) -> Vec<Verdict> {
+        v.signatures.iter().map(|s| s.verdict).collect()
+    }
+
+    #[test]
+    fn verify_rpm_nokey() {
+        let v = verify_package_signatures(BINRPM1, &Keyring::new()).unwrap();
+        assert_eq!(v.digests, vec![DigestKind::HeaderSHA1, DigestKind::HeaderPayloadMD5]);
+        assert_eq!(verdicts(&v), vec![Verdict::NoKey, Verdict::NoKey]);
+        assert!(!v.is_ok());
+        let rsa = &v.signatures[0];
+        assert_eq!(rsa.tag, SigTag::RSA);
+        assert_eq!(rsa.signature.version, 3);
+        assert_eq!(rsa.signature.key_id, 0x73bde98381b46521);
+        assert_eq!(rsa.signature.hash_algo as u32, PGPHASHALGO_SHA256);
+        assert_eq!(rsa.to_string(), "Header V3 RSA/SHA256 Signature, key ID 81b46521: NOKEY");
+        assert_eq!(v.signatures[1].tag, SigTag::PGP);
+    }
+
+    #[test]
+    fn verify_header_signatures() {
+        let keyring = keyring();
+        let keys = [(SigTag::RSA, "rsa"), (SigTag::DSA, "dsa"), (SigTag::RSA, "ecdsa"), (SigTag::RSA, "eddsa")];
+        for &(tag, key) in keys.iter() {
+            let data = fs::read(format!("tests/keys/{}.hdr.sig", key)).unwrap();
+            let (sig, hdr, raw, payload) = signed(&[(tag, &data)]);
+            let v = verify_signatures(&sig, &hdr, &raw, payload, &keyring).unwrap();
+            assert_eq!(verdicts(&v), vec![Verdict::Ok], "{} signature", key);
+            assert!(v.is_ok());
+
+            // a signature over a different header is bad
+            let mut raw = raw.clone();
+            let last = raw.len() - 1;
+            raw[last] ^= 0xff;
+            let v = verify_signatures(&sig, &hdr, &raw, payload, &keyring).unwrap();
+            assert_eq!(verdicts(&v), vec![Verdict::Bad], "{} signature", key);
+        }
+    }
+
+    #[test]
+    fn verify_payload_signature() {
+        let keyring = keyring();
+        let data = fs::read("tests/keys/rsa.hdrpayload.sig").unwrap();
+        let (sig, hdr, raw, payload) = signed(&[(SigTag::PGP, &data)]);
+        let v = verify_signatures(&sig, &hdr, &raw, payload, &keyring).unwrap();
+        assert_eq!(verdicts(&v), vec![Verdict::Ok]);
+        assert_eq!(v.signatures[0].to_string(), "V4 RSA/SHA256 Signature, key ID 09b0e0d9: OK");
+
+        let mut payload = payload.to_vec();
+        payload[0] ^= 0xff;
+        let v = verify_signatures(&sig, &hdr, &raw, &payload[..], &keyring).unwrap();
+        assert_eq!(verdicts(&v), vec![Verdict::Bad]);
+    }
+
+    #[test]
+    fn verify_bad_signature_data() {
+        let (sig, hdr, raw, payload) = signed(&[(SigTag::RSA, b"not a signature")]);
+        match verify_signatures(&sig, &hdr, &raw, payload, &keyring()) {
+            Err(RPMError::File(RPMFileError::BadPGP(_))) => (),
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+}
@@ -0,0 +1,344 @@
+/* version.rs - rpm-compatible version comparison
+ *
+ * Copyright (c) 2017, Red Hat, Inc.
+ *
+ * This library is free software; you can redistribute it and/or modify it
+ * under the terms and conditions of the GNU Lesser General Public License
+ * as published by the Free Software Foundation; either version 2.1 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU Lesser General Public License for
+ * more details.
+ *
+ * Authors:
+ *   Will Woods <wwoods@redhat.com>
+ */
+use std::cmp::Ordering;
+use std::fmt;
+
+/// Compare two version (or release) strings exactly like rpm's `rpmvercmp()`.
+///
+/// The strings are split into alternating runs of digits and letters, with
+/// everything else acting as a separator. Numeric runs compare as numbers
+/// and beat alphabetic ones; `~` sorts before anything (even the end of the
+/// string), and `^` sorts after the end of the string but before anything
+/// else.
+pub fn rpmvercmp(a: &str, b: &str) -> Ordering {
+    // NOTE: this is a pretty direct port of rpm/rpmio/rpmvercmp.c, and
+    // deliberately ASCII-only, like rpm's risalpha()/risdigit().
+    if a == b {
+        return Ordering::Equal;
+    }
+    let (one, two) = (a.as_bytes(), b.as_bytes());
+    let (mut i, mut j) = (0, 0);
+    let is_sep = |c: u8| !c.is_ascii_alphanumeric() && c != b'~' && c != b'^';
+
+    while i < one.len() || j < two.len() {
+        while i < one.len() && is_sep(one[i]) { i += 1 }
+        while j < two.len() && is_sep(two[j]) { j += 1 }
+        let (c1, c2) = (one.get(i).cloned(), two.get(j).cloned());
+
+        // tilde sorts before everything else
+        if c1 == Some(b'~') || c2 == Some(b'~') {
+            if c1 != Some(b'~') { return Ordering::Greater }
+            if c2 != Some(b'~') { return Ordering::Less }
+            i += 1;
+            j += 1;
+            continue;
+        }
+
+        // caret is like tilde, except that if one string has ended
+        // (the base version), the other one is newer
+        if c1 == Some(b'^') || c2 == Some(b'^') {
+            if c1.is_none() { return Ordering::Less }
+            if c2.is_none() { return Ordering::Greater }
+            if c1 != Some(b'^') { return Ordering::Greater }
+            if c2 != Some(b'^') { return Ordering::Less }
+            i += 1;
+            j += 1;
+            continue;
+        }
+
+        // if we ran off the end of either, we're done
+        if c1.is_none() || c2.is_none() {
+            break;
+        }
+
+        // grab the next completely numeric or completely alpha segment
+        let isnum = one[i].is_ascii_digit();
+        let in_seg = |c: &u8| if isnum { c.is_ascii_digit() } else { c.is_ascii_alphabetic() };
+        let seg1 = &one[i..i + one[i..].iter().take_while(|c| in_seg(c)).count()];
+        let seg2 = &two[j..j + two[j..].iter().take_while(|c| in_seg(c)).count()];
+        i += seg1.len();
+        j += seg2.len();
+
+        // segments of different types: numeric is always newer than alpha
+        if seg2.is_empty() {
+            return if isnum { Ordering::Greater } else { Ordering::Less };
+        }
+
+        let order = if isnum {
+            // strip leading zeros; then the longer number is bigger
+            let seg1 = &seg1[seg1.iter().take_while(|&&c| c == b'0').count()..];
+            let seg2 = &seg2[seg2.iter().take_while(|&&c| c == b'0').count()..];
+            seg1.len().cmp(&seg2.len()).then(seg1.cmp(seg2))
+        } else {
+            seg1.cmp(seg2)
+        };
+        if order != Ordering::Equal {
+            return order;
+        }
+    }
+
+    // all the segments matched, but the separators might have been different
+    match (i < one.len(), j < two.len()) {
+        (false, false) => Ordering::Equal,
+        (false, true) => Ordering::Less,
+        (true, _) => Ordering::Greater,
+    }
+}
+
+/// An epoch, version and release, ordered the way rpm orders packages:
+/// by epoch (a missing epoch counts as 0), then version, then release.
+#[derive(Debug,Clone)]
+pub struct Evr {
+    pub epoch: Option<u32>,
+    pub version: String,
+    pub release: String,
+}
+
+impl Evr {
+    pub fn new<V, R>(epoch: Option<u32>, version: V, release: R) -> Evr
+            where V: Into<String>, R: Into<String> {
+        Evr { epoch, version: version.into(), release: release.into() }
+    }
+
+    /// Parse an "[epoch:]version[-release]" string, as used in dependencies.
+    /// An epoch that isn't a number (just digits, no sign) is treated as
+    /// part of the version; an empty one, like in ":1.0", means 0.
+    pub fn parse(s: &str) -> Evr {
+        let (epoch, rest) = match s.find(':') {
+            Some(0) => (Some(0), &s[1..]),
+            Some(idx) if s[..idx].bytes().all(|b| b.is_ascii_digit()) => match s[..idx].parse() {
+                Ok(e) => (Some(e), &s[idx+1..]),
+                Err(_) => (None, s),
+            },
+            _ => (None, s),
+        };
+        let (version, release) = match rest.rfind('-') {
+            Some(idx) => (&rest[..idx], &rest[idx+1..]),
+            None => (rest, ""),
+        };
+        Evr::new(epoch, version, release)
+    }
+}
+
+impl Ord for Evr {
+    fn cmp(&self, other: &Evr) -> Ordering {
+        self.epoch.unwrap_or(0).cmp(&other.epoch.unwrap_or(0))
+            .then_with(|| rpmvercmp(&self.version, &other.version))
+            .then_with(|| rpmvercmp(&self.release, &other.release))
+    }
+}
+
+impl PartialOrd for Evr {
+    fn partial_cmp(&self, other: &Evr) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Evr {
+    fn eq(&self, other: &Evr) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Evr {}
+
+impl fmt::Display for Evr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(epoch) = self.epoch {
+            write!(f, "{}:", epoch)?;
+        }
+        write!(f, "{}", self.version)?;
+        if !self.release.is_empty() {
+            write!(f, "-{}", self.release)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cmp::Ordering::*;
+
+    // This is the table from rpm/tests/rpmvercmp.at
+    static RPMVERCMP_TESTS: &[(&str, &str, Ordering)] = &[
+        ("1.0", "1.0", Equal),
+        ("1.0", "2.0", Less),
+        ("2.0", "1.0", Greater),
+
+        ("2.0.1", "2.0.1", Equal),
+        ("2.0", "2.0.1", Less),
+        ("2.0.1", "2.0", Greater),
+
+        ("2.0.1a", "2.0.1a", Equal),
+        ("2.0.1a", "2.0.1", Greater),
+        ("2.0.1", "2.0.1a", Less),
+
+        ("5.5p1", "5.5p1", Equal),
+        ("5.5p1", "5.5p2", Less),
+        ("5.5p2", "5.5p1", Greater),
+
+        ("5.5p10", "5.5p10", Equal),
+        ("5.5p1", "5.5p10", Less),
+        ("5.5p10", "5.5p1", Greater),
+
+        ("10xyz", "10.1xyz", Less),
+        ("10.1xyz", "10xyz", Greater),
+
+        ("xyz10", "xyz10", Equal),
+        ("xyz10", "xyz10.1", Less),
+        ("xyz10.1", "xyz10", Greater),
+
+        ("xyz.4", "xyz.4", Equal),
+        ("xyz.4", "8", Less),
+        ("8", "xyz.4", Greater),
+        ("xyz.4", "2", Less),
+        ("2", "xyz.4", Greater),
+
+        ("5.5p2", "5.6p1", Less),
+        ("5.6p1", "5.5p2", Greater),
+
+        ("5.6p1", "6.5p1", Less),
+        ("6.5p1", "5.6p1", Greater),
+
+        ("6.0.rc1", "6.0", Greater),
+        ("6.0", "6.0.rc1", Less),
+
+        ("10b2", "10a1", Greater),
+        ("10a2", "10b2", Less),
+
+        ("1.0aa", "1.0aa", Equal),
+        ("1.0a", "1.0aa", Less),
+        ("1.0aa", "1.0a", Greater),
+
+        ("10.0001", "10.0001", Equal),
+        ("10.0001", "10.1", Equal),
+        ("10.1", "10.0001", Equal),
+        ("10.0001", "10.0039", Less),
+        ("10.0039", "10.0001", Greater),
+
+        ("4.999.9", "5.0", Less),
+        ("5.0", "4.999.9", Greater),
+
+        ("20101121", "20101121", Equal),
+        ("20101121", "20101122", Less),
+        ("20101122", "20101121", Greater),
+
+        ("2_0", "2_0", Equal),
+        ("2.0", "2_0", Equal),
+        ("2_0", "2.0", Equal),
+
+        // RhBug:178798 case
+        ("a", "a", Equal),
+        ("a+", "a+", Equal),
+        ("a+", "a_", Equal),
+        ("a_", "a+", Equal),
+        ("+a", "+a", Equal),
+        ("+a", "_a", Equal),
+        ("_a", "+a", Equal),
+        ("+_", "+_", Equal),
+        ("_+", "+_", Equal),
+        ("_+", "_+", Equal),
+        ("+", "_", Equal),
+        ("_", "+", Equal),
+
+        // Basic testcases for tilde sorting
+        ("1.0~rc1", "1.0~rc1", Equal),
+        ("1.0~rc1", "1.0", Less),
+        ("1.0", "1.0~rc1", Greater),
+        ("1.0~rc1", "1.0~rc2", Less),
+        ("1.0~rc2", "1.0~rc1", Greater),
+        ("1.0~rc1~git123", "1.0~rc1~git123", Equal),
+        ("1.0~rc1~git123", "1.0~rc1", Less),
+        ("1.0~rc1", "1.0~rc1~git123", Greater),
+
+        // Basic testcases for caret sorting
+        ("1.0^", "1.0^", Equal),
+        ("1.0^", "1.0", Greater),
+        ("1.0", "1.0^", Less),
+        ("1.0^git1", "1.0^git1", Equal),
+        ("1.0^git1", "1.0", Greater),
+        ("1.0", "1.0^git1", Less),
+        ("1.0^git1", "1.0^git2", Less),
+        ("1.0^git2", "1.0^git1", Greater),
+        ("1.0^git1", "1.01", Less),
+        ("1.01", "1.0^git1", Greater),
+        ("1.0^20160101", "1.0^20160101", Equal),
+        ("1.0^20160101", "1.0.1", Less),
+        ("1.0.1", "1.0^20160101", Greater),
+        ("1.0^20160101^git1", "1.0^20160101^git1", Equal),
+        ("1.0^20160102", "1.0^20160101^git1", Greater),
+        ("1.0^20160101^git1", "1.0^20160102", Less),
+
+        // Basic testcases for tilde and caret sorting
+        ("1.0~rc1^git1", "1.0~rc1^git1", Equal),
+        ("1.0~rc1^git1", "1.0~rc1", Greater),
+        ("1.0~rc1", "1.0~rc1^git1", Less),
+        ("1.0^git1~pre", "1.0^git1~pre", Equal),
+        ("1.0^git1", "1.0^git1~pre", Greater),
+        ("1.0^git1~pre", "1.0^git1", Less),
+
+        // These are included here to document current, arguably buggy behaviors
+        // for reference purposes and for easy checking against unintended
+        // behavior changes.
+        ("1b.fc17", "1b.fc17", Equal),
+        ("1b.fc17", "1.fc17", Less),
+        ("1.fc17", "1b.fc17", Greater),
+        ("1g.fc17", "1g.fc17", Equal),
+        ("1g.fc17", "1.fc17", Greater),
+        ("1.fc17", "1g.fc17", Less),
+    ];
+
+    #[test]
+    fn rpmvercmp_table() {
+        for &(a, b, expected) in RPMVERCMP_TESTS {
+            assert_eq!(rpmvercmp(a, b), expected, "rpmvercmp({:?}, {:?})", a, b);
+        }
+    }
+
+    #[test]
+    fn evr_parse() {
+        let evr = Evr::parse("1:1.0-23.fc24");
+        assert_eq!((evr.epoch, &evr.version[..], &evr.release[..]), (Some(1), "1.0", "23.fc24"));
+        let evr = Evr::parse("1.0");
+        assert_eq!((evr.epoch, &evr.version[..], &evr.release[..]), (None, "1.0", ""));
+        let evr = Evr::parse(":1.0-2");
+        assert_eq!((evr.epoch, &evr.version[..], &evr.release[..]), (Some(0), "1.0", "2"));
+        assert_eq!(Evr::parse("2:3.1-4").to_string(), "2:3.1-4");
+        assert_eq!(Evr::parse("3.1").to_string(), "3.1");
+    }
+
+    #[test]
+    fn evr_ordering() {
+        // epoch wins over everything else; no epoch is the same as 0
+        assert!(Evr::parse("1:1.0-1") > Evr::parse("9.9-9"));
+        assert_eq!(Evr::parse("0:1.0-1"), Evr::parse("1.0-1"));
+        assert!(Evr::parse("1.0-2") > Evr::parse("1.0-1"));
+        assert!(Evr::parse("1.0~rc1-1") < Evr::parse("1.0-1"));
+        // only plain digits make an epoch
+        assert_eq!(Evr::parse("+1:1.0-1").epoch, None);
+        assert_eq!(Evr::parse("+1:1.0-1").version, "+1:1.0");
+        assert_eq!(Evr::parse("-1:1.0").epoch, None);
+        assert_eq!(Evr::parse("12:1.0").epoch, Some(12));
+        let mut evrs = [Evr::parse("1.10-1"), Evr::parse("1:0.1-1"), Evr::parse("1.9-1"),
+                        Evr::parse("1.9-1.fc24")];
+        evrs.sort();
+        let sorted: Vec<String> = evrs.iter().map(|e| e.to_string()).collect();
+        assert_eq!(sorted, vec!["1.9-1", "1.9-1.fc24", "1.10-1", "1:0.1-1"]);
+    }
+}
@@ -0,0 +1,274 @@
+/* writer.rs - writing header sections back out
+ *
+ * Copyright (c) 2017, Red Hat, Inc.
+ *
+ * This library is free software; you can redistribute it and/or modify it
+ * under the terms and conditions of the GNU Lesser General Public License
+ * as published by the Free Software Foundation; either version 2.1 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU Lesser General Public License for
+ * more details.
+ *
+ * Authors:
+ *   Will Woods <wwoods@redhat.com>
+ */
+use header::Header;
+use tag::{Tag, TagID, TagType, TagValue};
+
+// The magic, version and reserved bytes that start each header section
+const SECTION_MAGIC: [u8; 8] = [0x8E, 0xAD, 0xE8, 0x01, 0, 0, 0, 0];
+
+// Values of each type are aligned to their size in the data store
+fn alignment(ttype: TagType) -> usize {
+    match ttype {
+        TagType::INT16 => 2,
+        TagType::INT32 => 4,
+        TagType::INT64 => 8,
+        _ => 1,
+    }
+}
+
+// Can a value be written as the given type?
+fn type_fits(ttype: TagType, value: &TagValue) -> bool {
+    match (ttype, value) {
+        (TagType::NULL, &TagValue::Null) |
+        (TagType::CHAR, &TagValue::Char(_)) |
+        (TagType::INT8, &TagValue::Int8(_)) |
+        (TagType::INT16, &TagValue::Int16(_)) |
+        (TagType::INT32, &TagValue::Int32(_)) |
+        (TagType::INT64, &TagValue::Int64(_)) |
+        (TagType::BIN, &TagValue::Binary(_)) |
+        (TagType::STRING_ARRAY, &TagValue::String(_)) |
+        (TagType::I18NSTRING, &TagValue::String(_)) => true,
+        (TagType::STRING, TagValue::String(v)) => v.len() == 1,
+        _ => false,
+    }
+}
+
+// The type to write a value as: the type the tag is supposed to have, if
+// the value fits it, or else a type to suit the value.
+pub(crate) fn value_type(expected: Option<TagType>, value: &TagValue) -> TagType {
+    match expected {
+        Some(ttype) if type_fits(ttype, value) => ttype,
+        _ => match *value {
+            TagValue::Null => TagType::NULL,
+            TagValue::Char(_) => TagType::CHAR,
+            TagValue::Int8(_) => TagType::INT8,
+            TagValue::Int16(_) => TagType::INT16,
+            TagValue::Int32(_) => TagType::INT32,
+            TagValue::Int64(_) => TagType::INT64,
+            TagValue::Binary(_) => TagType::BIN,
+            TagValue::String(ref v) if v.len() == 1 => TagType::STRING,
+            TagValue::String(_) => TagType::STRING_ARRAY,
+        },
+    }
+}
+
+// Append a value to the store, returning its count
+fn write_value(store: &mut Vec<u8>, value: &TagValue) -> usize {
+    match *value {
+        TagValue::Null => 0,
+        TagValue::Char(ref v) | TagValue::Int8(ref v) | TagValue::Binary(ref v) => {
+            store.extend_from_slice(v);
+            v.len()
+        },
+        TagValue::Int16(ref v) => {
+            v.iter().for_each(|n| store.extend_from_slice(&n.to_be_bytes()));
+            v.len()
+        },
+        TagValue::Int32(ref v) => {
+            v.iter().for_each(|n| store.extend_from_slice(&n.to_be_bytes()));
+            v.len()
+        },
+        TagValue::Int64(ref v) => {
+            v.iter().for_each(|n| store.extend_from_slice(&n.to_be_bytes()));
+            v.len()
+        },
+        TagValue::String(ref v) => {
+            for s in v {
+                store.extend_from_slice(s.as_bytes());
+                store.push(0);
+            }
+            v.len()
+        },
+    }
+}
+
+fn write_entry(index: &mut Vec<u8>, tagid: TagID, ttype: TagType, offset: usize, count: usize) {
+    index.extend_from_slice(&tagid.to_be_bytes());
+    index.extend_from_slice(&(ttype as u32).to_be_bytes());
+    index.extend_from_slice(&(offset as u32).to_be_bytes());
+    index.extend_from_slice(&(count as u32).to_be_bytes());
+}
+
+// An index entry to write: its tag, type and value
+type Entry<'a> = (TagID, TagType, &'a TagValue);
+
+// Write a value into the data store, with its index entry.
+fn write_item(index: &mut Vec<u8>, store: &mut Vec<u8>, &(tagid, ttype, value): &Entry) {
+    let align = alignment(ttype);
+    let pad = (align - store.len() % align) % align;
+    store.resize(store.len() + pad, 0);
+    let offset = store.len();
+    let count = write_value(store, value);
+    write_entry(index, tagid, ttype, offset, count);
+}
+
+// Write a header section: the section header, the index entries and the
+// data store, in the order given. If `region` is given, the entries are
+// wrapped in a region with that tag, like HEADERSIGNATURES, whose trailer
+// goes at the end of the region's data; the `dribbles` come after the
+// region, in both the index and the store. Any padding is up to the caller.
+pub(crate) fn write_section(entries: &[Entry], region: Option<TagID>, dribbles: &[Entry]) -> Vec<u8> {
+    let mut index = Vec::new();
+    let mut store = Vec::new();
+    for entry in entries {
+        write_item(&mut index, &mut store, entry);
+    }
+    let mut count = entries.len();
+    if let Some(tagid) = region {
+        // the trailer points back at the start of the index
+        count += 1;
+        let mut trailer = Vec::new();
+        write_entry(&mut trailer, tagid, TagType::BIN, (-16 * count as i32) as usize, 16);
+        let mut entry = Vec::new();
+        write_entry(&mut entry, tagid, TagType::BIN, store.len(), 16);
+        entry.extend(index);
+        index = entry;
+        store.extend(trailer);
+    }
+    for entry in dribbles {
+        write_item(&mut index, &mut store, entry);
+    }
+    count += dribbles.len();
+    let mut section = SECTION_MAGIC.to_vec();
+    section.extend_from_slice(&(count as u32).to_be_bytes());
+    section.extend_from_slice(&(store.len() as u32).to_be_bytes());
+    section.extend(index);
+    section.extend(store);
+    section
+}
+
+// Write a Header as a section, in tag order, like rpm does, with the types
+// its entries have. (Duplicate tags stay in the order they were in.)
+//
+// If the header already has a region with the given tag, like a header
+// read from a package or the rpmdb, only the entries that were in it go in
+// the new region, so the signatures still cover the same tags; any dribble
+// entries stay after it, in the order they were in. Otherwise, like for a
+// header that's being built, the region is put around all of the entries.
+pub(crate) fn write_header_section(hdr: &Header, region: Option<TagID>) -> Vec<u8> {
+    let entries: Vec<_> = hdr.entries()
+        .map(|(entry, value)| (entry.tagid, value_type(Some(entry.tagtype), value), value))
+        .collect();
+    let (mut immutable, dribbles) = match (hdr.region(), region) {
+        (Ok(Some(r)), Some(tagid)) if r.tag == tagid => (entries[1..r.count].to_vec(), &entries[r.count..]),
+        _ => (entries.iter().filter(|&&(id, _, _)| Some(id) != region).cloned().collect(), &[][..]),
+    };
+    immutable.sort_by_key(|&(id, _, _)| id);
+    write_section(&immutable, region, dribbles)
+}
+
+/// Write a main header back out the way it's stored in a package: a header
+/// section with its tags in order, inside a HEADERIMMUTABLE region if it
+/// had one. (The signature header has its own tag numbers and padding; see
+/// `SignatureHeader::to_bytes()`.)
+///
+/// A header read from a package or the rpmdb keeps its region as it was,
+/// with any dribble entries after it (see `Header::dribbles()`), so it
+/// comes out the same as it went in.
+pub fn header_to_bytes(hdr: &Header) -> Vec<u8> {
+    let immutable = Tag::HEADERIMMUTABLE as TagID;
+    let region = if hdr.contains_key(&immutable) { Some(immutable) } else { None };
+    write_header_section(hdr, region)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::{parse_section_header, parse_section_data};
+    use reader::Reader;
+    static BINRPM1: &[u8] = include_bytes!("../tests/rpms/binary.x86_64.rpm");
+
+    #[test]
+    fn write_and_parse() {
+        let name = TagValue::String(vec![String::from("hello")]);
+        let flags = TagValue::Int32(vec![1, 2]);
+        let size = TagValue::Int64(vec![3]);
+        let entries = [(1000, TagType::STRING, &name), (1001, TagType::INT32, &flags),
+                       (1002, TagType::INT64, &size)];
+        let section = write_section(&entries, Some(62), &[]);
+        let (rest, sh) = parse_section_header(&section).unwrap();
+        assert_eq!(sh.count, 4);
+        // "hello\0", 2 bytes of padding, 8 bytes of ints, the int64, the trailer
+        assert_eq!(sh.size, 6 + 2 + 8 + 8 + 16);
+        let (_, hdr) = parse_section_data(rest, sh.count as usize, sh.size as usize).unwrap();
+        assert_eq!(hdr.get(&1000), Some(&name));
+        assert_eq!(hdr.get(&1001), Some(&flags));
+        assert_eq!(hdr.get(&1002), Some(&size));
+        // the region trailer is an entry with a negative offset
+        let trailer = [0, 0, 0, 62, 0, 0, 0, 7, 0xff, 0xff, 0xff, 0xc0, 0, 0, 0, 16];
+        assert_eq!(hdr.get(&62), Some(&TagValue::Binary(trailer.to_vec())));
+    }
+
+    #[test]
+    fn header_round_trip() {
+        let mut r = Reader::from_bytes(BINRPM1);
+        r.lead().unwrap();
+        r.signature().unwrap();
+        let (hdr, raw) = r.raw_header().unwrap();
+        assert_eq!(header_to_bytes(&hdr), raw);
+    }
+
+    #[test]
+    fn header_round_trip_dribbles() {
+        let mut r = Reader::from_bytes(BINRPM1);
+        r.lead().unwrap();
+        r.signature().unwrap();
+        let (_, raw) = r.raw_header().unwrap();
+        // add an INSTALLTIME dribble after the region, like rpm does when
+        // it installs a package: an index entry, and its data (4-aligned)
+        // at the end of the store
+        let (count, size) = (62, 0x17d2);
+        let mut blob = raw[8..16].to_vec();
+        blob[3] = count + 1;
+        blob[6..8].copy_from_slice(&(size as u16 + 2 + 4).to_be_bytes());
+        blob.extend_from_slice(&raw[16..16 + 16 * count as usize]);
+        blob.extend_from_slice(&[0, 0, 0x03, 0xf0, 0, 0, 0, 4, 0, 0, 0x17, 0xd4, 0, 0, 0, 1]);
+        blob.extend_from_slice(&raw[16 + 16 * count as usize..]);
+        blob.extend_from_slice(&[0, 0, 0x5a, 0, 0, 0]);
+
+        let hdr = Header::from_blob(&blob).unwrap();
+        assert_eq!(hdr.dribbles().unwrap(), vec![Tag::INSTALLTIME as TagID]);
+        let bytes = header_to_bytes(&hdr);
+        assert_eq!(&bytes[8..], &blob[..]);
+        let region = hdr.region().unwrap().unwrap();
+        assert_eq!(region.section(&bytes).unwrap(), raw);
+    }
+
+    #[test]
+    fn value_types() {
+        let one = TagValue::String(vec![String::from("a")]);
+        let two = TagValue::String(vec![String::from("a"), String::from("b")]);
+        assert_eq!(value_type(Some(TagType::I18NSTRING), &one), TagType::I18NSTRING);
+        assert_eq!(value_type(Some(TagType::STRING), &one), TagType::STRING);
+        assert_eq!(value_type(Some(TagType::STRING), &two), TagType::STRING_ARRAY);
+        assert_eq!(value_type(Some(TagType::INT32), &one), TagType::STRING);
+        assert_eq!(value_type(None, &TagValue::Int16(vec![1])), TagType::INT16);
+    }
+
+    #[test]
+    fn header_without_region() {
+        let mut hdr = Header::new();
+        hdr.insert(Tag::NAME as TagID, TagValue::String(vec![String::from("foo")]));
+        hdr.insert(Tag::EPOCH as TagID, TagValue::Int32(vec![1]));
+        let bytes = header_to_bytes(&hdr);
+        let (rest, sh) = parse_section_header(&bytes).unwrap();
+        assert_eq!((sh.count, sh.size), (2, 8));
+        let (_, hdr2) = parse_section_data(rest, 2, 8).unwrap();
+        assert_eq!(hdr2, hdr);
+    }
+}
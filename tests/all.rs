@@ -17,7 +17,8 @@
  */
 extern crate rpm;
 
-use rpm::{Tag, TagInfo};
+use std::io::Read;
+use rpm::{Tag, SigTag, TagInfo, SigTagInfo, Reader, FileEntry};
 
 #[test]
 fn taginfo_from_id() {
@@ -39,3 +40,156 @@ fn taginfo_from_name() {
 fn taginfo_from_bad_name() {
     assert_eq!(TagInfo::from_name("lol wut"), None);
 }
+
+#[test]
+fn sigtaginfo_from_id() {
+    // same number, different tag
+    assert_eq!(SigTagInfo::from_id(SigTag::SIZE as i32).unwrap().name, "SIZE");
+    assert_eq!(SigTagInfo::from_id(Tag::NAME as i32).unwrap().id, SigTag::SIZE);
+}
+
+#[test]
+fn file_list() {
+    let mut r = Reader::from_file("tests/rpms/binary.x86_64.rpm").unwrap();
+    r.lead().unwrap();
+    r.header().unwrap();
+    let hdr = r.header().unwrap();
+    let files = FileEntry::from_header(&hdr).unwrap();
+    assert_eq!(files.len(), 4);
+    assert_eq!(files[3].path, "/usr/share/man/man1/hardlink.1.gz");
+    assert_eq!(files[3].size, 889);
+}
+
+#[test]
+fn write_round_trip() {
+    let original = std::fs::read("tests/rpms/binary.x86_64.rpm").unwrap();
+    let mut r = Reader::from_bytes(&original[..]);
+    let lead = r.lead().unwrap();
+    let sig = r.signature().unwrap();
+    let hdr = r.header().unwrap();
+    let mut payload = Vec::new();
+    r.payload().unwrap().read_to_end(&mut payload).unwrap();
+
+    let mut written = lead.to_bytes();
+    written.extend(sig.to_bytes());
+    written.extend(rpm::header_to_bytes(&hdr));
+    written.extend(payload);
+    assert!(written == original, "written package doesn't match the original");
+}
+
+#[test]
+fn build_package() {
+    let builder = rpm::PackageBuilder::new("hello", "1.0", "1", "x86_64")
+        .summary("Says hello")
+        .license("MIT")
+        .build_time(1500000000)
+        .compressor(rpm::Compressor::Xz)
+        .file(rpm::PackageFile::new("/usr/bin/hello", "#!/bin/sh\necho hello\n").mode(0o755))
+        .file(rpm::PackageFile::new("/etc/hello.conf", "").flags(rpm::FileFlags::CONFIG));
+    let mut built = Vec::new();
+    builder.build(&mut built).unwrap();
+    assert_eq!(rpm::verify_package(&built[..]).unwrap().len(), 4);
+
+    let mut r = Reader::from_bytes(&built[..]);
+    assert_eq!(r.lead().unwrap().name, "hello-1.0-1");
+    r.signature().unwrap();
+    let pkg = rpm::Package::new(r.header().unwrap());
+    assert_eq!(pkg.nevra().unwrap(), "hello-1.0-1.x86_64");
+    let files = pkg.files().unwrap();
+    assert_eq!(files[0].path, "/etc/hello.conf");
+    assert_eq!(files[1].path, "/usr/bin/hello");
+    assert_eq!(files[1].mode, 0o100755);
+}
+
+#[test]
+fn mmap_header_ref() {
+    // nothing changes the fixture while it's mapped
+    let mut r = unsafe { Reader::from_mmap("tests/rpms/binary.x86_64.rpm") }.unwrap();
+    r.lead().unwrap();
+    r.signature().unwrap();
+    let hdr = r.header_ref().unwrap();
+    let name = hdr.get(&(Tag::NAME as i32)).and_then(|v| v.as_str());
+    assert_eq!(name, Some("hardlink"));
+    match hdr.get(&(Tag::BASENAMES as i32)) {
+        Some(&rpm::TagValueRef::String(names)) => assert_eq!(names.get(3), Some("hardlink.1.gz")),
+        v => panic!("unexpected value: {:?}", v),
+    }
+    let hdr = hdr.to_header();
+    assert_eq!(rpm::FileEntry::from_header(&hdr).unwrap().len(), 4);
+}
+
+// Read everything we can out of a package, ignoring errors
+fn read_all(data: &[u8], payload: bool) {
+    let mut r = Reader::from_bytes(data);
+    if r.lead().is_err() {
+        return;
+    }
+    let sig = match r.signature() {
+        Ok(sig) => sig,
+        Err(_) => return,
+    };
+    let _ = sig.to_bytes();
+    let hdr = match r.header() {
+        Ok(hdr) => hdr,
+        Err(_) => return,
+    };
+    let _ = hdr.dribbles();
+    let _ = rpm::header_to_bytes(&hdr);
+    if payload {
+        if let Ok(mut archive) = r.archive(&hdr) {
+            while let Ok(Some(mut entry)) = archive.next_entry() {
+                let _ = entry.read_to_end(&mut Vec::new());
+            }
+        }
+        let _ = rpm::verify_package(data);
+    }
+    let mut r = Reader::from_slice(data);
+    if r.lead().is_ok() && r.header_ref().is_ok() {
+        let _ = r.header_ref().map(|hdr| hdr.to_header());
+    }
+    let pkg = rpm::Package::new(hdr);
+    let _ = pkg.nevra();
+    let _ = pkg.files();
+    if let Ok(requires) = pkg.requires() {
+        for dep in requires.iter().filter(|dep| dep.is_rich()) {
+            let _ = rpm::RichDep::parse(&dep.name);
+        }
+    }
+    let _ = pkg.provides();
+}
+
+#[test]
+fn hostile_input() {
+    let original = std::fs::read("tests/rpms/binary.x86_64.rpm").unwrap();
+    // chopped off anywhere in the headers, or partway through the payload
+    for len in (0..0x3200).chain((0x3200..original.len()).step_by(0x100)) {
+        read_all(&original[..len], len > 0x313a);
+    }
+    // with a scribbled-on byte anywhere in the headers
+    for pos in 0..0x313a {
+        let mut data = original.clone();
+        data[pos] ^= 0xa5;
+        read_all(&data, false);
+    }
+    // a header section that claims to be enormous
+    let mut data = original.clone();
+    data[0x68..0x70].copy_from_slice(&[0x7f, 0xff, 0xff, 0xff, 0x7f, 0xff, 0xff, 0xff]);
+    let mut r = Reader::from_bytes(&data[..]);
+    r.lead().unwrap();
+    let err = r.header().unwrap_err();
+    match err.file_error() {
+        Some(&rpm::RPMFileError::HeaderSize) => (),
+        _ => panic!("unexpected error: {}", err),
+    }
+    // a rich dependency nested deep enough to blow the stack, unless
+    // there's a limit
+    let deep = "(".repeat(1_000_000) + "a" + &")".repeat(1_000_000);
+    let err = rpm::RichDep::parse(&deep).unwrap_err();
+    match err.file_error() {
+        Some(&rpm::RPMFileError::BadRichDep(_, 128, _)) => (),
+        _ => panic!("unexpected error: {}", err),
+    }
+    let limits = rpm::Limits { max_rich_depth: 3, ..rpm::Limits::default() };
+    assert!(rpm::RichDep::parse_with_limits("(a and (b or (c with d)))", &limits).is_ok());
+    assert!(rpm::RichDep::parse_with_limits("(a and (b or (c with (d or e))))", &limits).is_err());
+}